@@ -0,0 +1,235 @@
+//! Deadzones and response curves for sticks and triggers, configured in the
+//! `[analog]` section of a [`Profile`](crate::profile::Profile):
+//!
+//! ```toml
+//! [analog.left_stick]
+//! deadzone = "radial"
+//! inner = 0.08
+//! outer = 0.02
+//! anti_deadzone = 0.2
+//! curve = { exponential = 1.5 }
+//!
+//! [analog.right_trigger]
+//! curve = { points = [[0.0, 0.0], [0.5, 0.2], [1.0, 1.0]] }
+//! threshold = 0.3
+//! ```
+//!
+//! Deadzones and the anti-deadzone are fractions of the full range.
+
+use anyhow::{bail, Context};
+use sdl2::controller::Axis;
+use serde::Deserialize;
+
+use crate::ControllerState;
+
+/// Maps how far an input is pushed to how far the output is, both from 0 to
+/// 1.
+#[derive(Deserialize, Debug, Clone, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Curve {
+    #[default]
+    Linear,
+    /// Raises the input to this power, above 1 gives finer control near the
+    /// centre.
+    Exponential(f32),
+    /// Linear interpolation between `[input, output]` points, sorted by input.
+    Points(Vec<[f32; 2]>),
+}
+
+impl Curve {
+    fn apply(&self, x: f32) -> f32 {
+        match self {
+            Curve::Linear => x,
+            Curve::Exponential(exponent) => x.powf(*exponent),
+            Curve::Points(points) => {
+                let Some(upper) = points.iter().position(|point| point[0] >= x) else {
+                    return points.last().map_or(x, |point| point[1]);
+                };
+                if upper == 0 {
+                    return points[0][1];
+                }
+                let [x0, y0] = points[upper - 1];
+                let [x1, y1] = points[upper];
+                y0 + (y1 - y0) * (x - x0) / (x1 - x0)
+            }
+        }
+    }
+
+    fn validate(&self) -> anyhow::Result<()> {
+        match self {
+            Curve::Linear => {}
+            Curve::Exponential(exponent) => {
+                if exponent.is_nan() || *exponent <= 0.0 {
+                    bail!("the exponent must be positive");
+                }
+            }
+            Curve::Points(points) => {
+                if points.len() < 2 {
+                    bail!("a curve needs at least two points");
+                }
+                if points.iter().flatten().any(|v| !(0.0..=1.0).contains(v)) {
+                    bail!("curve points must lie between 0 and 1");
+                }
+                if points.windows(2).any(|pair| pair[0][0] >= pair[1][0]) {
+                    bail!("curve points must be sorted by input");
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Whether a stick's deadzone applies to its distance from the centre or to
+/// each of its axes on their own.
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum DeadzoneShape {
+    #[default]
+    Radial,
+    Axial,
+}
+
+/// How a stick or trigger travels from the deadzone to full deflection.
+#[derive(Deserialize, Debug, Clone, Default, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct Response {
+    /// Only for sticks.
+    pub deadzone: DeadzoneShape,
+    /// Ignored travel around rest.
+    pub inner: f32,
+    /// Travel before the end that already counts as fully pushed.
+    pub outer: f32,
+    /// Smallest output once out of the inner deadzone, to skip a game's own
+    /// deadzone.
+    pub anti_deadzone: f32,
+    pub curve: Curve,
+    /// Only for triggers, turns them digital: fully pressed from this far on
+    /// and released before.
+    pub threshold: Option<f32>,
+}
+
+impl Response {
+    /// Maps `x`, from 0 to 1, through the deadzones and the curve.
+    fn apply(&self, x: f32) -> f32 {
+        if x <= self.inner {
+            return 0.0;
+        }
+        let scaled = ((x - self.inner) / (1.0 - self.inner - self.outer)).clamp(0.0, 1.0);
+        let curved = self.curve.apply(scaled).clamp(0.0, 1.0);
+        self.anti_deadzone + (1.0 - self.anti_deadzone) * curved
+    }
+
+    fn validate(&self) -> anyhow::Result<()> {
+        for (name, value) in [
+            ("inner", self.inner),
+            ("outer", self.outer),
+            ("anti_deadzone", self.anti_deadzone),
+        ] {
+            if !(0.0..1.0).contains(&value) {
+                bail!("`{}` must be at least 0 and less than 1", name);
+            }
+        }
+        if self.inner + self.outer >= 1.0 {
+            bail!("the deadzones leave no travel");
+        }
+        if let Some(threshold) = self.threshold {
+            if threshold.is_nan() || threshold <= 0.0 || threshold > 1.0 {
+                bail!("`threshold` must be above 0 and at most 1");
+            }
+        }
+        self.curve.validate()
+    }
+}
+
+/// The response of every stick and trigger of a pad.
+#[derive(Deserialize, Debug, Clone, Default, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct Analog {
+    pub left_stick: Response,
+    pub right_stick: Response,
+    pub left_trigger: Response,
+    pub right_trigger: Response,
+}
+
+impl Analog {
+    pub fn validate(&self) -> anyhow::Result<()> {
+        for (name, stick) in [
+            ("left_stick", &self.left_stick),
+            ("right_stick", &self.right_stick),
+        ] {
+            if stick.threshold.is_some() {
+                bail!("analog.{}: sticks have no `threshold`", name);
+            }
+            stick
+                .validate()
+                .with_context(|| format!("analog.{}", name))?;
+        }
+        for (name, trigger) in [
+            ("left_trigger", &self.left_trigger),
+            ("right_trigger", &self.right_trigger),
+        ] {
+            if trigger.deadzone != DeadzoneShape::default() {
+                bail!("analog.{}: triggers have no deadzone shape", name);
+            }
+            trigger
+                .validate()
+                .with_context(|| format!("analog.{}", name))?;
+        }
+        Ok(())
+    }
+
+    /// Runs every stick and trigger of `state` through its response.
+    pub fn apply(&self, state: &mut ControllerState) {
+        apply_stick(&self.left_stick, state, Axis::LeftX, Axis::LeftY);
+        apply_stick(&self.right_stick, state, Axis::RightX, Axis::RightY);
+        apply_trigger(&self.left_trigger, state, Axis::TriggerLeft);
+        apply_trigger(&self.right_trigger, state, Axis::TriggerRight);
+    }
+}
+
+fn normalize(value: i16) -> f32 {
+    (value as f32 / i16::MAX as f32).clamp(-1.0, 1.0)
+}
+
+fn denormalize(value: f32) -> i16 {
+    (value * i16::MAX as f32).round() as i16
+}
+
+fn apply_stick(stick: &Response, state: &mut ControllerState, x_axis: Axis, y_axis: Axis) {
+    if *stick == Response::default() {
+        return;
+    }
+
+    let (x, y) = (normalize(state.axis(x_axis)), normalize(state.axis(y_axis)));
+    let (x, y) = match stick.deadzone {
+        DeadzoneShape::Radial => {
+            let magnitude = x.hypot(y);
+            if magnitude == 0.0 {
+                (0.0, 0.0)
+            } else {
+                let scale = stick.apply(magnitude.min(1.0)) / magnitude;
+                (x * scale, y * scale)
+            }
+        }
+        DeadzoneShape::Axial => (
+            stick.apply(x.abs()).copysign(x),
+            stick.apply(y.abs()).copysign(y),
+        ),
+    };
+    state.set_axis(x_axis, denormalize(x.clamp(-1.0, 1.0)));
+    state.set_axis(y_axis, denormalize(y.clamp(-1.0, 1.0)));
+}
+
+fn apply_trigger(trigger: &Response, state: &mut ControllerState, axis: Axis) {
+    if *trigger == Response::default() {
+        return;
+    }
+
+    let value = normalize(state.axis(axis)).max(0.0);
+    let value = match trigger.threshold {
+        Some(threshold) if value >= threshold => 1.0,
+        Some(_) => 0.0,
+        None => trigger.apply(value),
+    };
+    state.set_axis(axis, denormalize(value));
+}
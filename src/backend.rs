@@ -0,0 +1,82 @@
+use sdl2::{
+    controller::{Axis, Button},
+    keyboard::Scancode,
+    mouse::MouseButton,
+};
+
+use std::time::Instant;
+
+use crate::{Capabilities, PadTarget, TouchPoint};
+
+/// Feedback sent by the host to a virtual pad, e.g. a game rumbling it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Feedback {
+    Rumble { large_motor: u8, small_motor: u8 },
+    Led { number: u8 },
+}
+
+/// A single virtual controller created by an [`OutputBackend`].
+///
+/// Button and axis updates are buffered until [`VirtualPad::submit_report`]
+/// is called, so a batch of changes reaches the host as one report.
+pub trait VirtualPad {
+    fn set_button(&mut self, button: Button, value: bool);
+
+    /// Axis values follow the XInput convention, positive Y is up.
+    fn set_axis(&mut self, axis: Axis, value: i16);
+
+    fn submit_report(&mut self);
+
+    /// Motion readings as carried by [`IolEvent::Motion`](crate::IolEvent),
+    /// ignored by pads that can't report motion.
+    fn set_motion(&mut self, _timestamp: u64, _gyro: [f32; 3], _accel: [f32; 3]) {}
+
+    /// Moves a finger on the pad's touchpad, ignored by pads without one.
+    fn set_touch(&mut self, _point: TouchPoint) {}
+
+    /// Offers the pad a keyboard key, returning whether it took it. Only pads
+    /// driven by a [`Profile`](crate::profile::Profile) bind keys.
+    fn set_key(&mut self, _scancode: Scancode, _value: bool) -> bool {
+        false
+    }
+
+    /// Lets pads whose report changes on its own, e.g. while a turbo button
+    /// is held, catch up with `now`. Returns when it wants to be called next.
+    fn tick(&mut self, _now: Instant) -> Option<Instant> {
+        None
+    }
+
+    fn unplug(&mut self);
+
+    /// Returns the next pending feedback notification, if any.
+    fn poll_feedback(&mut self) -> Option<Feedback> {
+        None
+    }
+}
+
+/// A sink for virtual devices, such as ViGEmBus on Windows or uinput on Linux.
+pub trait OutputBackend {
+    /// Creates a virtual pad, falling back to another kind of controller if
+    /// the backend can't emulate `target`.
+    fn plug(&mut self, target: PadTarget) -> anyhow::Result<Box<dyn VirtualPad>>;
+
+    /// The features this backend can reproduce, offered during the handshake.
+    fn capabilities(&self) -> Capabilities {
+        Capabilities::GAMEPAD
+    }
+
+    /// The features left when the pads are of kind `target`. The listener
+    /// offers those of its default target, as the handshake comes before any
+    /// pad is plugged.
+    fn target_capabilities(&self, _target: PadTarget) -> Capabilities {
+        self.capabilities()
+    }
+
+    fn set_key(&mut self, _scancode: Scancode, _value: bool) {}
+
+    fn move_mouse(&mut self, _dx: i32, _dy: i32) {}
+
+    fn set_mouse_button(&mut self, _button: MouseButton, _value: bool) {}
+
+    fn scroll_mouse(&mut self, _x: i32, _y: i32) {}
+}
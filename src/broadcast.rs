@@ -1,29 +1,130 @@
+use clap::Parser;
 use glow::HasContext;
 use imgui::{Condition, Context};
 use imgui_glow_renderer::AutoRenderer;
 use imgui_sdl2_support::SdlPlatform;
-use iol::IolEvent;
-use mio::net::UdpSocket;
-use mio::Events;
-use mio::{Interest, Poll, Token};
-use postcard::from_bytes;
-use postcard::to_vec;
-use sdl2::controller::GameController;
+use iol::client::{resolve_address, Client, Session};
+use iol::config::{self, Config};
+use iol::discovery::Discovery;
+use iol::profile::{Profile, ProfileFile, Remapper};
+use iol::recording::Recorder;
+use iol::{Capabilities, ControllerState, IolEvent, PadTarget, TouchPoint};
+use sdl2::controller::{Axis, GameController};
+use sdl2::keyboard::Scancode;
+use sdl2::mouse::MouseWheelDirection;
+use sdl2::sensor::SensorType;
 use sdl2::{
     event::Event,
-    video::{GLProfile, Window},
+    video::{GLContext, GLProfile, Window},
+    Sdl,
 };
-use std::collections::HashMap;
-use std::io;
-use std::net::IpAddr;
-use std::net::Ipv4Addr;
+use std::cell::RefCell;
+use std::collections::{HashMap, VecDeque};
+use std::fs;
 use std::net::SocketAddr;
-use std::time::Duration;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::sync::mpsc::{self, Receiver, TryRecvError};
+use std::thread;
+use std::time::{Duration, Instant};
 
 const SCREEN_WIDTH: u32 = 1280;
 const SCREEN_HEIGHT: u32 = 720;
 
-const UDP_SOCKET: Token = Token(0);
+// Light bar colours for player slots 1 to 4, as on a PlayStation.
+const PLAYER_COLORS: [[u8; 3]; 4] = [[0, 0, 64], [64, 0, 0], [0, 64, 0], [32, 0, 32]];
+
+// Kinds of virtual pad a controller can ask the listener for.
+const TARGET_CHOICES: [(&str, Option<PadTarget>); 3] = [
+    ("Listener default", None),
+    ("Xbox 360", Some(PadTarget::Xbox360)),
+    ("DualShock 4", Some(PadTarget::DualShock4)),
+];
+
+// Remapping profiles are offered for each controller, see `iol::profile`.
+const PROFILE_RELOAD_INTERVAL: Duration = Duration::from_secs(1);
+
+// Stands in for an SDL instance id for the pad driven by the keyboard, SDL
+// counts instance ids up from 0.
+const KEYBOARD_PAD: u32 = u32::MAX;
+// The controller name the listener matches per-device settings against.
+const KEYBOARD_PAD_NAME: &str = "Keyboard Pad";
+
+// Hitbox style layout of the keyboard pad when no profile is picked for it.
+const KEYBOARD_PAD_LAYOUT: &str = r#"
+[keys]
+W = "dpup"
+A = "dpleft"
+S = "dpdown"
+D = "dpright"
+Space = "dpup"
+U = "x"
+I = "y"
+O = "rightshoulder"
+P = "leftshoulder"
+J = "a"
+K = "b"
+L = { axis = "righttrigger" }
+";" = { axis = "lefttrigger" }
+Return = "start"
+Backspace = "back"
+
+[socd]
+horizontal = "neutral"
+vertical = "up-priority"
+"#;
+
+// Toggles mouse capture locally, never forwarded to the listener.
+const CAPTURE_TOGGLE: Scancode = Scancode::ScrollLock;
+
+// Longest the headless loop waits for an SDL event, short enough to keep up
+// with the highest motion rate.
+const HEADLESS_WAIT_MS: u32 = 4;
+// How often the headless mode retries connecting to the listener.
+const RECONNECT_INTERVAL: Duration = Duration::from_secs(5);
+// How often listeners are probed for while disconnected.
+const DISCOVERY_INTERVAL: Duration = Duration::from_secs(2);
+
+/// Forwards local controllers, keyboard and mouse to iol-listen.
+///
+/// Flags override the config files, see `iol::config`.
+#[derive(Parser)]
+#[command(version)]
+struct Args {
+    /// Config file read on top of the per-user one and ./iol.toml.
+    #[arg(long, value_name = "FILE")]
+    config: Option<PathBuf>,
+    /// Local address to send from.
+    #[arg(long)]
+    bind: Option<SocketAddr>,
+    /// Listener filled in on startup.
+    #[arg(long)]
+    server: Option<String>,
+    /// An env_logger filter, e.g. debug, overriding RUST_LOG.
+    #[arg(long, value_name = "FILTER")]
+    log_level: Option<String>,
+    /// Where to look for the profiles offered for each controller.
+    #[arg(long, value_name = "DIR")]
+    profile_dir: Option<PathBuf>,
+    /// Remap every controller through this profile until another one is
+    /// picked.
+    #[arg(long, value_name = "FILE")]
+    profile: Option<PathBuf>,
+    /// Full controller snapshots per second, 0 disables them.
+    #[arg(long)]
+    snapshot_rate: Option<u32>,
+    /// Motion updates per second, 0 disables them.
+    #[arg(long)]
+    motion_rate: Option<u32>,
+    /// Run without a window, forwarding controllers only and connecting to
+    /// the server on startup.
+    #[arg(long)]
+    headless: bool,
+    /// Record everything sent to the listener to this file, to be replayed
+    /// with iol-replay. JSON lines if it ends in `.jsonl`, postcard otherwise.
+    #[arg(long, value_name = "FILE")]
+    record: Option<PathBuf>,
+}
 
 // Create a new glow context.
 fn glow_context(window: &Window) -> glow::Context {
@@ -32,195 +133,679 @@ fn glow_context(window: &Window) -> glow::Context {
     }
 }
 
-fn setup_controller_id(
-    controller: &GameController,
-    controllers_netids: &mut HashMap<u32, u32>,
-    socket: &UdpSocket,
-    poll: &mut Poll,
-    events: &mut Events,
-    server_address: SocketAddr,
-) -> Result<(), io::Error> {
-    let mut buf = [0; 1 << 16];
-
-    let serialized = to_vec::<IolEvent, 32>(&IolEvent::PhysicalDeviceAdded {
-        which: controller.instance_id(),
-    })
-    .unwrap();
-    let result = socket.send_to(serialized.as_slice(), server_address);
-    match result {
-        Ok(_) => {}
-        Err(e) => {
-            return Err(e);
-        }
-    }
-    loop {
-        if let Err(err) = poll.poll(events, Some(Duration::from_secs(5))) {
-            match err.kind() {
-                io::ErrorKind::Interrupted => {
-                    continue;
-                }
-                _ => {
-                    return Err(err);
-                }
+// The listener expects these axes inverted relative to SDL2.
+fn fix_axis_value(axis: Axis, value: i16) -> i16 {
+    match axis {
+        Axis::LeftY | Axis::RightX => {
+            if value == -32768 {
+                (value + 1) * -1
+            } else {
+                value * -1
             }
         }
-        match socket.recv_from(&mut buf) {
-            Ok((packet_size, _)) => {
-                let event = from_bytes::<IolEvent>(&buf[..packet_size]).unwrap();
-
-                match event {
-                    IolEvent::VirtualDeviceAdded { id, which } => {
-                        println!("Controller {} was added on the listener.", id);
-                        controllers_netids.insert(id, which);
-                        return Ok(());
-                    }
-                    _ => {}
-                }
-            }
+        _ => value,
+    }
+}
+
+fn controller_state(controller: &GameController) -> ControllerState {
+    let mut state = ControllerState::default();
+    for button in ControllerState::BUTTONS {
+        state.set_button(button, controller.button(button));
+    }
+    for axis in ControllerState::AXES {
+        state.set_axis(axis, fix_axis_value(axis, controller.axis(axis)));
+    }
+    state
+}
+
+/// Loads every TOML and JSON file in `dir`, skipping the ones that don't
+/// parse.
+fn load_profiles(dir: &Path) -> Vec<ProfileFile> {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return vec![];
+    };
+    let mut paths: Vec<_> = entries
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| {
+            path.extension()
+                .is_some_and(|extension| extension == "toml" || extension == "json")
+        })
+        .collect();
+    paths.sort();
+
+    paths
+        .into_iter()
+        .filter_map(|path| match ProfileFile::load(path) {
+            Ok(profile) => Some(profile),
             Err(e) => {
-                return Err(e);
+                println!("Skipping profile. {:#}", e);
+                None
             }
+        })
+        .collect()
+}
+
+/// "No profile" followed by the file name of every profile.
+fn profile_labels(none: &str, profiles: &[ProfileFile]) -> Vec<String> {
+    [none.to_owned()]
+        .into_iter()
+        .chain(profiles.iter().map(|profile| {
+            let name = profile.path().file_name().unwrap_or_default();
+            name.to_string_lossy().into_owned()
+        }))
+        .collect()
+}
+
+/// Sends whatever differs between two states of the remapped pad `id`.
+fn send_changes(link: &mut Link, id: u32, before: ControllerState, after: ControllerState) {
+    for button in ControllerState::BUTTONS {
+        match (before.button(button), after.button(button)) {
+            (false, true) => link.send(IolEvent::ButtonDown { id, button }),
+            (true, false) => link.send(IolEvent::ButtonUp { id, button }),
+            _ => {}
         }
     }
+    for axis in ControllerState::AXES {
+        if before.axis(axis) != after.axis(axis) {
+            link.send(IolEvent::AxisMotion {
+                id,
+                axis,
+                value: after.axis(axis),
+            });
+        }
+    }
+}
+
+/// Offers a key to the profiles of every registered controller, returning
+/// whether one of them bound it.
+fn remap_key(
+    link: &mut Link,
+    remappers: &mut HashMap<u32, Remapper>,
+    controllers_netids: &HashMap<u32, u32>,
+    scancode: Scancode,
+    value: bool,
+) -> bool {
+    let mut taken = false;
+    for (which, remapper) in remappers.iter_mut() {
+        let Some(&id) = controllers_netids.get(which) else {
+            continue;
+        };
+        let before = remapper.output();
+        if remapper.set_key(scancode, value) {
+            taken = true;
+            send_changes(link, id, before, remapper.output());
+        }
+    }
+    taken
 }
 
-fn main() -> io::Result<()> {
-    env_logger::init();
+/// The latest motion readings of a controller.
+#[derive(Default)]
+struct MotionSample {
+    timestamp: u64,
+    gyro: [f32; 3],
+    accel: [f32; 3],
+    /// Whether a reading arrived since the sample was last sent.
+    fresh: bool,
+}
 
-    let mut poll = Poll::new()?;
-    let mut events = Events::with_capacity(1);
-    let addr = "0.0.0.0:5864".parse().unwrap();
+/// Finds the physical controller registered under network id `id`.
+fn find_controller<'a>(
+    controllers: &'a mut [GameController],
+    controllers_netids: &HashMap<u32, u32>,
+    id: u32,
+) -> Option<&'a mut GameController> {
+    let (&which, _) = controllers_netids.iter().find(|(_, &netid)| netid == id)?;
+    controllers.iter_mut().find(|c| c.instance_id() == which)
+}
 
-    let mut socket = UdpSocket::bind(addr)?;
-    poll.registry().register(
-        &mut socket,
-        UDP_SOCKET,
-        Interest::WRITABLE | Interest::READABLE,
-    )?;
+/// A controller to register on the listener, by `which` and name, asking
+/// for `target` or the listener's default kind of pad.
+struct PadRequest {
+    which: u32,
+    name: String,
+    target: Option<PadTarget>,
+}
 
-    //    let mut buf = [0; 1 << 16];
+impl PadRequest {
+    fn new(which: u32, name: String, pad_targets: &HashMap<u32, PadTarget>) -> Self {
+        PadRequest {
+            which,
+            name,
+            target: pad_targets.get(&which).copied(),
+        }
+    }
+}
 
-    let mut broadcast_keyboard = true;
-    let mut broadcast_gamepad = true;
-    let mut server_address_str = "192.168.1.12:4863".to_owned();
-    let mut server_address: SocketAddr =
-        SocketAddr::new(IpAddr::V4(Ipv4Addr::new(192, 168, 1, 12)), 4863);
+/// What a worker does with the client, anything that waits on the listener.
+enum Job {
+    /// Resolves `server`, connects to it and registers `pads` if the listener
+    /// accepts any.
+    Connect {
+        server: String,
+        capabilities: Capabilities,
+        pads: Vec<PadRequest>,
+    },
+    /// Registers one more pad during the current session.
+    Register(PadRequest),
+}
 
-    let mut connected: bool = false;
+/// What a [`Job`] came to, registrations by `which`.
+enum Outcome {
+    Connected(anyhow::Result<Vec<(u32, anyhow::Result<u32>)>>),
+    Registered(u32, anyhow::Result<u32>),
+}
 
-    'wait: loop {
-        if let Err(err) = poll.poll(&mut events, None) {
-            if err.kind() == io::ErrorKind::Interrupted {
-                continue;
+impl Job {
+    fn run(self, client: &mut Client) -> Outcome {
+        match self {
+            Job::Connect {
+                server,
+                capabilities,
+                pads,
+            } => Outcome::Connected(connect(client, &server, capabilities, pads)),
+            Job::Register(pad) => {
+                let id = client.register_device(pad.which, pad.target, &pad.name);
+                Outcome::Registered(pad.which, id)
             }
-            return Err(err);
         }
+    }
+}
+
+/// Connects to the listener at `server` and registers `pads` on it if it
+/// accepts any.
+fn connect(
+    client: &mut Client,
+    server: &str,
+    capabilities: Capabilities,
+    pads: Vec<PadRequest>,
+) -> anyhow::Result<Vec<(u32, anyhow::Result<u32>)>> {
+    let server_address = resolve_address(server)?;
+    let session = client.connect(server_address, capabilities)?;
+    println!(
+        "Connected to {} (protocol version {}).",
+        session.server_address, session.version
+    );
+    let refused = capabilities.difference(session.capabilities);
+    if refused.contains(Capabilities::MOTION) {
+        println!(
+            "The listener can't reproduce motion, gyro and accelerometer readings won't be sent."
+        );
+    }
+    if refused.contains(Capabilities::FEEDBACK) {
+        println!("The listener can't send rumble or LED changes back.");
+    }
+    if !session.capabilities.contains(Capabilities::GAMEPAD) {
+        return Ok(vec![]);
+    }
+    Ok(pads
+        .into_iter()
+        .map(|pad| {
+            let id = client.register_device(pad.which, pad.target, &pad.name);
+            (pad.which, id)
+        })
+        .collect())
+}
+
+/// The connection to the listener. The client is lent to a worker thread for
+/// each [`Job`], as waiting on the listener takes seconds when it is
+/// unreachable, and comes back with the job's [`Outcome`].
+struct Link {
+    /// `None` while a worker has the client.
+    client: Option<Client>,
+    /// The session of the client when it was lent.
+    lent_session: Option<Session>,
+    worker: Option<Receiver<(Client, Outcome)>>,
+    jobs: VecDeque<Job>,
+    /// Unplugs sent while a worker had the client.
+    held: Vec<IolEvent>,
+    connecting: bool,
+}
+
+impl Link {
+    fn new(client: Client) -> Self {
+        Link {
+            client: Some(client),
+            lent_session: None,
+            worker: None,
+            jobs: VecDeque::new(),
+            held: vec![],
+            connecting: false,
+        }
+    }
+
+    /// The client, unless a worker has it.
+    fn client(&mut self) -> Option<&mut Client> {
+        self.client.as_mut()
+    }
+
+    fn session(&self) -> Option<Session> {
+        match &self.client {
+            Some(client) => client.session().copied(),
+            None => self.lent_session,
+        }
+    }
+
+    fn is_connected(&self) -> bool {
+        self.session().is_some()
+    }
+
+    /// Whether a [`Job::Connect`] is queued or under way.
+    fn is_connecting(&self) -> bool {
+        self.connecting
+    }
+
+    /// Whether a worker has the client or will.
+    fn is_busy(&self) -> bool {
+        self.client.is_none() || !self.jobs.is_empty()
+    }
 
-        for event in events.iter() {
-            if event.token() == UDP_SOCKET {
-                break 'wait;
+    /// Sends `event` to the listener, only logging failures: an unreachable
+    /// listener must not stop the broadcaster, the session expires instead.
+    /// Input is dropped while a worker has the client, it would be stale by
+    /// the time the client is back, but unplugs wait for it so no virtual pad
+    /// is left behind.
+    fn send(&mut self, event: IolEvent) {
+        let Some(client) = self.client.as_mut() else {
+            if let IolEvent::PhysicalDeviceRemoved { .. } = event {
+                self.held.push(event);
             }
+            return;
+        };
+        if let Err(e) = client.send(event) {
+            log::warn!("Unable to send to the listener: {}", e);
         }
     }
 
-    /* initialize SDL and its video subsystem */
-    let sdl = sdl2::init().unwrap();
-    let video_subsystem = sdl.video().unwrap();
-    let controller_subsystem = sdl.game_controller().unwrap();
-    controller_subsystem.set_event_state(true);
-    let mut controllers: Vec<GameController> = vec![];
-    let mut controllers_netids: HashMap<u32, u32> = HashMap::new();
+    /// Queues `job`, starting it right away if the client is here.
+    fn start(&mut self, job: Job) {
+        if let Job::Connect { .. } = job {
+            self.connecting = true;
+        }
+        self.jobs.push_back(job);
+        self.lend();
+    }
+
+    /// Hands the client to a worker for the next job, if there is one.
+    fn lend(&mut self) {
+        if self.client.is_none() {
+            return;
+        }
+        let Some(job) = self.jobs.pop_front() else {
+            return;
+        };
+        let mut client = self.client.take().unwrap();
+        self.lent_session = client.session().copied();
+        let (tx, rx) = mpsc::channel();
+        thread::spawn(move || {
+            let outcome = job.run(&mut client);
+            tx.send((client, outcome)).ok();
+        });
+        self.worker = Some(rx);
+    }
+
+    /// Takes the client back, sending what was held for it.
+    fn take_back(&mut self, client: Client) {
+        self.worker = None;
+        self.lent_session = None;
+        self.client = Some(client);
+        for event in std::mem::take(&mut self.held) {
+            self.send(event);
+        }
+    }
+
+    /// Returns the outcome of the job under way once the worker is done, and
+    /// starts the next one.
+    fn poll(&mut self) -> anyhow::Result<Option<Outcome>> {
+        let Some(worker) = &self.worker else {
+            return Ok(None);
+        };
+        let (client, outcome) = match worker.try_recv() {
+            Ok(done) => done,
+            Err(TryRecvError::Empty) => return Ok(None),
+            Err(TryRecvError::Disconnected) => anyhow::bail!("the connection worker stopped"),
+        };
+        self.take_back(client);
+        if let Outcome::Connected(_) = outcome {
+            self.connecting = self
+                .jobs
+                .iter()
+                .any(|job| matches!(job, Job::Connect { .. }));
+        }
+        self.lend();
+        Ok(Some(outcome))
+    }
+
+    /// Waits for the client to come back, dropping the jobs left.
+    fn into_client(mut self) -> anyhow::Result<Client> {
+        self.jobs.clear();
+        if let Some(worker) = self.worker.take() {
+            let (client, _) = worker.recv()?;
+            self.take_back(client);
+        }
+        Ok(self.client.take().unwrap())
+    }
+}
+
+/// Takes in the id the listener gave to pad `which`, or why it gave none. A
+/// pad that was unplugged, or registered once more, in the meantime is
+/// unplugged from the listener again.
+fn pad_registered(
+    link: &mut Link,
+    which: u32,
+    registered: anyhow::Result<u32>,
+    present: bool,
+    controllers_netids: &mut HashMap<u32, u32>,
+    pad_errors: &mut HashMap<u32, String>,
+) {
+    match registered {
+        Ok(id) if !present || controllers_netids.contains_key(&which) => {
+            link.send(IolEvent::PhysicalDeviceRemoved { id });
+        }
+        Ok(id) => {
+            println!("Controller {} was added on the listener.", id);
+            controllers_netids.insert(which, id);
+            pad_errors.remove(&which);
+        }
+        Err(e) => {
+            println!("Unable to setup controller. {:#}", e);
+            pad_errors.insert(which, format!("{:#}", e));
+        }
+    }
+}
+
+/// The window and its imgui context, absent in headless mode.
+struct Gui {
+    window: Window,
+    _gl_context: GLContext,
+    imgui: Context,
+    platform: SdlPlatform,
+    renderer: AutoRenderer,
+}
+
+impl Gui {
+    fn new(sdl: &Sdl) -> Self {
+        let video_subsystem = sdl.video().unwrap();
+
+        /* hint SDL to initialize an OpenGL 3.3 core profile context */
+        let gl_attr = video_subsystem.gl_attr();
 
-    /* hint SDL to initialize an OpenGL 3.3 core profile context */
-    let gl_attr = video_subsystem.gl_attr();
+        gl_attr.set_context_version(3, 3);
+        gl_attr.set_context_profile(GLProfile::Core);
 
-    gl_attr.set_context_version(3, 3);
-    gl_attr.set_context_profile(GLProfile::Core);
+        /* create a new window, be sure to call opengl method on the builder when using glow! */
+        let window = video_subsystem
+            .window("iol", SCREEN_WIDTH, SCREEN_HEIGHT)
+            .allow_highdpi()
+            .opengl()
+            .position_centered()
+            .resizable()
+            .build()
+            .unwrap();
+
+        let gl_context = window.gl_create_context().unwrap();
+        window.gl_make_current(&gl_context).unwrap();
+
+        window.subsystem().gl_set_swap_interval(1).unwrap();
+
+        let gl = glow_context(&window);
+        let mut imgui = Context::create();
+
+        imgui.set_ini_filename(None);
+        imgui.set_log_filename(None);
+
+        /* setup platform and renderer, and fonts to imgui */
+        imgui
+            .fonts()
+            .add_font(&[imgui::FontSource::DefaultFontData { config: None }]);
+
+        let platform = SdlPlatform::init(&mut imgui);
+        let renderer = AutoRenderer::initialize(gl, &mut imgui).unwrap();
+
+        Gui {
+            window,
+            _gl_context: gl_context,
+            imgui,
+            platform,
+            renderer,
+        }
+    }
+}
 
-    /* create a new window, be sure to call opengl method on the builder when using glow! */
-    let window = video_subsystem
-        .window("iol", SCREEN_WIDTH, SCREEN_HEIGHT)
-        .allow_highdpi()
-        .opengl()
-        .position_centered()
-        .resizable()
-        .build()
-        .unwrap();
+/// Has a worker register controller `which` on the listener, its id is
+/// taken in by [`pad_registered`].
+fn setup_controller_id(
+    which: u32,
+    name: &str,
+    pad_targets: &HashMap<u32, PadTarget>,
+    link: &mut Link,
+) {
+    link.start(Job::Register(PadRequest::new(
+        which,
+        name.to_owned(),
+        pad_targets,
+    )));
+}
 
-    let gl_context = window.gl_create_context().unwrap();
-    window.gl_make_current(&gl_context).unwrap();
+fn main() -> anyhow::Result<()> {
+    let args = Args::parse();
+    let mut config = Config::load(args.config.as_deref())?.broadcast;
+    if let Some(bind) = args.bind {
+        config.bind = bind;
+    }
+    if let Some(server) = args.server {
+        config.server = server;
+    }
+    if args.log_level.is_some() {
+        config.log_level = args.log_level;
+    }
+    if let Some(dir) = args.profile_dir {
+        config.profile_dir = dir;
+    }
+    if args.profile.is_some() {
+        config.profile = args.profile;
+    }
+    if let Some(rate) = args.snapshot_rate {
+        config.snapshot_rate = rate;
+    }
+    if let Some(rate) = args.motion_rate {
+        config.motion_rate = rate;
+    }
 
-    window.subsystem().gl_set_swap_interval(1).unwrap();
+    config::init_logging(config.log_level.as_deref());
 
-    let gl = glow_context(&window);
-    let mut imgui = Context::create();
+    let mut client = Client::bind(config.bind)?;
+    if let Some(path) = args.record {
+        client.set_recorder(Some(Recorder::create(&path)?));
+        println!("Recording to {}", path.display());
+    }
+    let mut link = Link::new(client);
 
-    imgui.set_ini_filename(None);
-    imgui.set_log_filename(None);
+    let mut broadcast_keyboard = true;
+    let mut broadcast_gamepad = true;
+    let mut capture_mouse = false;
+    let mut server_address_str = config.server;
+    let mut connection_error: Option<String> = None;
+    // Full controller snapshots per second, 0 disables them.
+    let mut snapshot_rate = config.snapshot_rate;
+    let mut last_snapshot = Instant::now();
+    // Motion updates per second, 0 disables them.
+    let mut motion_rate = config.motion_rate;
+    let mut last_motion = Instant::now();
+    let mut motion_samples: HashMap<u32, MotionSample> = HashMap::new();
+    // The listener to connect to on startup in headless mode.
+    let headless_server = args.headless.then(|| server_address_str.clone());
+    let mut last_connect_attempt: Option<Instant> = None;
+    let mut discovery = Discovery::bind("0.0.0.0:0".parse().unwrap())?;
+    let mut last_probe: Option<Instant> = None;
 
-    /* setup platform and renderer, and fonts to imgui */
-    imgui
-        .fonts()
-        .add_font(&[imgui::FontSource::DefaultFontData { config: None }]);
+    /* initialize SDL, its video subsystem is only needed for the window */
+    if args.headless {
+        // Without a window SDL never has focus, forward controllers anyway.
+        sdl2::hint::set("SDL_JOYSTICK_ALLOW_BACKGROUND_EVENTS", "1");
+    }
+    let sdl = sdl2::init().unwrap();
+    let mouse_util = sdl.mouse();
+    let controller_subsystem = sdl.game_controller().unwrap();
+    controller_subsystem.set_event_state(true);
+    let mut controllers: Vec<GameController> = vec![];
+    let mut controllers_netids: HashMap<u32, u32> = HashMap::new();
+    let mut player_slots: HashMap<u32, u8> = HashMap::new();
+    // Why the listener gave no id to a controller.
+    let mut pad_errors: HashMap<u32, String> = HashMap::new();
+    let mut pad_targets: HashMap<u32, PadTarget> = HashMap::new();
+    let mut profiles = load_profiles(&config.profile_dir);
+    // Index into `profiles` of the profile new controllers start with.
+    let default_profile = match config.profile {
+        Some(path) => {
+            let profile = ProfileFile::load(path)?;
+            println!("Remapping controllers with {}", profile.path().display());
+            let same_file = |other: &ProfileFile| match (
+                other.path().canonicalize(),
+                profile.path().canonicalize(),
+            ) {
+                (Ok(a), Ok(b)) => a == b,
+                _ => false,
+            };
+            match profiles.iter().position(same_file) {
+                Some(index) => Some(index),
+                None => {
+                    profiles.push(profile);
+                    Some(profiles.len() - 1)
+                }
+            }
+        }
+        None => None,
+    };
+    let mut last_profile_check = Instant::now();
+    // Index into `profiles` of the profile picked for each controller.
+    let mut controller_profiles: HashMap<u32, usize> = HashMap::new();
+    let mut remappers: HashMap<u32, Remapper> = HashMap::new();
+    let mut keyboard_pad = false;
+    let keyboard_pad_layout = Rc::new(RefCell::new(
+        Profile::from_toml(KEYBOARD_PAD_LAYOUT).expect("invalid keyboard pad layout"),
+    ));
+    // Index into `profiles` of the profile driving the keyboard pad, if not
+    // the default layout.
+    let mut keyboard_pad_profile: Option<usize> = None;
 
-    let mut platform = SdlPlatform::init(&mut imgui);
-    let mut renderer = AutoRenderer::initialize(gl, &mut imgui).unwrap();
+    let mut gui = if args.headless {
+        None
+    } else {
+        Some(Gui::new(&sdl))
+    };
 
     let mut event_pump = sdl.event_pump().unwrap();
 
     'main: loop {
-        // Process each event.
+        // Process each event, the window paces the loop and without one we
+        // wait for events instead.
+        let events: Vec<Event> = match gui {
+            Some(_) => event_pump.poll_iter().collect(),
+            None => {
+                let first = event_pump.wait_event_timeout(HEADLESS_WAIT_MS);
+                first.into_iter().chain(event_pump.poll_iter()).collect()
+            }
+        };
 
-        for event in event_pump.poll_iter() {
+        for event in events {
             /* pass all events to imgui platfrom */
-            platform.handle_event(&mut imgui, &event);
+            if let Some(gui) = gui.as_mut() {
+                gui.platform.handle_event(&mut gui.imgui, &event);
+            }
 
             match event {
                 Event::Quit { .. } => break 'main,
                 Event::KeyDown {
-                    scancode, repeat, ..
+                    scancode: Some(CAPTURE_TOGGLE),
+                    repeat: false,
+                    ..
                 } => {
-                    if !repeat {
-                        if broadcast_keyboard {
-                            let serialized = to_vec::<IolEvent, 32>(&IolEvent::KeyDown {
-                                scancode: scancode.unwrap(),
-                                repeat,
-                            })
-                            .unwrap();
-
-                            socket.send_to(serialized.as_slice(), server_address)?;
-                        }
+                    capture_mouse = !capture_mouse;
+                    mouse_util.set_relative_mouse_mode(capture_mouse);
+                }
+                Event::KeyUp {
+                    scancode: Some(CAPTURE_TOGGLE),
+                    ..
+                } => {}
+                Event::KeyDown {
+                    scancode: Some(scancode),
+                    repeat: false,
+                    ..
+                } => {
+                    let remapped = remap_key(
+                        &mut link,
+                        &mut remappers,
+                        &controllers_netids,
+                        scancode,
+                        true,
+                    );
+                    if !remapped && broadcast_keyboard {
+                        link.send(IolEvent::KeyDown {
+                            scancode,
+                            repeat: false,
+                        });
                     }
                 }
-                Event::KeyUp { scancode, .. } => {
-                    if broadcast_keyboard {
-                        let serialized = to_vec::<IolEvent, 32>(&IolEvent::KeyUp {
-                            scancode: scancode.unwrap(),
-                        })
-                        .unwrap();
-
-                        socket.send_to(serialized.as_slice(), server_address)?;
+                Event::KeyUp {
+                    scancode: Some(scancode),
+                    ..
+                } => {
+                    let remapped = remap_key(
+                        &mut link,
+                        &mut remappers,
+                        &controllers_netids,
+                        scancode,
+                        false,
+                    );
+                    if !remapped && broadcast_keyboard {
+                        link.send(IolEvent::KeyUp { scancode });
                     }
                 }
+                Event::MouseMotion { xrel, yrel, .. } if capture_mouse => {
+                    link.send(IolEvent::MouseMotion { dx: xrel, dy: yrel });
+                }
+                Event::MouseButtonDown { mouse_btn, .. } if capture_mouse => {
+                    link.send(IolEvent::MouseButtonDown { button: mouse_btn });
+                }
+                Event::MouseButtonUp { mouse_btn, .. } => {
+                    // Always sent, so a button held while releasing capture
+                    // does not stay stuck on the listener, which drops the
+                    // releases of buttons it never saw pressed.
+                    link.send(IolEvent::MouseButtonUp { button: mouse_btn });
+                }
+                Event::MouseWheel {
+                    x, y, direction, ..
+                } if capture_mouse => {
+                    let (x, y) = match direction {
+                        MouseWheelDirection::Flipped => (-x, -y),
+                        _ => (x, y),
+                    };
+                    link.send(IolEvent::MouseWheel { x, y });
+                }
                 Event::ControllerDeviceAdded { which, .. } => {
                     println!("Controller {} was added.", which);
 
                     match controller_subsystem.open(which) {
                         Ok(c) => {
+                            for sensor in [SensorType::Gyroscope, SensorType::Accelerometer] {
+                                if c.has_sensor(sensor) {
+                                    if let Err(e) = c.sensor_set_enabled(sensor, true) {
+                                        log::warn!("Unable to enable {:?}: {}", sensor, e);
+                                    }
+                                }
+                            }
+                            let instance_id = c.instance_id();
+                            let name = c.name();
+                            println!("Opened {} as controller {}.", name, instance_id);
+                            let profile = default_profile.map(|index| profiles[index].profile());
+                            remappers.insert(instance_id, Remapper::new(profile));
+                            if let Some(index) = default_profile {
+                                controller_profiles.insert(instance_id, index);
+                            }
                             controllers.push(c);
-                            if connected {
-                                setup_controller_id(
-                                    &controllers
-                                        .iter()
-                                        .find(|&c| c.instance_id() == which)
-                                        .unwrap(),
-                                    &mut controllers_netids,
-                                    &socket,
-                                    &mut poll,
-                                    &mut events,
-                                    server_address,
-                                )
-                                .expect("Unable to setup controller.");
+                            if link.is_connected() {
+                                setup_controller_id(instance_id, &name, &pad_targets, &mut link);
                             }
                         }
                         Err(e) => {
@@ -230,13 +815,14 @@ fn main() -> io::Result<()> {
                 }
 
                 Event::ControllerDeviceRemoved { which, .. } => {
-                    let id = controllers_netids.get(&which).cloned();
-                    if let Some(id) = id {
-                        let serialized =
-                            to_vec::<IolEvent, 32>(&IolEvent::PhysicalDeviceRemoved { id })
-                                .unwrap();
-                        socket.send_to(serialized.as_slice(), server_address)?;
-                        controllers_netids.remove(&id);
+                    player_slots.remove(&which);
+                    pad_targets.remove(&which);
+                    motion_samples.remove(&which);
+                    controller_profiles.remove(&which);
+                    remappers.remove(&which);
+                    pad_errors.remove(&which);
+                    if let Some(id) = controllers_netids.remove(&which) {
+                        link.send(IolEvent::PhysicalDeviceRemoved { id });
                     }
 
                     controllers.remove(
@@ -247,53 +833,312 @@ fn main() -> io::Result<()> {
                     );
                     println!("Controller {} was removed.", which);
                 }
-                Event::ControllerButtonDown { which, button, .. } => {
-                    let id = controllers_netids.get(&which);
-                    if let Some(id) = id {
-                        let serialized =
-                            to_vec::<IolEvent, 32>(&IolEvent::ButtonDown { id: *id, button })
-                                .unwrap();
-                        socket.send_to(serialized.as_slice(), server_address)?;
+                Event::ControllerSensorUpdated {
+                    which,
+                    sensor,
+                    data,
+                    timestamp,
+                } => {
+                    let sample = motion_samples.entry(which).or_default();
+                    match sensor {
+                        SensorType::Gyroscope => sample.gyro = data,
+                        SensorType::Accelerometer => sample.accel = data,
+                        SensorType::Unknown => continue,
+                    }
+                    // SDL only gives us milliseconds.
+                    sample.timestamp = timestamp as u64 * 1000;
+                    sample.fresh = true;
+                }
+                Event::ControllerTouchpadDown {
+                    which,
+                    touchpad,
+                    finger,
+                    x,
+                    y,
+                    pressure,
+                    ..
+                }
+                | Event::ControllerTouchpadMotion {
+                    which,
+                    touchpad,
+                    finger,
+                    x,
+                    y,
+                    pressure,
+                    ..
+                } => {
+                    if let Some(&id) = controllers_netids.get(&which) {
+                        let point = TouchPoint {
+                            touchpad: touchpad as u8,
+                            finger: finger as u8,
+                            touching: true,
+                            x,
+                            y,
+                            pressure,
+                        };
+                        link.send(IolEvent::Touch { id, point });
+                    }
+                }
+                Event::ControllerTouchpadUp {
+                    which,
+                    touchpad,
+                    finger,
+                    x,
+                    y,
+                    pressure,
+                    ..
+                } => {
+                    if let Some(&id) = controllers_netids.get(&which) {
+                        let point = TouchPoint {
+                            touchpad: touchpad as u8,
+                            finger: finger as u8,
+                            touching: false,
+                            x,
+                            y,
+                            pressure,
+                        };
+                        link.send(IolEvent::Touch { id, point });
                     }
                 }
-                Event::ControllerButtonUp { which, button, .. } => {
+                Event::ControllerButtonDown { which, button, .. }
+                | Event::ControllerButtonUp { which, button, .. } => {
+                    let pressed = matches!(event, Event::ControllerButtonDown { .. });
                     let id = controllers_netids.get(&which);
-                    if let Some(id) = id {
-                        let serialized =
-                            to_vec::<IolEvent, 32>(&IolEvent::ButtonUp { id: *id, button })
-                                .unwrap();
-                        socket.send_to(serialized.as_slice(), server_address)?;
+                    if let (Some(&id), Some(remapper)) = (id, remappers.get_mut(&which)) {
+                        let before = remapper.output();
+                        remapper.set_button(button, pressed);
+                        send_changes(&mut link, id, before, remapper.output());
                     }
                 }
                 Event::ControllerAxisMotion {
                     which, axis, value, ..
                 } => {
                     let id = controllers_netids.get(&which);
-                    let fixed_value = match axis {
-                        sdl2::controller::Axis::LeftY | sdl2::controller::Axis::RightX => {
-                            if value == -32768 {
-                                (value + 1) * -1
-                            } else {
-                                value * -1
+                    let fixed_value = fix_axis_value(axis, value);
+                    if let (Some(&id), Some(remapper)) = (id, remappers.get_mut(&which)) {
+                        let before = remapper.output();
+                        remapper.set_axis(axis, fixed_value);
+                        send_changes(&mut link, id, before, remapper.output());
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        if snapshot_rate > 0 && last_snapshot.elapsed() >= Duration::from_secs(1) / snapshot_rate {
+            last_snapshot = Instant::now();
+            for (which, remapper) in remappers.iter_mut() {
+                let Some(&id) = controllers_netids.get(which) else {
+                    continue;
+                };
+                if let Some(controller) = controllers.iter().find(|c| c.instance_id() == *which) {
+                    remapper.set_state(controller_state(controller));
+                }
+                link.send(IolEvent::ControllerState {
+                    id,
+                    state: remapper.output(),
+                });
+            }
+        }
+
+        let motion_accepted = link
+            .session()
+            .is_some_and(|session| session.capabilities.contains(Capabilities::MOTION));
+        if motion_accepted
+            && motion_rate > 0
+            && last_motion.elapsed() >= Duration::from_secs(1) / motion_rate
+        {
+            last_motion = Instant::now();
+            for (which, sample) in motion_samples.iter_mut() {
+                let Some(&id) = controllers_netids.get(which) else {
+                    continue;
+                };
+                if sample.fresh {
+                    sample.fresh = false;
+                    link.send(IolEvent::Motion {
+                        id,
+                        timestamp: sample.timestamp,
+                        gyro: sample.gyro,
+                        accel: sample.accel,
+                    });
+                }
+            }
+        }
+
+        if last_profile_check.elapsed() >= PROFILE_RELOAD_INTERVAL {
+            last_profile_check = Instant::now();
+            for profile in profiles.iter_mut() {
+                match profile.reload_if_changed() {
+                    Ok(true) => println!("Reloaded profile {}.", profile.path().display()),
+                    Ok(false) => {}
+                    Err(e) => println!("Keeping the previous profile. {:#}", e),
+                }
+            }
+        }
+
+        if let Some(server_address) = headless_server.as_deref() {
+            let due = match last_connect_attempt {
+                Some(at) => at.elapsed() >= RECONNECT_INTERVAL,
+                None => true,
+            };
+            if !link.is_connected() && !link.is_connecting() && due {
+                last_connect_attempt = Some(Instant::now());
+                let pads = controllers
+                    .iter()
+                    .map(|c| PadRequest::new(c.instance_id(), c.name(), &pad_targets))
+                    .collect();
+                link.start(Job::Connect {
+                    server: server_address.to_owned(),
+                    capabilities: Capabilities::GAMEPAD
+                        .union(Capabilities::FEEDBACK)
+                        .union(Capabilities::MOTION),
+                    pads,
+                });
+            }
+        }
+
+        match link.poll()? {
+            Some(Outcome::Connected(Ok(registered))) => {
+                connection_error = None;
+                // Ids from an earlier session mean nothing to this one.
+                controllers_netids.clear();
+                pad_errors.clear();
+                let mut unregistered: Vec<(u32, String)> = controllers
+                    .iter()
+                    .map(|c| (c.instance_id(), c.name()))
+                    .collect();
+                if keyboard_pad {
+                    unregistered.push((KEYBOARD_PAD, KEYBOARD_PAD_NAME.to_owned()));
+                }
+                let gamepads = link
+                    .session()
+                    .is_some_and(|session| session.capabilities.contains(Capabilities::GAMEPAD));
+                for (which, id) in registered {
+                    unregistered.retain(|&(other, _)| other != which);
+                    let present = which == KEYBOARD_PAD && keyboard_pad
+                        || controllers.iter().any(|c| c.instance_id() == which);
+                    pad_registered(
+                        &mut link,
+                        which,
+                        id,
+                        present,
+                        &mut controllers_netids,
+                        &mut pad_errors,
+                    );
+                }
+                // Pads plugged in while connecting.
+                if gamepads {
+                    for (which, name) in unregistered {
+                        setup_controller_id(which, &name, &pad_targets, &mut link);
+                    }
+                }
+            }
+            Some(Outcome::Connected(Err(e))) => {
+                if headless_server.is_some() {
+                    println!("Unable to connect, retrying. {:#}", e);
+                } else {
+                    println!("Unable to connect. {:#}", e);
+                }
+                connection_error = Some(format!("{:#}", e));
+            }
+            Some(Outcome::Registered(which, id)) => {
+                let present = which == KEYBOARD_PAD && keyboard_pad
+                    || controllers.iter().any(|c| c.instance_id() == which);
+                pad_registered(
+                    &mut link,
+                    which,
+                    id,
+                    present,
+                    &mut controllers_netids,
+                    &mut pad_errors,
+                );
+            }
+            None => {}
+        }
+
+        if gui.is_some() && !link.is_connected() {
+            let due = match last_probe {
+                Some(at) => at.elapsed() >= DISCOVERY_INTERVAL,
+                None => true,
+            };
+            if due {
+                last_probe = Some(Instant::now());
+                if let Err(e) = discovery.probe_lan() {
+                    log::warn!("Unable to look for listeners: {}", e);
+                }
+            }
+            if let Err(e) = discovery.poll() {
+                log::warn!("Unable to receive discovery replies: {}", e);
+            }
+        }
+
+        let was_connected = link.is_connected();
+        if let Some(client) = link.client() {
+            if let Err(e) = client.keep_alive() {
+                log::warn!("Unable to send a heartbeat: {}", e);
+            }
+        }
+        if was_connected && !link.is_connected() {
+            println!("The listener stopped answering, disconnected.");
+            connection_error = Some("The listener stopped answering.".to_owned());
+            // The listener is gone, and so are the pads it plugged in.
+            controllers_netids.clear();
+            player_slots.clear();
+            pad_errors.clear();
+        }
+
+        while let Some(client) = link.client() {
+            match client.try_recv() {
+                Ok(Some(IolEvent::Rumble {
+                    id,
+                    low,
+                    high,
+                    duration,
+                })) => {
+                    if let Some(controller) =
+                        find_controller(&mut controllers, &controllers_netids, id)
+                    {
+                        if let Err(e) = controller.set_rumble(low, high, duration) {
+                            log::warn!("Unable to rumble {}: {}", controller.name(), e);
+                        }
+                    }
+                }
+                Ok(Some(IolEvent::PlayerSlot { id, slot })) => {
+                    if let Some(controller) =
+                        find_controller(&mut controllers, &controllers_netids, id)
+                    {
+                        println!("Controller {} is player {}.", id, slot + 1);
+                        player_slots.insert(controller.instance_id(), slot);
+                        if controller.has_led() {
+                            let [red, green, blue] =
+                                PLAYER_COLORS[slot as usize % PLAYER_COLORS.len()];
+                            if let Err(e) = controller.set_led(red, green, blue) {
+                                log::warn!("Unable to set the LED of {}: {}", controller.name(), e);
                             }
                         }
-                        _ => value,
-                    };
-                    if let Some(id) = id {
-                        let serialized = to_vec::<IolEvent, 32>(&IolEvent::AxisMotion {
-                            id: *id,
-                            axis,
-                            value: fixed_value,
-                        })
-                        .unwrap();
-                        socket.send_to(serialized.as_slice(), server_address)?;
                     }
                 }
-                _ => {}
+                Ok(Some(_)) => {}
+                Ok(None) => break,
+                Err(e) => {
+                    log::warn!("Unable to receive from the listener: {}", e);
+                    break;
+                }
             }
         }
 
-        platform.prepare_frame(&mut imgui, &window, &event_pump);
+        let Some(Gui {
+            window,
+            imgui,
+            platform,
+            renderer,
+            ..
+        }) = gui.as_mut()
+        else {
+            continue;
+        };
+
+        platform.prepare_frame(imgui, window, &event_pump);
         let ui = imgui.new_frame();
 
         ui.window("Receivers")
@@ -302,50 +1147,129 @@ fn main() -> io::Result<()> {
                 ui.checkbox("Keyboard", &mut broadcast_keyboard);
                 ui.same_line();
                 ui.checkbox("Gamepad", &mut broadcast_gamepad);
+                if ui.checkbox("Capture Mouse (Scroll Lock)", &mut capture_mouse) {
+                    mouse_util.set_relative_mouse_mode(capture_mouse);
+                }
+                if ui.checkbox("Keyboard Pad", &mut keyboard_pad) {
+                    if keyboard_pad {
+                        let profile = match keyboard_pad_profile {
+                            Some(index) => profiles[index].profile(),
+                            None => keyboard_pad_layout.clone(),
+                        };
+                        remappers.insert(KEYBOARD_PAD, Remapper::new(Some(profile)));
+                        if link.is_connected() {
+                            setup_controller_id(
+                                KEYBOARD_PAD,
+                                KEYBOARD_PAD_NAME,
+                                &pad_targets,
+                                &mut link,
+                            );
+                        }
+                    } else {
+                        remappers.remove(&KEYBOARD_PAD);
+                        pad_errors.remove(&KEYBOARD_PAD);
+                        if let Some(id) = controllers_netids.remove(&KEYBOARD_PAD) {
+                            link.send(IolEvent::PhysicalDeviceRemoved { id });
+                        }
+                    }
+                }
+                ui.same_line();
+                let mut choice = keyboard_pad_profile.map_or(0, |index| index + 1);
+                let labels = profile_labels("Default layout", &profiles);
+                if ui.combo_simple_string("##keyboard_pad_profile", &mut choice, &labels) {
+                    keyboard_pad_profile = choice.checked_sub(1);
+                    if let Some(remapper) = remappers.get_mut(&KEYBOARD_PAD) {
+                        remapper.set_profile(Some(match keyboard_pad_profile {
+                            Some(index) => profiles[index].profile(),
+                            None => keyboard_pad_layout.clone(),
+                        }));
+                    }
+                }
+                if let Some(error) = pad_errors.get(&KEYBOARD_PAD) {
+                    ui.text_colored([1.0, 0.0, 0.0, 1.0], error);
+                }
+                ui.slider("Snapshots per second", 0, 60, &mut snapshot_rate);
+                ui.slider("Motion updates per second", 0, 240, &mut motion_rate);
                 ui.spacing();
                 ui.dummy([0.0, 20.0]);
 
                 ui.input_text("Server Address", &mut server_address_str)
                     .build();
-                if !connected {
-                    if ui.button("Connect") {
-                        server_address = server_address_str
-                            .parse()
-                            .expect("Unable to parse socket address");
-                        for controller in controllers.iter() {
-                            let result = setup_controller_id(
-                                controller,
-                                &mut controllers_netids,
-                                &socket,
-                                &mut poll,
-                                &mut events,
-                                server_address,
-                            );
-                            match result {
-                                Ok(()) => {
-                                    connected = true;
-                                }
-                                Err(e) => match e.kind() {
-                                    _ => {
-                                        println!("Unable to setup controller. {:#?}", e)
-                                    }
-                                },
-                            }
+                if !link.is_connected() {
+                    let mut connect_to: Option<String> = None;
+                    let connecting = link.is_connecting();
+                    if connecting {
+                        ui.text_disabled("Connecting...");
+                    } else if ui.button("Connect") {
+                        connect_to = Some(server_address_str.clone());
+                    }
+                    if let Some(error) = &connection_error {
+                        ui.text_colored([1.0, 0.0, 0.0, 1.0], error);
+                    }
+
+                    ui.spacing();
+                    ui.text("Listeners on the network:");
+                    for listener in discovery.listeners() {
+                        let pads = match listener.capacity {
+                            Some(capacity) => format!("{}/{}", listener.pads, capacity),
+                            None => listener.pads.to_string(),
+                        };
+                        let label = format!(
+                            "{} ({}), {} clients, {} pads",
+                            listener.name, listener.address, listener.clients, pads
+                        );
+                        if !listener.is_compatible() {
+                            ui.text_disabled(format!(
+                                "{}, protocol version {}",
+                                label, listener.version
+                            ));
+                            continue;
+                        }
+                        if !connecting && ui.button(format!("Connect##{}", listener.address)) {
+                            server_address_str = listener.address.to_string();
+                            connect_to = Some(server_address_str.clone());
                         }
+                        ui.same_line();
+                        ui.text(label);
                     }
-                } else {
-                    if ui.button("Disconnect") {
-                        for controller in controllers_netids.iter() {
-                            let id = *controller.0;
-                            let serialized =
-                                to_vec::<IolEvent, 32>(&IolEvent::PhysicalDeviceRemoved { id })
-                                    .unwrap();
-                            socket.send_to(serialized.as_slice(), server_address).ok();
-                            println!("Controller {} was removed on the listener.", id);
+
+                    if let Some(server) = connect_to {
+                        let mut capabilities = Capabilities::MOUSE;
+                        if broadcast_gamepad || keyboard_pad {
+                            capabilities = capabilities
+                                .union(Capabilities::GAMEPAD)
+                                .union(Capabilities::FEEDBACK)
+                                .union(Capabilities::MOTION);
+                        }
+                        if broadcast_keyboard {
+                            capabilities = capabilities.union(Capabilities::KEYBOARD);
                         }
-                        controllers_netids.clear();
-                        connected = false;
+                        let keyboard =
+                            keyboard_pad.then(|| (KEYBOARD_PAD, KEYBOARD_PAD_NAME.to_owned()));
+                        let pads = keyboard
+                            .into_iter()
+                            .chain(controllers.iter().map(|c| (c.instance_id(), c.name())))
+                            .map(|(which, name)| PadRequest::new(which, name, &pad_targets))
+                            .collect();
+                        link.start(Job::Connect {
+                            server,
+                            capabilities,
+                            pads,
+                        });
                     }
+                } else if link.is_busy() {
+                    ui.text_disabled("Adding controllers...");
+                } else if ui.button("Disconnect") {
+                    for (_, &id) in controllers_netids.iter() {
+                        link.send(IolEvent::PhysicalDeviceRemoved { id });
+                        println!("Controller {} was removed on the listener.", id);
+                    }
+                    if let Some(client) = link.client() {
+                        client.disconnect();
+                    }
+                    controllers_netids.clear();
+                    player_slots.clear();
+                    pad_errors.clear();
                 }
             });
 
@@ -362,13 +1286,62 @@ fn main() -> io::Result<()> {
                     ui.text(controller.name());
                     ui.same_line();
                     let id = controllers_netids.get(&controller.instance_id());
-                    let id_string = if let Some(id) = id {
-                        format!("id: {}", id)
-                    } else {
-                        "id: n/a".to_string()
-                    };
+                    let error = pad_errors.get(&controller.instance_id());
+                    match (id, error) {
+                        (None, Some(error)) => ui.text_colored([1.0, 0.0, 0.0, 1.0], error),
+                        (id, _) => {
+                            let id_string = if let Some(id) = id {
+                                format!("id: {}", id)
+                            } else {
+                                "id: n/a".to_string()
+                            };
+                            ui.text_colored([1.0, 1.0, 0.0, 1.0], id_string);
+                        }
+                    }
+                    if let Some(slot) = player_slots.get(&controller.instance_id()) {
+                        ui.same_line();
+                        ui.text(format!("player: {}", slot + 1));
+                    }
 
-                    ui.text_colored([1.0, 1.0, 0.0, 1.0], id_string);
+                    let which = controller.instance_id();
+                    let mut choice = TARGET_CHOICES
+                        .iter()
+                        .position(|&(_, target)| target == pad_targets.get(&which).copied())
+                        .unwrap_or(0);
+                    let labels = TARGET_CHOICES.map(|(label, _)| label);
+                    ui.same_line();
+                    if ui.combo_simple_string(format!("##target{}", which), &mut choice, &labels) {
+                        match TARGET_CHOICES[choice].1 {
+                            Some(target) => pad_targets.insert(which, target),
+                            None => pad_targets.remove(&which),
+                        };
+                        // Replug the virtual pad so the new kind takes effect.
+                        if let Some(id) = controllers_netids.remove(&which) {
+                            player_slots.remove(&which);
+                            link.send(IolEvent::PhysicalDeviceRemoved { id });
+                            setup_controller_id(which, &controller.name(), &pad_targets, &mut link);
+                        }
+                    }
+
+                    let mut choice = controller_profiles.get(&which).map_or(0, |&i| i + 1);
+                    let labels = profile_labels("No profile", &profiles);
+                    if ui.combo_simple_string(format!("##profile{}", which), &mut choice, &labels) {
+                        let profile = choice.checked_sub(1);
+                        match profile {
+                            Some(index) => controller_profiles.insert(which, index),
+                            None => controller_profiles.remove(&which),
+                        };
+                        if let Some(remapper) = remappers.get_mut(&which) {
+                            remapper.set_profile(profile.map(|index| profiles[index].profile()));
+                            remapper.set_state(controller_state(controller));
+                            if let Some(&id) = controllers_netids.get(&which) {
+                                link.send(IolEvent::ControllerState {
+                                    id,
+                                    state: remapper.output(),
+                                });
+                            }
+                        }
+                    }
                 }
             });
 
@@ -380,5 +1353,7 @@ fn main() -> io::Result<()> {
         window.gl_swap_window();
     }
 
+    link.into_client()?.disconnect();
+
     Ok(())
 }
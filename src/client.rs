@@ -0,0 +1,441 @@
+use std::{
+    io,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, ToSocketAddrs},
+    process,
+    time::{Duration, Instant},
+};
+
+use anyhow::{anyhow, bail, Context};
+use log::warn;
+use mio::{net::UdpSocket, Events, Interest, Poll, Token};
+use postcard::{from_bytes, to_allocvec};
+
+use crate::{
+    config::DEFAULT_PORT, mdns, recording::Recorder, Capabilities, Envelope, IolEvent, PadTarget,
+    RejectReason, PROTOCOL_VERSION,
+};
+
+const UDP_SOCKET: Token = Token(0);
+const REPLY_TIMEOUT: Duration = Duration::from_secs(5);
+/// Well below the listener's default client timeout.
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(1);
+/// How long the listener may stay silent before the session is dropped, see
+/// [`Client::set_timeout`].
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(3);
+/// How long to wait for an answer when resolving a `.local` name.
+const MDNS_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// What was agreed on with a listener during the handshake.
+#[derive(Debug, Clone, Copy)]
+pub struct Session {
+    /// Random id stamped on every packet of this session.
+    pub id: u32,
+    pub server_address: SocketAddr,
+    pub version: u16,
+    pub capabilities: Capabilities,
+}
+
+/// The broadcaster's side of the connection to a listener.
+pub struct Client {
+    poll: Poll,
+    events: Events,
+    socket: UdpSocket,
+    session: Option<Session>,
+    sequence: u64,
+    last_heartbeat: Instant,
+    /// When the listener last sent anything for this session.
+    last_heard: Instant,
+    timeout: Duration,
+    /// Sequence number of the newest packet returned by [`Client::try_recv`].
+    last_received: Option<u64>,
+    recorder: Option<Recorder>,
+    buf: Vec<u8>,
+}
+
+impl Client {
+    pub fn bind(addr: SocketAddr) -> io::Result<Self> {
+        let poll = Poll::new()?;
+        let mut socket = UdpSocket::bind(addr)?;
+
+        poll.registry()
+            .register(&mut socket, UDP_SOCKET, Interest::READABLE)?;
+
+        Ok(Client {
+            poll,
+            events: Events::with_capacity(1),
+            socket,
+            session: None,
+            sequence: 0,
+            last_heartbeat: Instant::now(),
+            last_heard: Instant::now(),
+            timeout: DEFAULT_TIMEOUT,
+            last_received: None,
+            recorder: None,
+            buf: vec![0; 1 << 16],
+        })
+    }
+
+    /// Rebinds the socket to the unspecified address of the family of
+    /// `server_address`, on the same port, when it doesn't match, so an IPv4
+    /// bind address doesn't keep IPv6 listeners out.
+    fn match_family(&mut self, server_address: SocketAddr) -> io::Result<()> {
+        let local_address = self.socket.local_addr()?;
+        if local_address.is_ipv4() == server_address.is_ipv4() {
+            return Ok(());
+        }
+        let ip: IpAddr = if server_address.is_ipv4() {
+            Ipv4Addr::UNSPECIFIED.into()
+        } else {
+            Ipv6Addr::UNSPECIFIED.into()
+        };
+        let mut socket = UdpSocket::bind(SocketAddr::new(ip, local_address.port()))
+            .or_else(|_| UdpSocket::bind(SocketAddr::new(ip, 0)))?;
+        self.poll.registry().deregister(&mut self.socket)?;
+        self.poll
+            .registry()
+            .register(&mut socket, UDP_SOCKET, Interest::READABLE)?;
+        self.socket = socket;
+        Ok(())
+    }
+
+    pub fn session(&self) -> Option<&Session> {
+        self.session.as_ref()
+    }
+
+    pub fn is_connected(&self) -> bool {
+        self.session.is_some()
+    }
+
+    /// Whether the listener answered recently enough for the session to be
+    /// trusted, see [`Client::keep_alive`].
+    pub fn is_alive(&self) -> bool {
+        self.is_connected() && self.last_heard.elapsed() < self.timeout
+    }
+
+    /// When the listener last sent anything for this session.
+    pub fn last_heard(&self) -> Option<Instant> {
+        self.session.map(|_| self.last_heard)
+    }
+
+    /// Sets how long the listener may stay silent before the session is
+    /// dropped, [`DEFAULT_TIMEOUT`] unless set.
+    pub fn set_timeout(&mut self, timeout: Duration) {
+        self.timeout = timeout;
+    }
+
+    /// Records every event sent from now on, along with the ids the listener
+    /// gives to pads, to be replayed with [`crate::recording::replay`].
+    pub fn set_recorder(&mut self, recorder: Option<Recorder>) {
+        self.recorder = recorder;
+    }
+
+    /// Performs the hello/welcome handshake with the listener at
+    /// `server_address`, offering `capabilities`.
+    pub fn connect(
+        &mut self,
+        server_address: SocketAddr,
+        capabilities: Capabilities,
+    ) -> anyhow::Result<Session> {
+        self.disconnect();
+        self.match_family(server_address)?;
+
+        let id = new_session_id();
+        self.sequence = 0;
+        self.last_received = None;
+        self.send_to(
+            IolEvent::Hello {
+                version: PROTOCOL_VERSION,
+                capabilities,
+            },
+            server_address,
+            id,
+        )?;
+
+        let reply = self.wait_for(server_address, id, |event| {
+            matches!(event, IolEvent::Welcome { .. } | IolEvent::Rejected { .. })
+        })?;
+
+        match reply {
+            IolEvent::Welcome {
+                version,
+                capabilities,
+            } => {
+                let session = Session {
+                    id,
+                    server_address,
+                    version,
+                    capabilities,
+                };
+                self.session = Some(session);
+                Ok(session)
+            }
+            IolEvent::Rejected { version, reason } => bail!(
+                "listener rejected the connection ({:?}), it speaks protocol version {} and we speak {}",
+                reason,
+                version,
+                PROTOCOL_VERSION
+            ),
+            _ => unreachable!(),
+        }
+    }
+
+    /// Tells the listener to release everything held by this client.
+    pub fn disconnect(&mut self) {
+        if let Some(session) = self.session.take() {
+            self.send_to(IolEvent::Disconnect, session.server_address, session.id)
+                .ok();
+        }
+    }
+
+    /// Asks the listener for a virtual pad mirroring the physical controller
+    /// `which`, called `name`, and returns the network id it was given. A
+    /// `target` of `None` lets the listener pick the kind of pad.
+    pub fn register_device(
+        &mut self,
+        which: u32,
+        target: Option<PadTarget>,
+        name: &str,
+    ) -> anyhow::Result<u32> {
+        let session = self
+            .session
+            .ok_or_else(|| anyhow!("not connected to a listener"))?;
+
+        self.send_to(
+            IolEvent::PhysicalDeviceAdded {
+                which,
+                target,
+                name: name.to_owned(),
+            },
+            session.server_address,
+            session.id,
+        )?;
+
+        let reply = self.wait_for(session.server_address, session.id, |event| {
+            matches!(event, IolEvent::VirtualDeviceAdded { which: w, .. } if *w == which)
+                || matches!(
+                    event,
+                    IolEvent::Rejected {
+                        reason: RejectReason::PadLimitReached,
+                        ..
+                    }
+                )
+        })?;
+
+        match reply {
+            IolEvent::VirtualDeviceAdded { id, .. } => {
+                self.record(&reply);
+                Ok(id)
+            }
+            IolEvent::Rejected { .. } => {
+                bail!("the listener has as many pads plugged in as it allows")
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    /// Sends `event` to the connected listener, doing nothing while
+    /// disconnected.
+    pub fn send(&mut self, event: IolEvent) -> io::Result<()> {
+        match self.session {
+            Some(session) => self.send_to(event, session.server_address, session.id),
+            None => Ok(()),
+        }
+    }
+
+    /// Returns the next event the listener sent this session, without
+    /// blocking. Packets older than one already returned are dropped.
+    pub fn try_recv(&mut self) -> io::Result<Option<IolEvent>> {
+        let Some(session) = self.session else {
+            return Ok(None);
+        };
+
+        loop {
+            let (packet_size, source_address) = match self.socket.recv_from(&mut self.buf) {
+                Ok(received) => received,
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => return Ok(None),
+                Err(e) => return Err(e),
+            };
+            if source_address != session.server_address {
+                continue;
+            }
+
+            match from_bytes::<Envelope>(&self.buf[..packet_size]) {
+                Ok(envelope) if envelope.session == session.id => {
+                    self.last_heard = Instant::now();
+                    if matches!(self.last_received, Some(last) if envelope.sequence <= last) {
+                        continue;
+                    }
+                    self.last_received = Some(envelope.sequence);
+                    return Ok(Some(envelope.event));
+                }
+                Ok(_) => {}
+                Err(e) => warn!("Dropping malformed packet: {}", e),
+            }
+        }
+    }
+
+    /// Sends a heartbeat every second, or three times per timeout if that is
+    /// shorter, call it regularly to keep the session
+    /// alive on the listener. The listener echoes them, and once it has been
+    /// silent for longer than the timeout the session is dropped, as it has
+    /// restarted, dropped this client or gone away.
+    pub fn keep_alive(&mut self) -> io::Result<()> {
+        let Some(session) = self.session else {
+            return Ok(());
+        };
+        if !self.is_alive() {
+            warn!(
+                "No answer from {} for {:?}, dropping the session",
+                session.server_address, self.timeout
+            );
+            self.session = None;
+            return Ok(());
+        }
+        if self.last_heartbeat.elapsed() >= HEARTBEAT_INTERVAL.min(self.timeout / 3) {
+            self.last_heartbeat = Instant::now();
+            self.send(IolEvent::Heartbeat)?;
+        }
+        Ok(())
+    }
+
+    fn send_to(&mut self, event: IolEvent, address: SocketAddr, session: u32) -> io::Result<()> {
+        if !matches!(event, IolEvent::Heartbeat) {
+            self.record(&event);
+        }
+        let envelope = Envelope::new(session, self.sequence, event);
+        self.sequence += 1;
+        let serialized = to_allocvec(&envelope).unwrap();
+        self.socket.send_to(serialized.as_slice(), address)?;
+        Ok(())
+    }
+
+    /// Writes `event` to the recording, if any. The recording stops on the
+    /// first error rather than failing the session.
+    fn record(&mut self, event: &IolEvent) {
+        let Some(recorder) = self.recorder.as_mut() else {
+            return;
+        };
+        let recorded = match event {
+            IolEvent::Disconnect => recorder.record(event).and_then(|_| recorder.flush()),
+            _ => recorder.record(event),
+        };
+        if let Err(e) = recorded {
+            warn!("Stopping the recording: {}", e);
+            self.recorder = None;
+        }
+    }
+
+    /// Blocks until `server_address` sends an event for `session` accepted by
+    /// `matches`, discarding anything else it receives in the meantime.
+    fn wait_for(
+        &mut self,
+        server_address: SocketAddr,
+        session: u32,
+        matches: impl Fn(&IolEvent) -> bool,
+    ) -> anyhow::Result<IolEvent> {
+        let deadline = Instant::now() + REPLY_TIMEOUT;
+
+        loop {
+            loop {
+                match self.socket.recv_from(&mut self.buf) {
+                    Ok((packet_size, source_address)) => {
+                        if source_address != server_address {
+                            continue;
+                        }
+                        match from_bytes::<Envelope>(&self.buf[..packet_size]) {
+                            Ok(envelope) if envelope.session == session => {
+                                self.last_heard = Instant::now();
+                                if matches(&envelope.event) {
+                                    return Ok(envelope.event);
+                                }
+                            }
+                            Ok(_) => {}
+                            Err(e) => warn!("Dropping malformed packet: {}", e),
+                        }
+                    }
+                    Err(e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                    Err(e) => return Err(e.into()),
+                }
+            }
+
+            let now = Instant::now();
+            if now >= deadline {
+                bail!("timed out waiting for a reply from {}", server_address);
+            }
+
+            if let Err(err) = self.poll.poll(&mut self.events, Some(deadline - now)) {
+                if err.kind() != io::ErrorKind::Interrupted {
+                    return Err(err.into());
+                }
+            }
+        }
+    }
+}
+
+/// Parses the address of a listener: an IP address, a host name or a
+/// `name.local` resolved over mDNS, followed by `:port` unless it is the
+/// default one. IPv6 addresses with a port go in brackets, as in
+/// `[::1]:4863`.
+pub fn resolve_address(address: &str) -> anyhow::Result<SocketAddr> {
+    let address = address.trim();
+    if address.is_empty() {
+        bail!("no server address given");
+    }
+    if let Ok(address) = address.parse() {
+        return Ok(address);
+    }
+    let unbracketed = address.trim_start_matches('[').trim_end_matches(']');
+    if let Ok(ip) = unbracketed.parse::<IpAddr>() {
+        return Ok(SocketAddr::new(ip, DEFAULT_PORT));
+    }
+
+    let (host, port) = match address.rsplit_once(':') {
+        Some((host, port)) if !host.contains(':') => {
+            let port = port
+                .parse()
+                .with_context(|| format!("invalid port {:?}", port))?;
+            (host, port)
+        }
+        Some(_) => bail!(
+            "put IPv6 addresses in brackets, as in [::1]:{}",
+            DEFAULT_PORT
+        ),
+        None => (address, DEFAULT_PORT),
+    };
+    if host.is_empty() {
+        bail!("no host name in {:?}", address);
+    }
+
+    let mut mdns_error = None;
+    if host
+        .trim_end_matches('.')
+        .to_ascii_lowercase()
+        .ends_with(".local")
+    {
+        match mdns::resolve(host.trim_end_matches('.'), MDNS_TIMEOUT) {
+            Ok(ip) => return Ok(SocketAddr::new(ip.into(), port)),
+            // The system resolver may know about it still.
+            Err(e) => mdns_error = Some(e),
+        }
+    }
+    let resolved = (host, port)
+        .to_socket_addrs()
+        .map_err(anyhow::Error::from)
+        .and_then(|mut addresses| {
+            addresses
+                .next()
+                .ok_or_else(|| anyhow!("no address found for {}", host))
+        });
+    match (resolved, mdns_error) {
+        (Ok(address), _) => Ok(address),
+        (Err(e), Some(mdns_error)) => Err(e.context(mdns_error)),
+        (Err(e), None) => Err(e.context(format!("unable to resolve {}", host))),
+    }
+}
+
+/// Picks a session id that is unlikely to repeat across reconnects, without
+/// pulling in a random number generator.
+fn new_session_id() -> u32 {
+    let now = chrono::Utc::now();
+    (now.timestamp_subsec_nanos() ^ now.timestamp() as u32) ^ process::id().rotate_left(16)
+}
@@ -0,0 +1,251 @@
+//! Settings of `iol-listen` and `iol-broadcast`, read from TOML files in
+//! layers: the per-user file (see [`user_config_path`]), then `iol.toml` in
+//! the working directory, then the file given with `--config`. Each layer
+//! only overrides what it sets, and command line flags override them all.
+//!
+//! ```toml
+//! [listen]
+//! port = 4863
+//! backend = "uinput"
+//! profile = "profiles/hitbox.toml"
+//! client_timeout = 3.0
+//! target = "DualShock4"
+//! socd = { horizontal = "neutral", vertical = "up-priority" }
+//!
+//! [[listen.devices]]
+//! name = "PS4 Controller"
+//! profile = "profiles/southpaw.toml"
+//!
+//! [broadcast]
+//! bind = "0.0.0.0:5864"
+//! server = "192.168.1.12:4863"
+//! profile = "profiles/hitbox.toml"
+//! log_level = "debug"
+//! ```
+//!
+//! Relative paths are relative to the working directory.
+
+use std::{
+    env, fmt, fs,
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+    path::{Path, PathBuf},
+    str::FromStr,
+    time::Duration,
+};
+
+use anyhow::{bail, Context};
+use serde::Deserialize;
+
+use crate::{listener::DEFAULT_CLIENT_TIMEOUT, socd::Socd, PadTarget};
+
+/// The port `iol-listen` listens on unless told otherwise.
+pub const DEFAULT_PORT: u16 = 4863;
+
+/// Read from the working directory, on top of the per-user file.
+pub const LOCAL_CONFIG: &str = "iol.toml";
+
+/// Where the output devices of `iol-listen` come from. Only the ones
+/// compiled in are available.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Backend {
+    Vigem,
+    Uinput,
+}
+
+impl FromStr for Backend {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "vigem" => Ok(Backend::Vigem),
+            "uinput" => Ok(Backend::Uinput),
+            _ => Err(format!("unknown backend {:?}, expected vigem or uinput", s)),
+        }
+    }
+}
+
+impl fmt::Display for Backend {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Backend::Vigem => f.write_str("vigem"),
+            Backend::Uinput => f.write_str("uinput"),
+        }
+    }
+}
+
+/// Settings of the pads of a kind of controller on the listener.
+#[derive(Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct DeviceConfig {
+    /// The controller's name as SDL2 reports it on the broadcaster, which
+    /// lists it when the controller is opened.
+    pub name: String,
+    pub profile: Option<PathBuf>,
+    pub socd: Option<Socd>,
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct ListenConfig {
+    pub bind: IpAddr,
+    pub port: u16,
+    /// Shown to broadcasters discovering listeners, the host name by default.
+    pub name: Option<String>,
+    /// Whether to advertise the listener over mDNS, see [`crate::mdns`].
+    pub mdns: bool,
+    /// `None` picks whichever backend was compiled in.
+    pub backend: Option<Backend>,
+    /// An `env_logger` filter, e.g. `debug` or `iol::listener=debug`.
+    pub log_level: Option<String>,
+    /// Remaps every pad, see [`crate::profile`].
+    pub profile: Option<PathBuf>,
+    /// In seconds, see [`crate::listener::Listener::set_client_timeout`].
+    pub client_timeout: f64,
+    /// What to plug in for clients that don't ask for a kind of pad.
+    pub target: PadTarget,
+    pub socd: Socd,
+    /// The most pads plugged in at once, no limit by default.
+    pub max_pads: Option<u32>,
+    pub devices: Vec<DeviceConfig>,
+}
+
+impl Default for ListenConfig {
+    fn default() -> Self {
+        ListenConfig {
+            bind: IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+            port: DEFAULT_PORT,
+            name: None,
+            mdns: true,
+            backend: None,
+            log_level: None,
+            profile: None,
+            client_timeout: DEFAULT_CLIENT_TIMEOUT.as_secs_f64(),
+            target: PadTarget::default(),
+            socd: Socd::default(),
+            max_pads: None,
+            devices: vec![],
+        }
+    }
+}
+
+impl ListenConfig {
+    /// [`ListenConfig::client_timeout`] as a duration, which must be above 0.
+    pub fn client_timeout_duration(&self) -> anyhow::Result<Duration> {
+        if self.client_timeout.is_nan() || self.client_timeout <= 0.0 {
+            bail!("the client timeout must be a positive number of seconds");
+        }
+        Duration::try_from_secs_f64(self.client_timeout).context("the client timeout is too long")
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct BroadcastConfig {
+    pub bind: SocketAddr,
+    /// The listener filled in on startup, an address or a host name, see
+    /// [`crate::client::resolve_address`].
+    pub server: String,
+    /// An `env_logger` filter, e.g. `debug` or `iol::client=debug`.
+    pub log_level: Option<String>,
+    /// Where to look for the profiles offered for each controller.
+    pub profile_dir: PathBuf,
+    /// Picked for every controller until another one is chosen.
+    pub profile: Option<PathBuf>,
+    /// Full controller snapshots per second, 0 disables them.
+    pub snapshot_rate: u32,
+    /// Motion updates per second, 0 disables them.
+    pub motion_rate: u32,
+}
+
+impl Default for BroadcastConfig {
+    fn default() -> Self {
+        BroadcastConfig {
+            bind: SocketAddr::new(IpAddr::V4(Ipv4Addr::UNSPECIFIED), 5864),
+            server: format!("192.168.1.12:{}", DEFAULT_PORT),
+            log_level: None,
+            profile_dir: PathBuf::from("profiles"),
+            profile: None,
+            snapshot_rate: 10,
+            motion_rate: 60,
+        }
+    }
+}
+
+#[derive(Deserialize, Debug, Clone, Default, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub listen: ListenConfig,
+    pub broadcast: BroadcastConfig,
+}
+
+impl Config {
+    /// Reads every layer that exists, `extra` being the file given on the
+    /// command line, which must exist.
+    pub fn load(extra: Option<&Path>) -> anyhow::Result<Self> {
+        let mut layers = vec![];
+        if let Some(path) = user_config_path().filter(|path| path.is_file()) {
+            layers.push(read(&path)?);
+        }
+        if Path::new(LOCAL_CONFIG).is_file() {
+            layers.push(read(Path::new(LOCAL_CONFIG))?);
+        }
+        if let Some(path) = extra {
+            layers.push(read(path)?);
+        }
+        Self::from_layers(layers)
+    }
+
+    /// Merges `layers`, later ones winning, on top of the defaults.
+    pub fn from_layers(layers: impl IntoIterator<Item = toml::Table>) -> anyhow::Result<Self> {
+        let mut merged = toml::Table::new();
+        for layer in layers {
+            merge(&mut merged, layer);
+        }
+        toml::Value::Table(merged)
+            .try_into()
+            .context("invalid configuration")
+    }
+}
+
+/// `config.toml` in the `iol` directory of the user's configuration
+/// directory, e.g. `~/.config/iol/config.toml` or
+/// `%APPDATA%\iol\config.toml`.
+pub fn user_config_path() -> Option<PathBuf> {
+    let dir = if cfg!(windows) {
+        env::var_os("APPDATA").map(PathBuf::from)
+    } else {
+        env::var_os("XDG_CONFIG_HOME")
+            .map(PathBuf::from)
+            .or_else(|| env::var_os("HOME").map(|home| Path::new(&home).join(".config")))
+    };
+    Some(dir?.join("iol").join("config.toml"))
+}
+
+/// Sets up logging from `RUST_LOG`, overridden by `level` if given.
+pub fn init_logging(level: Option<&str>) {
+    let mut builder = env_logger::Builder::from_default_env();
+    if let Some(level) = level {
+        builder.parse_filters(level);
+    }
+    builder.init();
+}
+
+fn read(path: &Path) -> anyhow::Result<toml::Table> {
+    let text =
+        fs::read_to_string(path).with_context(|| format!("unable to read {}", path.display()))?;
+    text.parse()
+        .with_context(|| format!("unable to parse {}", path.display()))
+}
+
+/// Copies `layer` over `base`, descending into tables both have.
+fn merge(base: &mut toml::Table, layer: toml::Table) {
+    for (key, value) in layer {
+        match (base.get_mut(&key), value) {
+            (Some(toml::Value::Table(base)), toml::Value::Table(layer)) => merge(base, layer),
+            (_, value) => {
+                base.insert(key, value);
+            }
+        }
+    }
+}
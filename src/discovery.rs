@@ -0,0 +1,121 @@
+//! Finding listeners on the local network: a broadcaster sends a
+//! [`IolEvent::DiscoveryProbe`] to the broadcast address and every listener
+//! on that port answers with a [`IolEvent::DiscoveryReply`].
+
+use std::{
+    collections::HashMap,
+    io,
+    net::{Ipv4Addr, SocketAddr},
+    time::{Duration, Instant},
+};
+
+use log::warn;
+use mio::net::UdpSocket;
+use postcard::{from_bytes, to_allocvec};
+
+use crate::{config::DEFAULT_PORT, Capabilities, Envelope, IolEvent, PROTOCOL_VERSION};
+
+/// How long a listener stays listed without answering a probe.
+const EXPIRY: Duration = Duration::from_secs(6);
+
+/// A listener that answered a probe.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Discovered {
+    pub address: SocketAddr,
+    pub name: String,
+    pub version: u16,
+    pub capabilities: Capabilities,
+    pub clients: u32,
+    pub pads: u32,
+    pub capacity: Option<u32>,
+    pub last_seen: Instant,
+}
+
+impl Discovered {
+    /// Whether a broadcaster of this build can connect to it.
+    pub fn is_compatible(&self) -> bool {
+        self.version == PROTOCOL_VERSION
+    }
+}
+
+/// Probes for listeners and keeps track of the ones that answered.
+pub struct Discovery {
+    socket: UdpSocket,
+    found: HashMap<SocketAddr, Discovered>,
+    buf: Vec<u8>,
+}
+
+impl Discovery {
+    pub fn bind(addr: SocketAddr) -> io::Result<Self> {
+        let socket = UdpSocket::bind(addr)?;
+        socket.set_broadcast(true)?;
+        Ok(Discovery {
+            socket,
+            found: HashMap::new(),
+            buf: vec![0; 1 << 16],
+        })
+    }
+
+    /// Probes the listeners on the default port of the local network.
+    pub fn probe_lan(&mut self) -> io::Result<()> {
+        self.probe(SocketAddr::new(Ipv4Addr::BROADCAST.into(), DEFAULT_PORT))
+    }
+
+    /// Probes `target`, a listener or a broadcast address.
+    pub fn probe(&mut self, target: SocketAddr) -> io::Result<()> {
+        let envelope = Envelope::new(0, 0, IolEvent::DiscoveryProbe);
+        self.socket
+            .send_to(&to_allocvec(&envelope).unwrap(), target)?;
+        Ok(())
+    }
+
+    /// Takes in the replies received so far, without blocking.
+    pub fn poll(&mut self) -> io::Result<()> {
+        loop {
+            let (packet_size, address) = match self.socket.recv_from(&mut self.buf) {
+                Ok(received) => received,
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => return Ok(()),
+                Err(e) => return Err(e),
+            };
+            match from_bytes::<Envelope>(&self.buf[..packet_size]) {
+                Ok(Envelope {
+                    event:
+                        IolEvent::DiscoveryReply {
+                            name,
+                            version,
+                            capabilities,
+                            clients,
+                            pads,
+                            capacity,
+                        },
+                    ..
+                }) => {
+                    let listener = Discovered {
+                        address,
+                        name,
+                        version,
+                        capabilities,
+                        clients,
+                        pads,
+                        capacity,
+                        last_seen: Instant::now(),
+                    };
+                    self.found.insert(address, listener);
+                }
+                Ok(_) => {}
+                Err(e) => warn!("Dropping malformed packet from {}: {}", address, e),
+            }
+        }
+    }
+
+    /// The listeners that answered recently, by name.
+    pub fn listeners(&self) -> Vec<&Discovered> {
+        let mut listeners: Vec<_> = self
+            .found
+            .values()
+            .filter(|listener| listener.last_seen.elapsed() < EXPIRY)
+            .collect();
+        listeners.sort_by(|a, b| (&a.name, a.address).cmp(&(&b.name, b.address)));
+        listeners
+    }
+}
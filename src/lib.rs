@@ -1,12 +1,113 @@
 use sdl2::{
     controller::{Axis, Button},
     keyboard::Scancode,
+    mouse::MouseButton,
 };
 use serde::{Deserialize, Serialize};
 
+pub mod analog;
+pub mod backend;
+pub mod client;
+pub mod config;
+pub mod discovery;
+pub mod listener;
+pub mod macros;
+pub mod mdns;
+pub mod mock;
+pub mod profile;
+pub mod recording;
+pub mod socd;
+
+#[cfg(feature = "vigem")]
+pub mod sendinput;
+#[cfg(feature = "uinput")]
+pub mod uinput;
 #[cfg(feature = "vigem")]
 pub mod vigem;
 
+/// Version of the wire format spoken by this build.
+///
+/// Bump it whenever [`Envelope`] or [`IolEvent`] changes in a way older
+/// builds can't decode.
+pub const PROTOCOL_VERSION: u16 = 4;
+
+/// Optional features a peer supports, exchanged during the handshake.
+///
+/// The listener accepts the intersection of both sides, so a peer missing a
+/// feature only loses that feature instead of the whole session.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Capabilities(pub u32);
+
+impl Capabilities {
+    pub const GAMEPAD: Capabilities = Capabilities(1 << 0);
+    pub const KEYBOARD: Capabilities = Capabilities(1 << 1);
+    pub const MOUSE: Capabilities = Capabilities(1 << 2);
+    /// Rumble and other feedback sent from the listener back to the pads.
+    pub const FEEDBACK: Capabilities = Capabilities(1 << 3);
+    /// Gyroscope and accelerometer readings.
+    pub const MOTION: Capabilities = Capabilities(1 << 4);
+
+    pub const ALL: Capabilities = Capabilities(
+        Self::GAMEPAD.0 | Self::KEYBOARD.0 | Self::MOUSE.0 | Self::FEEDBACK.0 | Self::MOTION.0,
+    );
+
+    pub fn contains(self, other: Capabilities) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    pub fn intersection(self, other: Capabilities) -> Capabilities {
+        Capabilities(self.0 & other.0)
+    }
+
+    pub fn union(self, other: Capabilities) -> Capabilities {
+        Capabilities(self.0 | other.0)
+    }
+
+    pub fn difference(self, other: Capabilities) -> Capabilities {
+        Capabilities(self.0 & !other.0)
+    }
+
+    pub fn is_empty(self) -> bool {
+        self.0 == 0
+    }
+}
+
+/// The kind of controller a virtual pad presents itself as to the host.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum PadTarget {
+    #[default]
+    Xbox360,
+    DualShock4,
+}
+
+impl std::str::FromStr for PadTarget {
+    type Err = String;
+
+    /// Parses the serialized names, ignoring case.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "xbox360" => Ok(PadTarget::Xbox360),
+            "dualshock4" => Ok(PadTarget::DualShock4),
+            _ => Err(format!(
+                "unknown pad {:?}, expected Xbox360 or DualShock4",
+                s
+            )),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RejectReason {
+    VersionMismatch,
+    NoCommonCapabilities,
+    /// A pad was refused as the listener has as many plugged in as it allows.
+    PadLimitReached,
+}
+
+/// Every message exchanged between broadcaster and listener.
+///
+/// Variants are encoded by index, so new ones must only ever be appended and
+/// the handshake variants must keep their layout across protocol versions.
 #[derive(Serialize, Deserialize, Debug)]
 pub enum IolEvent {
     ButtonUp {
@@ -36,6 +137,11 @@ pub enum IolEvent {
     },
     PhysicalDeviceAdded {
         which: u32,
+        /// What to plug in for this device, `None` leaves it to the listener.
+        target: Option<PadTarget>,
+        /// The controller's name as SDL2 reports it, which per-device settings
+        /// of the listener are matched against.
+        name: String,
     },
     PhysicalDeviceRemoved {
         id: u32,
@@ -44,6 +150,207 @@ pub enum IolEvent {
         id: u32,
         which: u32,
     },
+    /// Sent by the broadcaster when it stops forwarding, so the listener can
+    /// release whatever that client was still holding.
+    Disconnect,
+    /// Relative pointer motion, in pixels.
+    MouseMotion {
+        dx: i32,
+        dy: i32,
+    },
+    MouseButtonDown {
+        #[serde(with = "sdl2_mouse_button_serde")]
+        button: MouseButton,
+    },
+    MouseButtonUp {
+        #[serde(with = "sdl2_mouse_button_serde")]
+        button: MouseButton,
+    },
+    /// Scroll amount in wheel notches, positive `y` is away from the user.
+    MouseWheel {
+        x: i32,
+        y: i32,
+    },
+    /// First message of a session, sent by the broadcaster.
+    Hello {
+        version: u16,
+        capabilities: Capabilities,
+    },
+    /// The listener accepted the session with the negotiated capabilities.
+    Welcome {
+        version: u16,
+        capabilities: Capabilities,
+    },
+    Rejected {
+        version: u16,
+        reason: RejectReason,
+    },
+    /// Every button and axis of a device at once, sent periodically so a lost
+    /// delta doesn't leave the virtual pad out of sync for long.
+    ControllerState {
+        id: u32,
+        state: ControllerState,
+    },
+    /// Sent by the broadcaster every second and echoed by the listener, so
+    /// each side can tell an idle peer from a dead one.
+    Heartbeat,
+    /// Sent by the listener when the host rumbles a virtual pad. Motor speeds
+    /// follow SDL2, `duration` is in milliseconds and 0 stops the motors.
+    Rumble {
+        id: u32,
+        low: u16,
+        high: u16,
+        duration: u32,
+    },
+    /// Sent by the listener when the host assigns a virtual pad a player
+    /// slot, i.e. the XInput LED number, counting from 0.
+    PlayerSlot {
+        id: u32,
+        slot: u8,
+    },
+    /// Latest motion sensor readings of a device, in SDL2's units and axes:
+    /// rad/s for the gyroscope and m/s² for the accelerometer. `timestamp` is
+    /// when the controller took them, in microseconds.
+    Motion {
+        id: u32,
+        timestamp: u64,
+        gyro: [f32; 3],
+        accel: [f32; 3],
+    },
+    /// A finger touching, moving on or leaving a controller's touchpad.
+    Touch {
+        id: u32,
+        point: TouchPoint,
+    },
+    /// Sent by a broadcaster looking for listeners, usually to the broadcast
+    /// address. Listeners answer it whatever the protocol version, so
+    /// broadcasters can list the ones they are too old or too new for
+    /// instead of not seeing them at all. Variants are only ever appended,
+    /// which keeps both of these decodable across versions.
+    DiscoveryProbe,
+    /// A listener's answer to a [`IolEvent::DiscoveryProbe`].
+    DiscoveryReply {
+        name: String,
+        version: u16,
+        /// What the listener's backend can drive.
+        capabilities: Capabilities,
+        /// Broadcasters currently connected.
+        clients: u32,
+        /// Virtual pads currently plugged in.
+        pads: u32,
+        /// The most pads the listener plugs in at once, `None` for no limit.
+        capacity: Option<u32>,
+    },
+}
+
+/// One finger on a controller touchpad, as reported by SDL2.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct TouchPoint {
+    pub touchpad: u8,
+    pub finger: u8,
+    /// False once the finger was lifted.
+    pub touching: bool,
+    /// From 0 on the left to 1 on the right.
+    pub x: f32,
+    /// From 0 at the top to 1 at the bottom.
+    pub y: f32,
+    pub pressure: f32,
+}
+
+/// A full snapshot of a controller's buttons and axes.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ControllerState {
+    /// One bit per button, indexed by its SDL2 button value.
+    pub buttons: u32,
+    /// Indexed by SDL2 axis value, with the same signs as
+    /// [`IolEvent::AxisMotion`].
+    pub axes: [i16; 6],
+}
+
+impl ControllerState {
+    pub const BUTTONS: [Button; 21] = [
+        Button::A,
+        Button::B,
+        Button::X,
+        Button::Y,
+        Button::Back,
+        Button::Guide,
+        Button::Start,
+        Button::LeftStick,
+        Button::RightStick,
+        Button::LeftShoulder,
+        Button::RightShoulder,
+        Button::DPadUp,
+        Button::DPadDown,
+        Button::DPadLeft,
+        Button::DPadRight,
+        Button::Misc1,
+        Button::Paddle1,
+        Button::Paddle2,
+        Button::Paddle3,
+        Button::Paddle4,
+        Button::Touchpad,
+    ];
+
+    pub const AXES: [Axis; 6] = [
+        Axis::LeftX,
+        Axis::LeftY,
+        Axis::RightX,
+        Axis::RightY,
+        Axis::TriggerLeft,
+        Axis::TriggerRight,
+    ];
+
+    pub fn button(&self, button: Button) -> bool {
+        self.buttons & (1 << button as u32) != 0
+    }
+
+    pub fn set_button(&mut self, button: Button, value: bool) {
+        if value {
+            self.buttons |= 1 << button as u32;
+        } else {
+            self.buttons &= !(1 << button as u32);
+        }
+    }
+
+    pub fn axis(&self, axis: Axis) -> i16 {
+        self.axes[axis as usize]
+    }
+
+    pub fn set_axis(&mut self, axis: Axis, value: i16) {
+        self.axes[axis as usize] = value;
+    }
+}
+
+/// The header every datagram is wrapped in.
+///
+/// UDP may reorder or duplicate datagrams, so receivers use `session` and
+/// `sequence` to drop anything older than what they already applied.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct Envelope {
+    /// Kept first so it can be read even when the rest doesn't decode.
+    pub version: u16,
+    /// Chosen by the broadcaster on every connect, so late packets from an
+    /// earlier session from the same address are told apart.
+    pub session: u32,
+    /// Incremented for every datagram the sender emits within a session.
+    pub sequence: u64,
+    /// The sender's clock when the datagram was sent, in microseconds since
+    /// the Unix epoch.
+    pub timestamp: i64,
+    pub event: IolEvent,
+}
+
+impl Envelope {
+    pub fn new(session: u32, sequence: u64, event: IolEvent) -> Self {
+        Envelope {
+            version: PROTOCOL_VERSION,
+            session,
+            sequence,
+            timestamp: chrono::Utc::now().timestamp_micros(),
+            event,
+        }
+    }
 }
 
 pub(crate) mod sdl2_scancode_serde {
@@ -181,3 +488,48 @@ pub(crate) mod sdl2_axis_serde {
         deserializer.deserialize_str(AxisVisitor)
     }
 }
+
+pub(crate) mod sdl2_mouse_button_serde {
+    use std::fmt;
+
+    use sdl2::mouse::MouseButton;
+    use serde::{
+        de::{self, Visitor},
+        Deserializer, Serializer,
+    };
+
+    pub fn serialize<S>(button: &MouseButton, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        serializer.serialize_u8(*button as u8)
+    }
+    pub struct MouseButtonVisitor;
+
+    impl<'de> Visitor<'de> for MouseButtonVisitor {
+        type Value = MouseButton;
+
+        fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+            formatter.write_str("a valid SDL2 mouse button index")
+        }
+
+        fn visit_u64<E>(self, value: u64) -> Result<Self::Value, E>
+        where
+            E: de::Error,
+        {
+            match u8::try_from(value).map(MouseButton::from_ll) {
+                Ok(MouseButton::Unknown) | Err(_) => {
+                    Err(E::custom("mouse button not recognized by SDL2"))
+                }
+                Ok(button) => Ok(button),
+            }
+        }
+    }
+
+    pub fn deserialize<'de, D>(deserializer: D) -> Result<MouseButton, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        deserializer.deserialize_u8(MouseButtonVisitor)
+    }
+}
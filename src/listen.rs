@@ -1,119 +1,164 @@
-use log::warn;
-use mio::{Events, Interest, Poll, Token};
+use clap::Parser;
+use iol::{
+    backend::OutputBackend,
+    config::{self, Backend, Config},
+    listener::Listener,
+    mdns::{Responder, Service},
+    profile::ProfileFile,
+    PadTarget,
+};
 use std::{
-    io,
-    net::{IpAddr, Ipv4Addr, SocketAddr},
+    net::{IpAddr, SocketAddr},
+    path::PathBuf,
 };
 
-const UDP_SOCKET: Token = Token(0);
-const PORT: u16 = 4863;
+/// Plugs in virtual devices driven by iol-broadcast over the network.
+///
+/// Flags override the config files, see `iol::config`.
+#[derive(Parser)]
+#[command(version)]
+struct Args {
+    /// Remap every pad through this profile.
+    profile: Option<PathBuf>,
+    /// Config file read on top of the per-user one and ./iol.toml.
+    #[arg(long, value_name = "FILE")]
+    config: Option<PathBuf>,
+    /// Address to listen on.
+    #[arg(long)]
+    bind: Option<IpAddr>,
+    #[arg(short, long)]
+    port: Option<u16>,
+    /// Shown to broadcasters discovering listeners, defaults to the host name.
+    #[arg(long)]
+    name: Option<String>,
+    /// Don't advertise the listener over mDNS.
+    #[arg(long)]
+    no_mdns: bool,
+    /// vigem or uinput, defaults to the one compiled in.
+    #[arg(long)]
+    backend: Option<Backend>,
+    /// An env_logger filter, e.g. debug, overriding RUST_LOG.
+    #[arg(long, value_name = "FILTER")]
+    log_level: Option<String>,
+    /// Seconds of silence before a client's inputs are released.
+    #[arg(long, value_name = "SECONDS")]
+    client_timeout: Option<f64>,
+    /// Xbox360 or DualShock4, for clients that don't ask for a kind of pad.
+    #[arg(long)]
+    target: Option<PadTarget>,
+    /// The most pads plugged in at once, across every broadcaster.
+    #[arg(long, value_name = "COUNT")]
+    max_pads: Option<u32>,
+}
 
-fn main() -> io::Result<()> {
-    use std::{collections::HashMap, rc::Rc};
+/// The backend used when none is asked for.
+#[cfg(feature = "vigem")]
+const DEFAULT_BACKEND: Option<Backend> = Some(Backend::Vigem);
 
-    use iol::{vigem::ViGEMState, IolEvent};
-    use mio::net::UdpSocket;
-    use postcard::{from_bytes, to_vec};
-    use vigem_client::TargetId;
+#[cfg(all(feature = "uinput", not(feature = "vigem")))]
+const DEFAULT_BACKEND: Option<Backend> = Some(Backend::Uinput);
 
-    env_logger::init();
+#[cfg(not(any(feature = "vigem", feature = "uinput")))]
+const DEFAULT_BACKEND: Option<Backend> = None;
 
-    let mut poll = Poll::new()?;
-    let mut events = Events::with_capacity(1);
-    let addr = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(0, 0, 0, 0)), PORT);
+fn create_backend(backend: Option<Backend>) -> anyhow::Result<Box<dyn OutputBackend>> {
+    match backend.or(DEFAULT_BACKEND) {
+        #[cfg(feature = "vigem")]
+        Some(Backend::Vigem) => Ok(Box::new(iol::vigem::ViGEMBackend::new()?)),
+        #[cfg(feature = "uinput")]
+        Some(Backend::Uinput) => Ok(Box::new(iol::uinput::UInputBackend::new()?)),
+        #[allow(unreachable_patterns)]
+        Some(backend) => anyhow::bail!(
+            "iol-listen was built without the {} backend, rebuild it with `--features {}`.",
+            backend,
+            backend
+        ),
+        None => anyhow::bail!(
+            "iol-listen was built without an output backend, \
+            rebuild it with `--features vigem` (Windows) or `--features uinput` (Linux)."
+        ),
+    }
+}
 
-    let mut socket = UdpSocket::bind(addr)?;
+fn main() -> anyhow::Result<()> {
+    let args = Args::parse();
+    let mut config = Config::load(args.config.as_deref())?.listen;
+    if let Some(bind) = args.bind {
+        config.bind = bind;
+    }
+    if let Some(port) = args.port {
+        config.port = port;
+    }
+    if args.name.is_some() {
+        config.name = args.name;
+    }
+    if args.no_mdns {
+        config.mdns = false;
+    }
+    if args.backend.is_some() {
+        config.backend = args.backend;
+    }
+    if args.log_level.is_some() {
+        config.log_level = args.log_level;
+    }
+    if args.profile.is_some() {
+        config.profile = args.profile;
+    }
+    if let Some(timeout) = args.client_timeout {
+        config.client_timeout = timeout;
+    }
+    if let Some(target) = args.target {
+        config.target = target;
+    }
+    if args.max_pads.is_some() {
+        config.max_pads = args.max_pads;
+    }
 
-    poll.registry().register(
-        &mut socket,
-        UDP_SOCKET,
-        Interest::WRITABLE | Interest::READABLE,
-    )?;
+    config::init_logging(config.log_level.as_deref());
 
-    println!("You can connect to the server via port {}", PORT);
+    let client_timeout = config.client_timeout_duration()?;
+    let backend = create_backend(config.backend)?;
 
-    let mut buf = [0; 1 << 16];
-    let mut controllers: HashMap<u32, ViGEMState> = HashMap::new();
-    let vigem_client = Rc::new(vigem_client::Client::connect().unwrap());
+    let addr = SocketAddr::new(config.bind, config.port);
+    let mut listener = Listener::bind(addr, backend)?;
 
-    loop {
-        if let Err(err) = poll.poll(&mut events, None) {
-            if err.kind() == io::ErrorKind::Interrupted {
-                continue;
+    listener.set_client_timeout(client_timeout);
+    listener.set_default_target(config.target);
+    listener.set_socd(config.socd);
+    listener.set_max_pads(config.max_pads);
+    if let Some(name) = config.name {
+        listener.set_name(name);
+    }
+    if config.mdns {
+        let service = Service::new(listener.name(), listener.local_addr()?.port());
+        match Responder::bind(service) {
+            Ok(responder) => {
+                println!(
+                    "Advertising {} on {}",
+                    responder.service().instance_name(),
+                    responder.service().host
+                );
+                listener.advertise(responder)?;
             }
-            return Err(err);
+            Err(e) => log::warn!("Unable to advertise over mDNS: {:#}", e),
         }
+    }
 
-        for event in events.iter() {
-            match event.token() {
-                UDP_SOCKET => loop {
-                    match socket.recv_from(&mut buf) {
-                        Ok((packet_size, source_address)) => {
-                            let event = from_bytes::<IolEvent>(&buf[..packet_size]);
-                            match event.unwrap() {
-                                // TODO: Keyboard emulation
-                                IolEvent::KeyDown { .. } => {}
-                                IolEvent::KeyUp { .. } => {}
-                                IolEvent::PhysicalDeviceAdded { which } => {
-                                    let id = controllers.len() as u32;
-                                    println!("Controller {} was added.", id);
-                                    let mut target = vigem_client::Xbox360Wired::new(
-                                        vigem_client.clone(),
-                                        TargetId::XBOX360_WIRED,
-                                    );
-                                    target.plugin().unwrap();
-                                    target.wait_ready().unwrap();
-                                    controllers.insert(id, ViGEMState::new(vigem_client.clone()));
-
-                                    let serialized =
-                                        to_vec::<IolEvent, 32>(&IolEvent::VirtualDeviceAdded {
-                                            id,
-                                            which,
-                                        })
-                                        .unwrap();
-
-                                    socket.send_to(serialized.as_slice(), source_address)?;
-                                    println!("Controller virtual device {} was added.", id)
-                                }
-                                IolEvent::PhysicalDeviceRemoved { id } => {
-                                    controllers.remove(&id);
-                                    println!("Controller {} was removed.", id);
-                                }
-                                IolEvent::ButtonDown { id, button } => {
-                                    let controller = controllers.get_mut(&id);
-                                    if let Some(controller) = controller {
-                                        controller.from_sdl2_button(button, true)
-                                    }
-                                }
-                                IolEvent::ButtonUp { id, button } => {
-                                    let controller = controllers.get_mut(&id);
-                                    if let Some(controller) = controller {
-                                        controller.from_sdl2_button(button, false);
-                                    }
-                                }
-                                IolEvent::AxisMotion {
-                                    id, axis, value, ..
-                                } => {
-                                    let controller = controllers.get_mut(&id);
-                                    if let Some(controller) = controller {
-                                        controller.from_sdl2_axis(axis, value);
-                                    }
-                                }
-                                _ => {}
-                            }
-                        }
-                        Err(e) if e.kind() == io::ErrorKind::WouldBlock => {
-                            break;
-                        }
-                        Err(e) => {
-                            return Err(e);
-                        }
-                    }
-                },
-                _ => {
-                    warn!("Got event for unexpected token: {:?}", event);
-                }
-            }
+    if let Some(path) = config.profile {
+        let profile = ProfileFile::load(path)?;
+        println!("Remapping pads with {}", profile.path().display());
+        listener.set_profile(Some(profile));
+    }
+    for device in config.devices {
+        if let Some(path) = device.profile {
+            listener.set_device_profile(&device.name, ProfileFile::load(path)?);
+        }
+        if let Some(socd) = device.socd {
+            listener.set_device_socd(&device.name, socd);
         }
     }
+
+    println!("You can connect to the server via port {}", config.port);
+
+    listener.run()
 }
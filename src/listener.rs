@@ -0,0 +1,905 @@
+use std::{
+    cell::Cell,
+    collections::{HashMap, HashSet},
+    io,
+    net::SocketAddr,
+    rc::Rc,
+    time::{Duration, Instant},
+};
+
+use log::{debug, warn};
+use mio::{net::UdpSocket, Events, Interest, Poll, Token};
+use postcard::{from_bytes, to_allocvec};
+use sdl2::{keyboard::Scancode, mouse::MouseButton};
+
+use crate::{
+    backend::{Feedback, OutputBackend, VirtualPad},
+    mdns::{self, Responder},
+    profile::{ProfileFile, RemappedPad},
+    socd::{Socd, SocdPad},
+    Capabilities, ControllerState, Envelope, IolEvent, PadTarget, RejectReason, PROTOCOL_VERSION,
+};
+
+const UDP_SOCKET: Token = Token(0);
+const MDNS_SOCKET: Token = Token(1);
+
+/// How long a client may stay silent before its inputs are released, the
+/// broadcaster sends a heartbeat every second.
+pub const DEFAULT_CLIENT_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// How often [`Listener::run`] wakes up without traffic, to pick up feedback
+/// from the backend and expire silent clients.
+const HOUSEKEEPING_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Rumble is forwarded with a bounded duration and refreshed while it lasts,
+/// so a lost stop packet can't leave a controller vibrating.
+const RUMBLE_DURATION: Duration = Duration::from_millis(1000);
+const RUMBLE_REFRESH: Duration = Duration::from_millis(500);
+
+/// How often profile files are checked for changes.
+const PROFILE_RELOAD_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Rumble currently requested by the host for a virtual pad.
+struct ActiveRumble {
+    low: u16,
+    high: u16,
+    sent: Instant,
+}
+
+/// A device whose events must be applied in order.
+///
+/// Ordering is tracked per device, so a packet for one pad that overtakes a
+/// packet for another is still applied.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+enum Stream {
+    Keyboard,
+    Mouse,
+    Pad(u32),
+    /// Plugging and unplugging the physical device `which`.
+    Device(u32),
+}
+
+impl Stream {
+    fn of(event: &IolEvent) -> Option<Stream> {
+        match *event {
+            IolEvent::KeyDown { .. } | IolEvent::KeyUp { .. } => Some(Stream::Keyboard),
+            IolEvent::MouseMotion { .. }
+            | IolEvent::MouseButtonDown { .. }
+            | IolEvent::MouseButtonUp { .. }
+            | IolEvent::MouseWheel { .. } => Some(Stream::Mouse),
+            IolEvent::ButtonDown { id, .. }
+            | IolEvent::ButtonUp { id, .. }
+            | IolEvent::AxisMotion { id, .. }
+            | IolEvent::ControllerState { id, .. }
+            | IolEvent::Motion { id, .. }
+            | IolEvent::Touch { id, .. }
+            | IolEvent::PhysicalDeviceRemoved { id } => Some(Stream::Pad(id)),
+            IolEvent::PhysicalDeviceAdded { which, .. } => Some(Stream::Device(which)),
+            _ => None,
+        }
+    }
+}
+
+/// What a single broadcaster currently owns on this listener.
+struct Client {
+    session: u32,
+    capabilities: Capabilities,
+    /// The `which` of the physical device behind each pad, by network id.
+    devices: HashMap<u32, u32>,
+    keys: HashSet<Scancode>,
+    mouse_buttons: HashSet<MouseButton>,
+    /// Sequence number of the newest packet applied on each stream.
+    sequences: HashMap<Stream, u64>,
+    last_seen: Instant,
+    /// Whether the client timed out and its inputs were released.
+    neutralized: bool,
+}
+
+impl Client {
+    fn new(session: u32, capabilities: Capabilities) -> Self {
+        Client {
+            session,
+            capabilities,
+            devices: HashMap::new(),
+            keys: HashSet::new(),
+            mouse_buttons: HashSet::new(),
+            sequences: HashMap::new(),
+            last_seen: Instant::now(),
+            neutralized: false,
+        }
+    }
+
+    /// Records `sequence` as applied on the event's stream, returning false
+    /// if the packet is a duplicate or older than one already applied.
+    fn accept(&mut self, event: &IolEvent, sequence: u64) -> bool {
+        let Some(stream) = Stream::of(event) else {
+            return true;
+        };
+        match self.sequences.get(&stream) {
+            Some(&last) if sequence <= last => false,
+            _ => {
+                self.sequences.insert(stream, sequence);
+                true
+            }
+        }
+    }
+}
+
+/// Receives [`IolEvent`]s over UDP and replays them on virtual devices
+/// created by an [`OutputBackend`].
+pub struct Listener {
+    poll: Poll,
+    events: Events,
+    socket: UdpSocket,
+    backend: Box<dyn OutputBackend>,
+    controllers: HashMap<u32, Box<dyn VirtualPad>>,
+    clients: HashMap<SocketAddr, Client>,
+    next_id: u32,
+    sequence: u64,
+    client_timeout: Duration,
+    default_target: PadTarget,
+    rumble: HashMap<u32, ActiveRumble>,
+    profile: Option<ProfileFile>,
+    /// Profiles set for controllers by name, whether any is plugged in yet or
+    /// not.
+    device_profiles: HashMap<String, ProfileFile>,
+    last_profile_check: Instant,
+    /// SOCD policy of pads without one of their own.
+    socd: Socd,
+    /// Policies set for controllers by name, whether any is plugged in yet or
+    /// not.
+    device_socd: HashMap<String, Socd>,
+    /// The policy each plugged in pad currently follows.
+    pad_socd: HashMap<u32, Rc<Cell<Socd>>>,
+    /// The name of the controller behind each plugged in pad.
+    pad_names: HashMap<u32, String>,
+    /// When a pad next asked to be ticked.
+    next_tick: Option<Instant>,
+    /// Shown to broadcasters discovering listeners.
+    name: String,
+    /// The most pads plugged in at once, if limited.
+    max_pads: Option<u32>,
+    /// Answers mDNS queries about this listener, if advertised.
+    responder: Option<Responder>,
+    buf: Vec<u8>,
+}
+
+impl Listener {
+    pub fn bind(addr: SocketAddr, backend: Box<dyn OutputBackend>) -> io::Result<Self> {
+        let poll = Poll::new()?;
+        let mut socket = UdpSocket::bind(addr)?;
+
+        poll.registry()
+            .register(&mut socket, UDP_SOCKET, Interest::READABLE)?;
+
+        Ok(Listener {
+            poll,
+            events: Events::with_capacity(2),
+            socket,
+            backend,
+            controllers: HashMap::new(),
+            clients: HashMap::new(),
+            next_id: 0,
+            sequence: 0,
+            client_timeout: DEFAULT_CLIENT_TIMEOUT,
+            default_target: PadTarget::default(),
+            rumble: HashMap::new(),
+            profile: None,
+            device_profiles: HashMap::new(),
+            last_profile_check: Instant::now(),
+            socd: Socd::default(),
+            device_socd: HashMap::new(),
+            pad_socd: HashMap::new(),
+            pad_names: HashMap::new(),
+            next_tick: None,
+            name: mdns::host_name().unwrap_or_else(|| "iol-listen".to_owned()),
+            max_pads: None,
+            responder: None,
+            buf: vec![0; 1 << 16],
+        })
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.socket.local_addr()
+    }
+
+    /// Sets how long a client may stay silent before everything it holds is
+    /// released. After twice that long it is dropped and its virtual devices
+    /// are unplugged.
+    pub fn set_client_timeout(&mut self, timeout: Duration) {
+        self.client_timeout = timeout;
+    }
+
+    /// Sets what to plug in for devices whose client doesn't ask for a
+    /// specific kind of controller.
+    pub fn set_default_target(&mut self, target: PadTarget) {
+        self.default_target = target;
+    }
+
+    /// Sets the name broadcasters see when discovering listeners, the host
+    /// name by default.
+    pub fn set_name(&mut self, name: String) {
+        self.name = name;
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Limits how many pads are plugged in at once, across every client.
+    /// Requests for more are turned down and logged.
+    pub fn set_max_pads(&mut self, max_pads: Option<u32>) {
+        self.max_pads = max_pads;
+    }
+
+    /// Answers mDNS queries with `responder` from now on, so the listener
+    /// shows up in DNS-SD browsers and its host name resolves.
+    pub fn advertise(&mut self, mut responder: Responder) -> io::Result<()> {
+        self.poll
+            .registry()
+            .register(responder.socket_mut(), MDNS_SOCKET, Interest::READABLE)?;
+        self.responder = Some(responder);
+        Ok(())
+    }
+
+    /// Remaps every pad plugged from now on through `profile`, unless
+    /// overridden with [`Listener::set_device_profile`]. Profiles are
+    /// reloaded whenever their file changes.
+    pub fn set_profile(&mut self, profile: Option<ProfileFile>) {
+        self.profile = profile;
+    }
+
+    /// Remaps pads of controllers called `name`, as SDL2 names them on the
+    /// broadcaster, through `profile` once they are plugged in.
+    pub fn set_device_profile(&mut self, name: &str, profile: ProfileFile) {
+        self.device_profiles.insert(name.to_owned(), profile);
+    }
+
+    /// Sets how opposing D-pad directions of every pad are cleaned, unless
+    /// overridden with [`Listener::set_device_socd`].
+    pub fn set_socd(&mut self, socd: Socd) {
+        self.socd = socd;
+        for (id, policy) in self.pad_socd.iter() {
+            if !self.device_socd.contains_key(&self.pad_names[id]) {
+                policy.set(socd);
+            }
+        }
+    }
+
+    /// Sets how opposing D-pad directions of pads of controllers called
+    /// `name` are cleaned, taking effect right away for the ones already
+    /// plugged in.
+    pub fn set_device_socd(&mut self, name: &str, socd: Socd) {
+        self.device_socd.insert(name.to_owned(), socd);
+        for (id, policy) in self.pad_socd.iter() {
+            if self.pad_names[id] == name {
+                policy.set(socd);
+            }
+        }
+    }
+
+    pub fn run(&mut self) -> anyhow::Result<()> {
+        loop {
+            let timeout = match self.next_tick {
+                Some(tick) => tick
+                    .saturating_duration_since(Instant::now())
+                    .min(HOUSEKEEPING_INTERVAL),
+                None => HOUSEKEEPING_INTERVAL,
+            };
+            self.poll_once(Some(timeout))?;
+        }
+    }
+
+    /// Waits up to `timeout` for packets and handles every one that arrived.
+    pub fn poll_once(&mut self, timeout: Option<Duration>) -> anyhow::Result<()> {
+        if let Err(err) = self.poll.poll(&mut self.events, timeout) {
+            if err.kind() == io::ErrorKind::Interrupted {
+                return Ok(());
+            }
+            return Err(err.into());
+        }
+
+        let readable = self.events.iter().any(|event| event.token() == UDP_SOCKET);
+        let queried = self.events.iter().any(|event| event.token() == MDNS_SOCKET);
+        for event in self.events.iter() {
+            if event.token() != UDP_SOCKET && event.token() != MDNS_SOCKET {
+                warn!("Got event for unexpected token: {:?}", event);
+            }
+        }
+        if readable {
+            self.receive();
+        }
+        if let Some(responder) = self.responder.as_mut().filter(|_| queried) {
+            if let Err(e) = responder.respond() {
+                warn!("Unable to answer mDNS queries: {}", e);
+            }
+        }
+
+        self.tick_pads();
+        self.forward_feedback();
+        self.expire_clients();
+        self.reload_profile();
+        Ok(())
+    }
+
+    fn receive(&mut self) {
+        loop {
+            match self.socket.recv_from(&mut self.buf) {
+                Ok((packet_size, source_address)) => {
+                    match from_bytes::<Envelope>(&self.buf[..packet_size]) {
+                        Ok(envelope) => self.handle_envelope(envelope, source_address),
+                        Err(e) => {
+                            warn!("Dropping malformed packet from {}: {}", source_address, e)
+                        }
+                    }
+                }
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => {
+                    return;
+                }
+                // Windows reports an ICMP port unreachable for an earlier
+                // datagram this way, on the next receive.
+                Err(e) if e.kind() == io::ErrorKind::ConnectionReset => {
+                    debug!("Ignoring {}", e);
+                }
+                Err(e) => {
+                    warn!("Unable to receive: {}", e);
+                    return;
+                }
+            }
+        }
+    }
+
+    /// Updates pads whose report changes over time, such as turbo buttons and
+    /// macros, and notes when they need it next.
+    fn tick_pads(&mut self) {
+        let now = Instant::now();
+        self.next_tick = self
+            .controllers
+            .values_mut()
+            .filter_map(|controller| controller.tick(now))
+            .min();
+    }
+
+    /// Sends feedback the host gave the virtual pads back to their owners,
+    /// and refreshes rumble that is still going.
+    fn forward_feedback(&mut self) {
+        let mut rumbles = vec![];
+        let mut slots = vec![];
+        for (&id, controller) in self.controllers.iter_mut() {
+            while let Some(feedback) = controller.poll_feedback() {
+                match feedback {
+                    Feedback::Rumble {
+                        large_motor,
+                        small_motor,
+                    } => {
+                        // Scale the 8 bit XInput speeds to SDL2's 16 bit ones.
+                        rumbles.push((id, large_motor as u16 * 257, small_motor as u16 * 257));
+                    }
+                    Feedback::Led { number } => slots.push((id, number)),
+                }
+            }
+        }
+
+        for (id, slot) in slots {
+            if let Some((address, session)) = self.feedback_target(id) {
+                self.send_to(IolEvent::PlayerSlot { id, slot }, address, session);
+            }
+        }
+
+        let now = Instant::now();
+        for (&id, rumble) in self.rumble.iter() {
+            if now.duration_since(rumble.sent) >= RUMBLE_REFRESH
+                && !rumbles.iter().any(|&(other, _, _)| other == id)
+            {
+                rumbles.push((id, rumble.low, rumble.high));
+            }
+        }
+
+        for (id, low, high) in rumbles {
+            self.send_rumble(id, low, high);
+        }
+    }
+
+    /// The address and session of the client owning pad `id`, if it wants
+    /// feedback.
+    fn feedback_target(&self, id: u32) -> Option<(SocketAddr, u32)> {
+        self.clients
+            .iter()
+            .find(|(_, client)| client.devices.contains_key(&id))
+            .filter(|(_, client)| client.capabilities.contains(Capabilities::FEEDBACK))
+            .map(|(&address, client)| (address, client.session))
+    }
+
+    fn send_rumble(&mut self, id: u32, low: u16, high: u16) {
+        let Some((address, session)) = self.feedback_target(id) else {
+            self.rumble.remove(&id);
+            return;
+        };
+
+        let duration = if low == 0 && high == 0 {
+            self.rumble.remove(&id);
+            Duration::ZERO
+        } else {
+            self.rumble.insert(
+                id,
+                ActiveRumble {
+                    low,
+                    high,
+                    sent: Instant::now(),
+                },
+            );
+            RUMBLE_DURATION
+        };
+
+        self.send_to(
+            IolEvent::Rumble {
+                id,
+                low,
+                high,
+                duration: duration.as_millis() as u32,
+            },
+            address,
+            session,
+        )
+    }
+
+    fn reload_profile(&mut self) {
+        if self.last_profile_check.elapsed() < PROFILE_RELOAD_INTERVAL {
+            return;
+        }
+        self.last_profile_check = Instant::now();
+
+        for profile in self
+            .profile
+            .iter_mut()
+            .chain(self.device_profiles.values_mut())
+        {
+            match profile.reload_if_changed() {
+                Ok(true) => println!("Reloaded profile {}.", profile.path().display()),
+                Ok(false) => {}
+                Err(e) => warn!("Keeping the previous profile: {:#}", e),
+            }
+        }
+    }
+
+    /// Releases the inputs of clients that went silent, and drops the ones
+    /// that stayed silent long enough.
+    fn expire_clients(&mut self) {
+        let now = Instant::now();
+        let mut expired = vec![];
+
+        for (address, client) in self.clients.iter_mut() {
+            let silence = now.duration_since(client.last_seen);
+            if silence >= self.client_timeout * 2 {
+                expired.push(*address);
+            } else if silence >= self.client_timeout && !client.neutralized {
+                println!("Client {} timed out, releasing its inputs.", address);
+                neutralize(self.backend.as_mut(), &mut self.controllers, client);
+            }
+        }
+
+        for address in expired {
+            println!("Client {} timed out, unplugging its devices.", address);
+            self.disconnect(address);
+        }
+    }
+
+    fn handle_envelope(&mut self, envelope: Envelope, source_address: SocketAddr) {
+        let Envelope {
+            version,
+            session,
+            sequence,
+            event,
+            ..
+        } = envelope;
+
+        if let IolEvent::Hello {
+            version,
+            capabilities,
+        } = event
+        {
+            return self.handshake(source_address, session, version, capabilities);
+        }
+
+        if let IolEvent::DiscoveryProbe = event {
+            let reply = IolEvent::DiscoveryReply {
+                name: self.name.clone(),
+                version: PROTOCOL_VERSION,
+                capabilities: self.backend.target_capabilities(self.default_target),
+                clients: self.clients.len() as u32,
+                pads: self.controllers.len() as u32,
+                capacity: self.max_pads,
+            };
+            return self.send_to(reply, source_address, session);
+        }
+
+        if version != PROTOCOL_VERSION {
+            warn!(
+                "Dropping packet from {} with protocol version {}",
+                source_address, version
+            );
+            return;
+        }
+
+        let Some(client) = self.clients.get_mut(&source_address) else {
+            if !matches!(event, IolEvent::Disconnect) {
+                warn!(
+                    "Ignoring {:?} from {} before handshake",
+                    event, source_address
+                );
+            }
+            return;
+        };
+
+        if session != client.session || !client.accept(&event, sequence) {
+            debug!(
+                "Discarding stale packet {} of session {} from {}",
+                sequence, session, source_address
+            );
+            return;
+        }
+        client.last_seen = Instant::now();
+        client.neutralized = false;
+
+        let required = match event {
+            IolEvent::KeyDown { .. } | IolEvent::KeyUp { .. } => Capabilities::KEYBOARD,
+            IolEvent::MouseMotion { .. }
+            | IolEvent::MouseButtonDown { .. }
+            | IolEvent::MouseButtonUp { .. }
+            | IolEvent::MouseWheel { .. } => Capabilities::MOUSE,
+            IolEvent::PhysicalDeviceAdded { .. }
+            | IolEvent::ButtonDown { .. }
+            | IolEvent::ButtonUp { .. }
+            | IolEvent::AxisMotion { .. }
+            | IolEvent::ControllerState { .. }
+            | IolEvent::Touch { .. } => Capabilities::GAMEPAD,
+            IolEvent::Motion { .. } => Capabilities::MOTION,
+            _ => Capabilities(0),
+        };
+        if !client.capabilities.contains(required) {
+            return;
+        }
+
+        match event {
+            IolEvent::KeyDown { scancode, .. } => {
+                client.keys.insert(scancode);
+                set_key(
+                    self.backend.as_mut(),
+                    &mut self.controllers,
+                    client,
+                    scancode,
+                    true,
+                );
+            }
+            IolEvent::KeyUp { scancode } => {
+                client.keys.remove(&scancode);
+                set_key(
+                    self.backend.as_mut(),
+                    &mut self.controllers,
+                    client,
+                    scancode,
+                    false,
+                );
+            }
+            IolEvent::PhysicalDeviceAdded {
+                which,
+                target,
+                name,
+            } => {
+                // Asked again for a device that is still plugged in: answer
+                // again rather than plugging another pad.
+                let existing = client
+                    .devices
+                    .iter()
+                    .find(|&(_, &added)| added == which)
+                    .map(|(&id, _)| id);
+                if let Some(id) = existing {
+                    self.send_to(
+                        IolEvent::VirtualDeviceAdded { id, which },
+                        source_address,
+                        session,
+                    );
+                    return;
+                }
+
+                if let Some(max_pads) = self.max_pads {
+                    if self.controllers.len() >= max_pads as usize {
+                        warn!(
+                            "Refusing a pad from {}, all {} are plugged in",
+                            source_address, max_pads
+                        );
+                        return self.send_to(
+                            IolEvent::Rejected {
+                                version: PROTOCOL_VERSION,
+                                reason: RejectReason::PadLimitReached,
+                            },
+                            source_address,
+                            session,
+                        );
+                    }
+                }
+
+                let id = self.next_id;
+                let target = target.unwrap_or(self.default_target);
+                let missing = client
+                    .capabilities
+                    .difference(self.backend.target_capabilities(target));
+                if missing.contains(Capabilities::FEEDBACK) {
+                    println!("{:?} pads get no rumble or LED from this backend.", target);
+                }
+                match self.backend.plug(target) {
+                    Ok(controller) => {
+                        let profile = self.device_profiles.get(&name).or(self.profile.as_ref());
+                        let controller: Box<dyn VirtualPad> = match profile {
+                            Some(profile) => {
+                                Box::new(RemappedPad::new(controller, profile.profile()))
+                            }
+                            None => controller,
+                        };
+                        let socd = self.device_socd.get(&name).copied().unwrap_or(self.socd);
+                        let socd = Rc::new(Cell::new(socd));
+                        self.pad_socd.insert(id, socd.clone());
+                        self.pad_names.insert(id, name);
+                        let controller = Box::new(SocdPad::new(controller, socd));
+                        println!("Controller {} was added.", id);
+                        self.next_id += 1;
+                        self.controllers.insert(id, controller);
+                        client.devices.insert(id, which);
+                    }
+                    Err(e) => {
+                        warn!("Unable to plug in virtual device: {:#}", e);
+                        return;
+                    }
+                }
+
+                self.send_to(
+                    IolEvent::VirtualDeviceAdded { id, which },
+                    source_address,
+                    session,
+                );
+                println!("Controller virtual device {} was added.", id)
+            }
+            IolEvent::PhysicalDeviceRemoved { id } if client.devices.contains_key(&id) => {
+                // Adds of the device sent before it was removed must not plug
+                // it in again if they arrive late.
+                if let Some(which) = client.devices.remove(&id) {
+                    let last = client
+                        .sequences
+                        .entry(Stream::Device(which))
+                        .or_insert(sequence);
+                    *last = (*last).max(sequence);
+                }
+                self.remove_device(id);
+            }
+            IolEvent::Disconnect => {
+                self.disconnect(source_address);
+            }
+            IolEvent::Heartbeat => {
+                self.send_to(IolEvent::Heartbeat, source_address, session);
+            }
+            IolEvent::MouseMotion { dx, dy } => {
+                self.backend.move_mouse(dx, dy);
+            }
+            IolEvent::MouseButtonDown { button } => {
+                client.mouse_buttons.insert(button);
+                self.backend.set_mouse_button(button, true);
+            }
+            IolEvent::MouseButtonUp { button } => {
+                // The broadcaster sends every release, pressed through it or not.
+                let pressed = client.mouse_buttons.remove(&button);
+                if pressed {
+                    self.backend.set_mouse_button(button, false);
+                }
+            }
+            IolEvent::MouseWheel { x, y } => {
+                self.backend.scroll_mouse(x, y);
+            }
+            IolEvent::ButtonDown { id, button } => {
+                if let Some(controller) = owned_controller(&mut self.controllers, client, id) {
+                    controller.set_button(button, true);
+                    controller.submit_report();
+                }
+            }
+            IolEvent::ButtonUp { id, button } => {
+                if let Some(controller) = owned_controller(&mut self.controllers, client, id) {
+                    controller.set_button(button, false);
+                    controller.submit_report();
+                }
+            }
+            IolEvent::AxisMotion { id, axis, value } => {
+                if let Some(controller) = owned_controller(&mut self.controllers, client, id) {
+                    controller.set_axis(axis, value);
+                    controller.submit_report();
+                }
+            }
+            IolEvent::Motion {
+                id,
+                timestamp,
+                gyro,
+                accel,
+            } => {
+                if let Some(controller) = owned_controller(&mut self.controllers, client, id) {
+                    controller.set_motion(timestamp, gyro, accel);
+                }
+            }
+            IolEvent::Touch { id, point } => {
+                if let Some(controller) = owned_controller(&mut self.controllers, client, id) {
+                    controller.set_touch(point);
+                }
+            }
+            IolEvent::ControllerState { id, state } => {
+                if let Some(controller) = owned_controller(&mut self.controllers, client, id) {
+                    for button in ControllerState::BUTTONS {
+                        controller.set_button(button, state.button(button));
+                    }
+                    for axis in ControllerState::AXES {
+                        controller.set_axis(axis, state.axis(axis));
+                    }
+                    controller.submit_report();
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn handshake(
+        &mut self,
+        source_address: SocketAddr,
+        session: u32,
+        version: u16,
+        capabilities: Capabilities,
+    ) {
+        let capabilities =
+            capabilities.intersection(self.backend.target_capabilities(self.default_target));
+
+        // A duplicated hello must not throw away what the session already set
+        // up, only a hello for a new session starts over.
+        if let Some(client) = self.clients.get(&source_address) {
+            if client.session == session {
+                let capabilities = client.capabilities;
+                return self.send_to(
+                    IolEvent::Welcome {
+                        version: PROTOCOL_VERSION,
+                        capabilities,
+                    },
+                    source_address,
+                    session,
+                );
+            }
+        }
+        self.disconnect(source_address);
+
+        let reason = if version != PROTOCOL_VERSION {
+            Some(RejectReason::VersionMismatch)
+        } else if capabilities.is_empty() {
+            Some(RejectReason::NoCommonCapabilities)
+        } else {
+            None
+        };
+
+        if let Some(reason) = reason {
+            warn!(
+                "Rejecting {} speaking protocol version {}: {:?}",
+                source_address, version, reason
+            );
+            return self.send_to(
+                IolEvent::Rejected {
+                    version: PROTOCOL_VERSION,
+                    reason,
+                },
+                source_address,
+                session,
+            );
+        }
+
+        self.clients
+            .insert(source_address, Client::new(session, capabilities));
+        println!("Client {} connected.", source_address);
+
+        self.send_to(
+            IolEvent::Welcome {
+                version: PROTOCOL_VERSION,
+                capabilities,
+            },
+            source_address,
+            session,
+        )
+    }
+
+    /// Sends `event` to `address`. Failures are only logged, as one peer
+    /// going away must not stop the listener for every other client.
+    fn send_to(&mut self, event: IolEvent, address: SocketAddr, session: u32) {
+        let envelope = Envelope::new(session, self.sequence, event);
+        self.sequence += 1;
+        let serialized = to_allocvec(&envelope).unwrap();
+        if let Err(e) = self.socket.send_to(serialized.as_slice(), address) {
+            warn!("Unable to send to {}: {}", address, e);
+        }
+    }
+
+    fn remove_device(&mut self, id: u32) {
+        self.rumble.remove(&id);
+        self.pad_socd.remove(&id);
+        self.pad_names.remove(&id);
+        if let Some(mut controller) = self.controllers.remove(&id) {
+            controller.unplug();
+        }
+        println!("Controller {} was removed.", id);
+    }
+
+    /// Releases everything held by the client and unplugs its devices.
+    fn disconnect(&mut self, source_address: SocketAddr) {
+        let Some(mut client) = self.clients.remove(&source_address) else {
+            return;
+        };
+
+        neutralize(self.backend.as_mut(), &mut self.controllers, &mut client);
+        for id in client.devices.into_keys() {
+            self.remove_device(id);
+        }
+        println!("Client {} disconnected.", source_address);
+    }
+}
+
+/// Releases every key, mouse button and pad input held by `client`, leaving
+/// its devices plugged in.
+fn neutralize(
+    backend: &mut dyn OutputBackend,
+    controllers: &mut HashMap<u32, Box<dyn VirtualPad>>,
+    client: &mut Client,
+) {
+    for scancode in std::mem::take(&mut client.keys) {
+        set_key(backend, controllers, client, scancode, false);
+    }
+    for button in client.mouse_buttons.drain() {
+        backend.set_mouse_button(button, false);
+    }
+    for id in client.devices.keys() {
+        if let Some(controller) = controllers.get_mut(id) {
+            for button in ControllerState::BUTTONS {
+                controller.set_button(button, false);
+            }
+            for axis in ControllerState::AXES {
+                controller.set_axis(axis, 0);
+            }
+            controller.submit_report();
+        }
+    }
+    client.neutralized = true;
+}
+
+/// Offers a key to the client's pads, and sends it to the keyboard if none
+/// of their profiles bind it.
+fn set_key(
+    backend: &mut dyn OutputBackend,
+    controllers: &mut HashMap<u32, Box<dyn VirtualPad>>,
+    client: &Client,
+    scancode: Scancode,
+    value: bool,
+) {
+    let mut taken = false;
+    for id in client.devices.keys() {
+        if let Some(controller) = controllers.get_mut(id) {
+            if controller.set_key(scancode, value) {
+                controller.submit_report();
+                taken = true;
+            }
+        }
+    }
+    if !taken {
+        backend.set_key(scancode, value);
+    }
+}
+
+/// Looks up a controller, but only if `client` is the one that registered it.
+fn owned_controller<'a>(
+    controllers: &'a mut HashMap<u32, Box<dyn VirtualPad>>,
+    client: &Client,
+    id: u32,
+) -> Option<&'a mut Box<dyn VirtualPad>> {
+    if client.devices.contains_key(&id) {
+        controllers.get_mut(&id)
+    } else {
+        None
+    }
+}
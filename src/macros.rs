@@ -0,0 +1,216 @@
+//! Turbo buttons and macros, configured in a [`Profile`]:
+//!
+//! ```toml
+//! # Presses per second while held, from 0.1 to 1000.
+//! [turbo]
+//! a = 12.0
+//!
+//! # Played once every time paddle1 is pressed, durations are in ms.
+//! [macros.paddle1]
+//! steps = [
+//!     { buttons = ["dpdown"], duration = 17 },
+//!     { buttons = ["dpdown", "dpright"], duration = 17 },
+//!     { buttons = ["dpright", "x"], axes = { righttrigger = 32767 }, duration = 50 },
+//! ]
+//! ```
+//!
+//! Both act on the pad as remapped, so a key bound to `paddle1` plays the
+//! macro too. The button triggering a macro is never reported itself.
+
+use std::{
+    collections::HashMap,
+    ops::RangeInclusive,
+    time::{Duration, Instant},
+};
+
+use anyhow::{anyhow, bail, Context};
+use sdl2::controller::{Axis, Button};
+use serde::Deserialize;
+
+use crate::{profile::Profile, ControllerState};
+
+/// The turbo rates a profile may ask for, in presses per second.
+pub const TURBO_RATES: RangeInclusive<f32> = 0.1..=1000.0;
+
+/// One state of a macro, held for `duration`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MacroStep {
+    pub buttons: Vec<Button>,
+    pub axes: Vec<(Axis, i16)>,
+    pub duration: Duration,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Macro {
+    pub steps: Vec<MacroStep>,
+}
+
+impl Macro {
+    fn duration(&self) -> Duration {
+        self.steps.iter().map(|step| step.duration).sum()
+    }
+
+    /// The step playing `elapsed` into the macro and when it ends, relative
+    /// to the start of the macro.
+    fn step_at(&self, elapsed: Duration) -> Option<(&MacroStep, Duration)> {
+        let mut end = Duration::ZERO;
+        for step in self.steps.iter() {
+            end += step.duration;
+            if elapsed < end {
+                return Some((step, end));
+            }
+        }
+        None
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct RawMacro {
+    steps: Vec<RawStep>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RawStep {
+    #[serde(default)]
+    buttons: Vec<String>,
+    #[serde(default)]
+    axes: HashMap<String, i16>,
+    /// In milliseconds.
+    duration: u64,
+}
+
+impl Macro {
+    pub(crate) fn resolve(raw: RawMacro) -> anyhow::Result<Self> {
+        if raw.steps.is_empty() {
+            bail!("a macro needs at least one step");
+        }
+        let steps = raw
+            .steps
+            .into_iter()
+            .enumerate()
+            .map(|(index, step)| {
+                let buttons = step
+                    .buttons
+                    .iter()
+                    .map(|name| {
+                        Button::from_string(name)
+                            .ok_or_else(|| anyhow!("unknown button {:?}", name))
+                    })
+                    .collect::<anyhow::Result<_>>();
+                let axes = step
+                    .axes
+                    .iter()
+                    .map(|(name, &value)| {
+                        let axis = Axis::from_string(name)
+                            .ok_or_else(|| anyhow!("unknown axis {:?}", name))?;
+                        let trigger = matches!(axis, Axis::TriggerLeft | Axis::TriggerRight);
+                        if trigger && value < 0 {
+                            bail!("{} only goes from 0 to 32767", name);
+                        }
+                        Ok((axis, value))
+                    })
+                    .collect::<anyhow::Result<_>>();
+                if step.duration == 0 {
+                    bail!("step {}: the duration must be above 0", index);
+                }
+                Ok(MacroStep {
+                    buttons: buttons.with_context(|| format!("step {}", index))?,
+                    axes: axes.with_context(|| format!("step {}", index))?,
+                    duration: Duration::from_millis(step.duration),
+                })
+            })
+            .collect::<anyhow::Result<_>>()?;
+        Ok(Macro { steps })
+    }
+}
+
+/// Plays the turbo buttons and macros of a profile over what the rest of the
+/// pipeline reports.
+#[derive(Debug, Default)]
+pub struct MacroEngine {
+    /// The state last passed to [`MacroEngine::apply`].
+    input: ControllerState,
+    /// When each turbo button currently held was pressed.
+    turbo: HashMap<Button, Instant>,
+    /// When each macro currently playing started, by triggering button.
+    playing: HashMap<Button, Instant>,
+}
+
+impl MacroEngine {
+    /// Returns what the pad should report at `now` with `input` held.
+    pub fn apply(
+        &mut self,
+        profile: &Profile,
+        input: ControllerState,
+        now: Instant,
+    ) -> ControllerState {
+        for button in ControllerState::BUTTONS {
+            let pressed = input.button(button) && !self.input.button(button);
+            if !input.button(button) {
+                self.turbo.remove(&button);
+            } else if pressed && profile.turbo.contains_key(&button) {
+                self.turbo.insert(button, now);
+            }
+            if pressed && profile.macros.contains_key(&button) {
+                self.playing.entry(button).or_insert(now);
+            }
+        }
+        self.input = input;
+
+        let mut output = input;
+        for (button, &since) in self.turbo.iter() {
+            let Some(&rate) = profile.turbo.get(button) else {
+                continue;
+            };
+            let (half_periods, _) = turbo_phase(rate, now - since);
+            output.set_button(*button, half_periods % 2 == 0);
+        }
+
+        self.playing.retain(|trigger, &mut since| {
+            profile
+                .macros
+                .get(trigger)
+                .is_some_and(|m| now - since < m.duration())
+        });
+        for button in profile.macros.keys() {
+            output.set_button(*button, false);
+        }
+        for (trigger, &since) in self.playing.iter() {
+            let Some((step, _)) = profile.macros[trigger].step_at(now - since) else {
+                continue;
+            };
+            for &button in step.buttons.iter() {
+                output.set_button(button, true);
+            }
+            for &(axis, value) in step.axes.iter() {
+                output.set_axis(axis, value);
+            }
+        }
+
+        output
+    }
+
+    /// When the output next changes on its own, if ever.
+    pub fn next_change(&self, profile: &Profile, now: Instant) -> Option<Instant> {
+        let turbo = self.turbo.iter().filter_map(|(button, &since)| {
+            let rate = *profile.turbo.get(button)?;
+            let (half_periods, half_period) = turbo_phase(rate, now - since);
+            Some(since + half_period * (half_periods + 1))
+        });
+        let macros = self.playing.iter().filter_map(|(trigger, &since)| {
+            let (_, end) = profile.macros.get(trigger)?.step_at(now - since)?;
+            Some(since + end)
+        });
+        turbo.chain(macros).min()
+    }
+}
+
+/// How many half periods of a turbo button went by in `elapsed`, it is
+/// pressed during the even ones, and how long one lasts.
+fn turbo_phase(rate: f32, elapsed: Duration) -> (u32, Duration) {
+    let half_period = Duration::from_secs_f64(0.5 / rate as f64).max(Duration::from_millis(1));
+    let half_periods = elapsed.as_nanos() / half_period.as_nanos();
+    (half_periods as u32, half_period)
+}
@@ -0,0 +1,612 @@
+//! A minimal mDNS responder and resolver, just enough for listeners to show
+//! up as `_iol._udp` services in DNS-SD browsers and for `name.local` to be
+//! resolved when connecting to them.
+//!
+//! The host name the SRV record points at is normally answered for by the
+//! OS' own responder (Bonjour, Avahi, systemd-resolved or Windows'). The
+//! responder only answers A records for it when none of those does.
+//!
+//! Only IPv4, uncompressed names are sent, and compressed ones are read.
+
+use std::{
+    env, fs, io,
+    net::{IpAddr, Ipv4Addr, SocketAddr, SocketAddrV4},
+    process,
+    time::{Duration, Instant},
+};
+
+use anyhow::{bail, ensure};
+use log::{debug, warn};
+use mio::net::UdpSocket;
+use socket2::{Domain, Protocol, Socket, Type};
+
+use crate::PROTOCOL_VERSION;
+
+pub const MDNS_GROUP: Ipv4Addr = Ipv4Addr::new(224, 0, 0, 251);
+pub const MDNS_PORT: u16 = 5353;
+
+/// The DNS-SD service type listeners advertise.
+pub const SERVICE: &str = "_iol._udp.local";
+/// Lists every service type on the network, for browsers.
+const SERVICES: &str = "_services._dns-sd._udp.local";
+
+pub const TYPE_A: u16 = 1;
+pub const TYPE_PTR: u16 = 12;
+pub const TYPE_TXT: u16 = 16;
+pub const TYPE_SRV: u16 = 33;
+pub const TYPE_ANY: u16 = 255;
+
+const CLASS_IN: u16 = 1;
+/// Set on a question's class when the answer is wanted by unicast.
+const UNICAST_RESPONSE: u16 = 0x8000;
+const FLAG_RESPONSE: u16 = 0x8000;
+const FLAG_AUTHORITATIVE: u16 = 0x0400;
+const TTL: u32 = 120;
+/// The most a one-shot, legacy unicast answer may be cached for, per RFC 6762
+/// section 6.7.
+const LEGACY_TTL: u32 = 10;
+/// How long to wait for another host to claim an instance name.
+const PROBE_TIMEOUT: Duration = Duration::from_millis(250);
+/// Instance names tried before giving up on finding a free one.
+const PROBE_ATTEMPTS: u32 = 10;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Question {
+    pub name: String,
+    pub qtype: u16,
+    /// Whether the querier asked for a unicast answer.
+    pub unicast: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RecordData {
+    A(Ipv4Addr),
+    Ptr(String),
+    Txt(Vec<String>),
+    Srv {
+        port: u16,
+        target: String,
+    },
+    /// Any other type, left undecoded.
+    Other(u16),
+}
+
+impl RecordData {
+    fn rtype(&self) -> u16 {
+        match self {
+            RecordData::A(_) => TYPE_A,
+            RecordData::Ptr(_) => TYPE_PTR,
+            RecordData::Txt(_) => TYPE_TXT,
+            RecordData::Srv { .. } => TYPE_SRV,
+            RecordData::Other(rtype) => *rtype,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Record {
+    pub name: String,
+    pub ttl: u32,
+    pub data: RecordData,
+}
+
+/// A DNS message, with the answer, authority and additional sections read
+/// into `records` together.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Message {
+    pub id: u16,
+    pub response: bool,
+    pub questions: Vec<Question>,
+    pub records: Vec<Record>,
+}
+
+impl Message {
+    pub fn encode(&self) -> Vec<u8> {
+        let mut out = vec![];
+        let flags = if self.response {
+            FLAG_RESPONSE | FLAG_AUTHORITATIVE
+        } else {
+            0
+        };
+        for value in [
+            self.id,
+            flags,
+            self.questions.len() as u16,
+            self.records.len() as u16,
+            0,
+            0,
+        ] {
+            out.extend(value.to_be_bytes());
+        }
+        for question in self.questions.iter() {
+            write_name(&mut out, &question.name);
+            out.extend(question.qtype.to_be_bytes());
+            let class = if question.unicast {
+                CLASS_IN | UNICAST_RESPONSE
+            } else {
+                CLASS_IN
+            };
+            out.extend(class.to_be_bytes());
+        }
+        for record in self.records.iter() {
+            write_name(&mut out, &record.name);
+            out.extend(record.data.rtype().to_be_bytes());
+            out.extend(CLASS_IN.to_be_bytes());
+            out.extend(record.ttl.to_be_bytes());
+            let mut data = vec![];
+            match &record.data {
+                RecordData::A(address) => data.extend(address.octets()),
+                RecordData::Ptr(name) => write_name(&mut data, name),
+                RecordData::Txt(entries) => {
+                    for entry in entries.iter() {
+                        let entry = &entry.as_bytes()[..entry.len().min(255)];
+                        data.push(entry.len() as u8);
+                        data.extend(entry);
+                    }
+                }
+                RecordData::Srv { port, target } => {
+                    data.extend([0, 0, 0, 0]);
+                    data.extend(port.to_be_bytes());
+                    write_name(&mut data, target);
+                }
+                RecordData::Other(_) => {}
+            }
+            out.extend((data.len() as u16).to_be_bytes());
+            out.extend(data);
+        }
+        out
+    }
+
+    pub fn decode(packet: &[u8]) -> anyhow::Result<Self> {
+        let mut reader = Reader { packet, pos: 0 };
+        let id = reader.u16()?;
+        let flags = reader.u16()?;
+        let questions = reader.u16()?;
+        let records = reader.u16()? as usize + reader.u16()? as usize + reader.u16()? as usize;
+
+        let mut message = Message {
+            id,
+            response: flags & FLAG_RESPONSE != 0,
+            ..Default::default()
+        };
+        for _ in 0..questions {
+            let name = reader.name()?;
+            let qtype = reader.u16()?;
+            let class = reader.u16()?;
+            message.questions.push(Question {
+                name,
+                qtype,
+                unicast: class & UNICAST_RESPONSE != 0,
+            });
+        }
+        for _ in 0..records {
+            let name = reader.name()?;
+            let rtype = reader.u16()?;
+            let _class = reader.u16()?;
+            let ttl = reader.u32()?;
+            let length = reader.u16()? as usize;
+            let end = reader.pos + length;
+            ensure!(end <= packet.len(), "truncated record");
+            let data = match rtype {
+                TYPE_A => {
+                    ensure!(length == 4, "bad A record");
+                    let octets = reader.bytes(4)?;
+                    RecordData::A(Ipv4Addr::new(octets[0], octets[1], octets[2], octets[3]))
+                }
+                TYPE_PTR => RecordData::Ptr(reader.name()?),
+                TYPE_TXT => {
+                    let mut entries = vec![];
+                    while reader.pos < end {
+                        let length = reader.u8()? as usize;
+                        let entry = reader.bytes(length)?;
+                        entries.push(String::from_utf8_lossy(entry).into_owned());
+                    }
+                    RecordData::Txt(entries)
+                }
+                TYPE_SRV => {
+                    let _priority_and_weight = reader.u32()?;
+                    let port = reader.u16()?;
+                    RecordData::Srv {
+                        port,
+                        target: reader.name()?,
+                    }
+                }
+                rtype => RecordData::Other(rtype),
+            };
+            reader.pos = end;
+            message.records.push(Record { name, ttl, data });
+        }
+        Ok(message)
+    }
+}
+
+fn write_name(out: &mut Vec<u8>, name: &str) {
+    for label in name.split('.').filter(|label| !label.is_empty()) {
+        let label = &label.as_bytes()[..label.len().min(63)];
+        out.push(label.len() as u8);
+        out.extend(label);
+    }
+    out.push(0);
+}
+
+struct Reader<'a> {
+    packet: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn bytes(&mut self, count: usize) -> anyhow::Result<&'a [u8]> {
+        let Some(bytes) = self.packet.get(self.pos..self.pos + count) else {
+            bail!("truncated message");
+        };
+        self.pos += count;
+        Ok(bytes)
+    }
+
+    fn u8(&mut self) -> anyhow::Result<u8> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn u16(&mut self) -> anyhow::Result<u16> {
+        let bytes = self.bytes(2)?;
+        Ok(u16::from_be_bytes([bytes[0], bytes[1]]))
+    }
+
+    fn u32(&mut self) -> anyhow::Result<u32> {
+        let bytes = self.bytes(4)?;
+        Ok(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    /// Reads a name, following compression pointers.
+    fn name(&mut self) -> anyhow::Result<String> {
+        let mut labels = vec![];
+        let mut pos = self.pos;
+        // Where to carry on reading once the name ends, set by the first
+        // pointer followed.
+        let mut resume = None;
+        for _ in 0..128 {
+            let Some(&length) = self.packet.get(pos) else {
+                bail!("truncated name");
+            };
+            match length {
+                0 => {
+                    self.pos = resume.unwrap_or(pos + 1);
+                    return Ok(labels.join("."));
+                }
+                length if length & 0xc0 == 0xc0 => {
+                    let Some(&low) = self.packet.get(pos + 1) else {
+                        bail!("truncated name");
+                    };
+                    resume.get_or_insert(pos + 2);
+                    pos = ((length as usize & 0x3f) << 8) | low as usize;
+                }
+                length => {
+                    let start = pos + 1;
+                    let Some(label) = self.packet.get(start..start + length as usize) else {
+                        bail!("truncated name");
+                    };
+                    labels.push(String::from_utf8_lossy(label).into_owned());
+                    pos = start + length as usize;
+                }
+            }
+        }
+        bail!("name compression loop")
+    }
+}
+
+fn same_name(a: &str, b: &str) -> bool {
+    a.trim_end_matches('.')
+        .eq_ignore_ascii_case(b.trim_end_matches('.'))
+}
+
+/// The host name, as far as it can be found without a system call.
+pub fn host_name() -> Option<String> {
+    let name = if cfg!(windows) {
+        env::var("COMPUTERNAME").ok()
+    } else {
+        fs::read_to_string("/etc/hostname")
+            .ok()
+            .or_else(|| env::var("HOSTNAME").ok())
+    };
+    name.map(|name| name.trim().to_owned())
+        .filter(|name| !name.is_empty())
+}
+
+/// The address other hosts on the LAN most likely reach this one on: the
+/// one multicast goes out from.
+fn local_ipv4() -> Option<Ipv4Addr> {
+    let socket = std::net::UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0)).ok()?;
+    socket.connect((MDNS_GROUP, MDNS_PORT)).ok()?;
+    match socket.local_addr().ok()?.ip() {
+        IpAddr::V4(address) if !address.is_unspecified() => Some(address),
+        _ => None,
+    }
+}
+
+/// What a [`Responder`] advertises.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Service {
+    /// The service instance name, e.g. shown in browsers.
+    pub instance: String,
+    /// The host name the service runs on, ending in `.local`.
+    pub host: String,
+    /// The address answered for `host`, `None` while the OS' responder
+    /// answers for it.
+    pub address: Option<Ipv4Addr>,
+    pub port: u16,
+    pub txt: Vec<String>,
+}
+
+impl Service {
+    /// A listener called `instance` on `port` of this host.
+    pub fn new(instance: &str, port: u16) -> Self {
+        // Responders advertise the first label of the host name as is, any
+        // domain it is in gives way to `.local`.
+        let host = host_name().unwrap_or_else(|| "iol-listen".to_owned());
+        let host = host.split('.').next().unwrap_or_default();
+        Service {
+            // A dot would split the instance name into several labels.
+            instance: instance.replace('.', "-"),
+            host: format!("{}.local", host),
+            address: local_ipv4(),
+            port,
+            txt: vec![format!("version={}", PROTOCOL_VERSION)],
+        }
+    }
+
+    /// The full name of the service instance.
+    pub fn instance_name(&self) -> String {
+        format!("{}.{}", self.instance, SERVICE)
+    }
+
+    /// Asks `server` whether another host already advertises our instance
+    /// name and, while one does, numbers ours: "htpc (2)", "htpc (3)"...
+    pub fn deconflict(&mut self, server: SocketAddr, timeout: Duration) -> anyhow::Result<()> {
+        let base = self.instance.clone();
+        for attempt in 2..PROBE_ATTEMPTS + 2 {
+            let instance = self.instance_name();
+            let records = query(server, &instance, TYPE_SRV, timeout)?;
+            let taken = records.iter().any(|record| {
+                same_name(&record.name, &instance) && record.data.rtype() == TYPE_SRV
+            });
+            if !taken {
+                return Ok(());
+            }
+            debug!("{} is taken, trying another name", instance);
+            self.instance = format!("{} ({})", base, attempt);
+        }
+        bail!("no free instance name left for {}", base)
+    }
+
+    /// Asks `server` whether a responder already answers for our host name,
+    /// as the OS' does, and if so leaves the host's address to it.
+    pub fn defer_host(&mut self, server: SocketAddr, timeout: Duration) -> anyhow::Result<()> {
+        let records = query(server, &self.host, TYPE_A, timeout)?;
+        let answered = records
+            .iter()
+            .any(|record| same_name(&record.name, &self.host) && record.data.rtype() == TYPE_A);
+        if answered {
+            debug!("{} is answered for already", self.host);
+            self.address = None;
+        }
+        Ok(())
+    }
+
+    /// The records answering `question`, if it is about this service.
+    fn answers(&self, question: &Question) -> Vec<Record> {
+        let instance = self.instance_name();
+        let wants = |rtype| question.qtype == rtype || question.qtype == TYPE_ANY;
+        let record = |name: &str, data| Record {
+            name: name.to_owned(),
+            ttl: TTL,
+            data,
+        };
+        let about_service = same_name(&question.name, SERVICE) && wants(TYPE_PTR);
+        let about_instance = same_name(&question.name, &instance);
+        let srv = about_service || (about_instance && wants(TYPE_SRV));
+        let txt = about_service || (about_instance && wants(TYPE_TXT));
+        let a = srv || txt || (same_name(&question.name, &self.host) && wants(TYPE_A));
+
+        // Answers come with whatever the querier needs next to connect.
+        let mut records = vec![];
+        if same_name(&question.name, SERVICES) && wants(TYPE_PTR) {
+            records.push(record(SERVICES, RecordData::Ptr(SERVICE.to_owned())));
+        }
+        if about_service {
+            records.push(record(SERVICE, RecordData::Ptr(instance.clone())));
+        }
+        if srv {
+            let target = self.host.clone();
+            let port = self.port;
+            records.push(record(&instance, RecordData::Srv { port, target }));
+        }
+        if txt {
+            records.push(record(&instance, RecordData::Txt(self.txt.clone())));
+        }
+        if let Some(address) = self.address.filter(|_| a) {
+            records.push(record(&self.host, RecordData::A(address)));
+        }
+        records
+    }
+}
+
+/// Answers mDNS queries about a [`Service`].
+pub struct Responder {
+    socket: UdpSocket,
+    service: Service,
+    buf: Vec<u8>,
+}
+
+impl Responder {
+    /// Joins the mDNS group, sharing the port with any other responder
+    /// running on this host, once `service` has an instance name no other
+    /// host on the LAN uses and knows whether to answer for the host name.
+    pub fn bind(mut service: Service) -> anyhow::Result<Self> {
+        let group = SocketAddr::new(MDNS_GROUP.into(), MDNS_PORT);
+        service.deconflict(group, PROBE_TIMEOUT)?;
+        service.defer_host(group, PROBE_TIMEOUT)?;
+        let socket = Socket::new(Domain::IPV4, Type::DGRAM, Some(Protocol::UDP))?;
+        socket.set_reuse_address(true)?;
+        #[cfg(unix)]
+        socket.set_reuse_port(true)?;
+        socket.set_nonblocking(true)?;
+        socket.bind(&SocketAddrV4::new(Ipv4Addr::UNSPECIFIED, MDNS_PORT).into())?;
+        socket.join_multicast_v4(&MDNS_GROUP, &Ipv4Addr::UNSPECIFIED)?;
+        socket.set_multicast_loop_v4(true)?;
+        Ok(Self::with_socket(
+            UdpSocket::from_std(socket.into()),
+            service,
+        ))
+    }
+
+    /// Answers queries sent straight to `addr`, without joining the group.
+    pub fn bind_to(addr: SocketAddr, service: Service) -> anyhow::Result<Self> {
+        Ok(Self::with_socket(UdpSocket::bind(addr)?, service))
+    }
+
+    fn with_socket(socket: UdpSocket, service: Service) -> Self {
+        Responder {
+            socket,
+            service,
+            buf: vec![0; 9000],
+        }
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.socket.local_addr()
+    }
+
+    pub fn service(&self) -> &Service {
+        &self.service
+    }
+
+    pub(crate) fn socket_mut(&mut self) -> &mut UdpSocket {
+        &mut self.socket
+    }
+
+    /// Answers every query received so far, without blocking.
+    pub fn respond(&mut self) -> io::Result<()> {
+        loop {
+            let (packet_size, source_address) = match self.socket.recv_from(&mut self.buf) {
+                Ok(received) => received,
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => return Ok(()),
+                Err(e) => return Err(e),
+            };
+            let query = match Message::decode(&self.buf[..packet_size]) {
+                Ok(query) if !query.response => query,
+                Ok(_) => continue,
+                Err(e) => {
+                    debug!(
+                        "Dropping malformed mDNS packet from {}: {}",
+                        source_address, e
+                    );
+                    continue;
+                }
+            };
+
+            let mut records: Vec<Record> = vec![];
+            for question in query.questions.iter() {
+                for record in self.service.answers(question) {
+                    if !records.contains(&record) {
+                        records.push(record);
+                    }
+                }
+            }
+            if records.is_empty() {
+                continue;
+            }
+
+            // Queries from another port than mDNS' are one-shot queries from
+            // plain resolvers, they get a regular DNS answer back, which
+            // mustn't outlive the records it was taken from for long.
+            let legacy = source_address.port() != MDNS_PORT;
+            if legacy {
+                for record in records.iter_mut() {
+                    record.ttl = record.ttl.min(LEGACY_TTL);
+                }
+            }
+            let unicast = legacy || query.questions.iter().any(|question| question.unicast);
+            let response = Message {
+                id: if legacy { query.id } else { 0 },
+                response: true,
+                questions: if legacy { query.questions } else { vec![] },
+                records,
+            };
+            let destination = if unicast {
+                source_address
+            } else {
+                SocketAddr::new(MDNS_GROUP.into(), MDNS_PORT)
+            };
+            if let Err(e) = self.socket.send_to(&response.encode(), destination) {
+                warn!("Unable to answer mDNS query from {}: {}", source_address, e);
+            }
+        }
+    }
+}
+
+/// Asks `server`, usually the mDNS group, about `name` and returns the
+/// records of the first answer, or nothing after `timeout`.
+pub fn query(
+    server: SocketAddr,
+    name: &str,
+    qtype: u16,
+    timeout: Duration,
+) -> anyhow::Result<Vec<Record>> {
+    let socket = std::net::UdpSocket::bind((Ipv4Addr::UNSPECIFIED, 0))?;
+    let id = process::id() as u16;
+    let query = Message {
+        id,
+        response: false,
+        questions: vec![Question {
+            name: name.to_owned(),
+            qtype,
+            unicast: true,
+        }],
+        records: vec![],
+    };
+    socket.send_to(&query.encode(), server)?;
+
+    let deadline = Instant::now() + timeout;
+    let mut buf = vec![0; 9000];
+    loop {
+        let now = Instant::now();
+        if now >= deadline {
+            return Ok(vec![]);
+        }
+        socket.set_read_timeout(Some(deadline - now))?;
+        let packet_size = match socket.recv_from(&mut buf) {
+            Ok((packet_size, _)) => packet_size,
+            Err(e)
+                if matches!(
+                    e.kind(),
+                    io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+                ) =>
+            {
+                return Ok(vec![]);
+            }
+            Err(e) => return Err(e.into()),
+        };
+        let Ok(response) = Message::decode(&buf[..packet_size]) else {
+            continue;
+        };
+        let answered = response.records.iter().any(|record| {
+            same_name(&record.name, name) && (qtype == TYPE_ANY || record.data.rtype() == qtype)
+        });
+        if response.response && answered {
+            return Ok(response.records);
+        }
+    }
+}
+
+/// Resolves a `name.local` host name over mDNS.
+pub fn resolve(host: &str, timeout: Duration) -> anyhow::Result<Ipv4Addr> {
+    let server = SocketAddr::new(MDNS_GROUP.into(), MDNS_PORT);
+    let records = query(server, host, TYPE_A, timeout)?;
+    records
+        .into_iter()
+        .find_map(|record| match record.data {
+            RecordData::A(address) if same_name(&record.name, host) => Some(address),
+            _ => None,
+        })
+        .ok_or_else(|| anyhow::anyhow!("no answer for {} over mDNS", host))
+}
@@ -0,0 +1,218 @@
+use std::{
+    cell::RefCell,
+    collections::{HashMap, HashSet, VecDeque},
+    rc::Rc,
+};
+
+use sdl2::{
+    controller::{Axis, Button},
+    keyboard::Scancode,
+    mouse::MouseButton,
+};
+
+use crate::{
+    backend::{Feedback, OutputBackend, VirtualPad},
+    Capabilities, PadTarget, TouchPoint,
+};
+
+/// A snapshot of a virtual pad, taken whenever a report is submitted.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MockReport {
+    pub buttons: HashSet<Button>,
+    pub axes: HashMap<Axis, i16>,
+}
+
+impl MockReport {
+    pub fn button(&self, button: Button) -> bool {
+        self.buttons.contains(&button)
+    }
+
+    pub fn axis(&self, axis: Axis) -> i16 {
+        self.axes.get(&axis).copied().unwrap_or(0)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MockMotion {
+    pub timestamp: u64,
+    pub gyro: [f32; 3],
+    pub accel: [f32; 3],
+}
+
+#[derive(Debug, Default)]
+pub struct MockPadState {
+    pub plugged: bool,
+    pub target: PadTarget,
+    /// Changes made since the last submitted report.
+    pub pending: MockReport,
+    pub reports: Vec<MockReport>,
+    /// Feedback the pad will hand out through [`VirtualPad::poll_feedback`].
+    pub feedback: VecDeque<Feedback>,
+    pub motion: Vec<MockMotion>,
+    pub touches: Vec<TouchPoint>,
+}
+
+impl MockPadState {
+    /// The state the host currently sees, i.e. the last submitted report.
+    pub fn current(&self) -> MockReport {
+        self.reports.last().cloned().unwrap_or_default()
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct MockBackendState {
+    pub pads: Vec<Rc<RefCell<MockPadState>>>,
+    pub keys: HashSet<Scancode>,
+    pub key_log: Vec<(Scancode, bool)>,
+    /// Sum of every relative mouse motion received.
+    pub mouse_position: (i32, i32),
+    pub mouse_buttons: HashSet<MouseButton>,
+    pub mouse_button_log: Vec<(MouseButton, bool)>,
+    pub wheel: (i32, i32),
+}
+
+/// An [`OutputBackend`] that records everything done to it in memory.
+///
+/// Clones share their state, so a test can keep one handle and hand the
+/// other to a [`Listener`](crate::listener::Listener).
+#[derive(Debug, Clone)]
+pub struct MockBackend {
+    state: Rc<RefCell<MockBackendState>>,
+    capabilities: Capabilities,
+    target_capabilities: HashMap<PadTarget, Capabilities>,
+}
+
+impl Default for MockBackend {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl MockBackend {
+    pub fn new() -> Self {
+        Self::with_capabilities(Capabilities::ALL)
+    }
+
+    /// A backend that only offers `capabilities` during the handshake.
+    pub fn with_capabilities(capabilities: Capabilities) -> Self {
+        MockBackend {
+            state: Default::default(),
+            capabilities,
+            target_capabilities: HashMap::new(),
+        }
+    }
+
+    /// Narrows what the backend offers while `target` is the default one.
+    pub fn set_target_capabilities(&mut self, target: PadTarget, capabilities: Capabilities) {
+        self.target_capabilities.insert(target, capabilities);
+    }
+
+    pub fn state(&self) -> std::cell::Ref<'_, MockBackendState> {
+        self.state.borrow()
+    }
+
+    /// Returns the `index`th pad plugged into this backend, in plug order.
+    pub fn pad(&self, index: usize) -> Option<Rc<RefCell<MockPadState>>> {
+        self.state.borrow().pads.get(index).cloned()
+    }
+}
+
+impl OutputBackend for MockBackend {
+    fn capabilities(&self) -> Capabilities {
+        self.capabilities
+    }
+
+    fn target_capabilities(&self, target: PadTarget) -> Capabilities {
+        match self.target_capabilities.get(&target) {
+            Some(&capabilities) => capabilities,
+            None => self.capabilities,
+        }
+    }
+
+    fn plug(&mut self, target: PadTarget) -> anyhow::Result<Box<dyn VirtualPad>> {
+        let state = Rc::new(RefCell::new(MockPadState {
+            plugged: true,
+            target,
+            ..Default::default()
+        }));
+        self.state.borrow_mut().pads.push(state.clone());
+        Ok(Box::new(MockPad { state }))
+    }
+
+    fn set_key(&mut self, scancode: Scancode, value: bool) {
+        let mut state = self.state.borrow_mut();
+        if value {
+            state.keys.insert(scancode);
+        } else {
+            state.keys.remove(&scancode);
+        }
+        state.key_log.push((scancode, value));
+    }
+
+    fn move_mouse(&mut self, dx: i32, dy: i32) {
+        let mut state = self.state.borrow_mut();
+        state.mouse_position.0 += dx;
+        state.mouse_position.1 += dy;
+    }
+
+    fn set_mouse_button(&mut self, button: MouseButton, value: bool) {
+        let mut state = self.state.borrow_mut();
+        if value {
+            state.mouse_buttons.insert(button);
+        } else {
+            state.mouse_buttons.remove(&button);
+        }
+        state.mouse_button_log.push((button, value));
+    }
+
+    fn scroll_mouse(&mut self, x: i32, y: i32) {
+        let mut state = self.state.borrow_mut();
+        state.wheel.0 += x;
+        state.wheel.1 += y;
+    }
+}
+
+pub struct MockPad {
+    state: Rc<RefCell<MockPadState>>,
+}
+
+impl VirtualPad for MockPad {
+    fn set_button(&mut self, button: Button, value: bool) {
+        let mut state = self.state.borrow_mut();
+        if value {
+            state.pending.buttons.insert(button);
+        } else {
+            state.pending.buttons.remove(&button);
+        }
+    }
+
+    fn set_axis(&mut self, axis: Axis, value: i16) {
+        self.state.borrow_mut().pending.axes.insert(axis, value);
+    }
+
+    fn submit_report(&mut self) {
+        let mut state = self.state.borrow_mut();
+        let report = state.pending.clone();
+        state.reports.push(report);
+    }
+
+    fn set_motion(&mut self, timestamp: u64, gyro: [f32; 3], accel: [f32; 3]) {
+        self.state.borrow_mut().motion.push(MockMotion {
+            timestamp,
+            gyro,
+            accel,
+        });
+    }
+
+    fn set_touch(&mut self, point: TouchPoint) {
+        self.state.borrow_mut().touches.push(point);
+    }
+
+    fn unplug(&mut self) {
+        self.state.borrow_mut().plugged = false;
+    }
+
+    fn poll_feedback(&mut self) -> Option<Feedback> {
+        self.state.borrow_mut().feedback.pop_front()
+    }
+}
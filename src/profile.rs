@@ -0,0 +1,555 @@
+//! User-defined remapping of a pad's buttons, axes and keyboard keys.
+//!
+//! Profiles are TOML or JSON files, picked by extension:
+//!
+//! ```toml
+//! [buttons]
+//! a = "b"
+//! b = "a"
+//! guide = "none"
+//! leftshoulder = { axis = "lefttrigger" }
+//!
+//! [axes]
+//! leftx = { invert = true }
+//! righttrigger = { button = "rightshoulder", threshold = 8000 }
+//!
+//! [keys]
+//! Space = "a"
+//! W = "dpup"
+//! S = "dpdown"
+//!
+//! [socd]
+//! horizontal = "neutral"
+//! vertical = "up-priority"
+//!
+//! [analog.left_stick]
+//! inner = 0.1
+//!
+//! [turbo]
+//! x = 10.0
+//! ```
+//!
+//! Buttons and axes use SDL2's game controller names, keys use SDL2's
+//! scancode names. Anything left out of the profile passes through as is.
+//! The `[socd]` policies, see [`SocdPolicy`](crate::socd::SocdPolicy), clean
+//! up the D-pad once everything else was applied. The `[analog]` section, see
+//! [`Analog`](crate::analog::Analog), shapes the sticks and triggers before
+//! anything is remapped. Turbo buttons and macros are described in
+//! [`macros`](crate::macros).
+
+use std::{
+    cell::RefCell,
+    collections::{HashMap, HashSet},
+    fs,
+    path::{Path, PathBuf},
+    rc::Rc,
+    time::{Instant, SystemTime},
+};
+
+use anyhow::{anyhow, bail, Context};
+use sdl2::{
+    controller::{Axis, Button},
+    keyboard::Scancode,
+};
+use serde::Deserialize;
+
+use crate::{
+    analog::Analog,
+    backend::{Feedback, VirtualPad},
+    macros::{Macro, MacroEngine, RawMacro, TURBO_RATES},
+    socd::{Socd, SocdResolver},
+    ControllerState, TouchPoint,
+};
+
+/// How far an axis must move before a button bound to it is pressed, when
+/// the profile doesn't say.
+pub const DEFAULT_THRESHOLD: i16 = 16384;
+
+/// What a button or key does to the pad while it is held.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Binding {
+    Button(Button),
+    /// Pushes `axis` to `value`.
+    Axis {
+        axis: Axis,
+        value: i16,
+    },
+    /// Does nothing at all.
+    Disabled,
+}
+
+/// Where the value of an axis ends up.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AxisBinding {
+    Axis {
+        axis: Axis,
+        invert: bool,
+    },
+    /// Holds `button` while the axis is past `threshold`, on the same side of
+    /// 0 as `threshold`.
+    Button {
+        button: Button,
+        threshold: i16,
+    },
+    Disabled,
+}
+
+/// A parsed remapping profile, see the [module docs](self) for the format.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Profile {
+    pub buttons: HashMap<Button, Binding>,
+    pub axes: HashMap<Axis, AxisBinding>,
+    pub keys: HashMap<Scancode, Binding>,
+    pub socd: Socd,
+    pub analog: Analog,
+    /// Presses per second of each turbo button.
+    pub turbo: HashMap<Button, f32>,
+    pub macros: HashMap<Button, Macro>,
+}
+
+/// A binding as written in the file, before names are resolved.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum RawBinding {
+    Name(String),
+    Table(RawTarget),
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RawTarget {
+    button: Option<String>,
+    axis: Option<String>,
+    value: Option<i16>,
+    threshold: Option<i16>,
+    invert: Option<bool>,
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+struct RawProfile {
+    buttons: HashMap<String, RawBinding>,
+    axes: HashMap<String, RawBinding>,
+    keys: HashMap<String, RawBinding>,
+    socd: Socd,
+    analog: Analog,
+    turbo: HashMap<String, f32>,
+    macros: HashMap<String, RawMacro>,
+}
+
+impl Profile {
+    /// Reads a profile, as TOML unless the file name ends in `.json`.
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let text = fs::read_to_string(path)
+            .with_context(|| format!("unable to read profile {}", path.display()))?;
+        let is_json = path
+            .extension()
+            .is_some_and(|extension| extension.eq_ignore_ascii_case("json"));
+        let profile = if is_json {
+            Profile::from_json(&text)
+        } else {
+            Profile::from_toml(&text)
+        };
+        profile.with_context(|| format!("invalid profile {}", path.display()))
+    }
+
+    pub fn from_toml(text: &str) -> anyhow::Result<Self> {
+        Profile::resolve(toml::from_str(text)?)
+    }
+
+    pub fn from_json(text: &str) -> anyhow::Result<Self> {
+        Profile::resolve(serde_json::from_str(text)?)
+    }
+
+    fn resolve(raw: RawProfile) -> anyhow::Result<Self> {
+        raw.analog.validate()?;
+        let mut profile = Profile {
+            socd: raw.socd,
+            analog: raw.analog,
+            ..Default::default()
+        };
+        for (name, binding) in raw.buttons {
+            let button = parse_button(&name)?;
+            let binding = resolve_binding(binding).with_context(|| format!("buttons.{}", name))?;
+            profile.buttons.insert(button, binding);
+        }
+        for (name, binding) in raw.axes {
+            let axis = parse_axis(&name)?;
+            let binding =
+                resolve_axis_binding(axis, binding).with_context(|| format!("axes.{}", name))?;
+            profile.axes.insert(axis, binding);
+        }
+        for (name, binding) in raw.keys {
+            let scancode =
+                Scancode::from_name(&name).ok_or_else(|| anyhow!("unknown key {:?}", name))?;
+            let binding = resolve_binding(binding).with_context(|| format!("keys.{}", name))?;
+            profile.keys.insert(scancode, binding);
+        }
+        for (name, rate) in raw.turbo {
+            let button = parse_button(&name)?;
+            if !TURBO_RATES.contains(&rate) {
+                bail!(
+                    "turbo.{}: the rate must be from {} to {} presses per second",
+                    name,
+                    TURBO_RATES.start(),
+                    TURBO_RATES.end()
+                );
+            }
+            profile.turbo.insert(button, rate);
+        }
+        for (name, definition) in raw.macros {
+            let button = parse_button(&name)?;
+            let definition =
+                Macro::resolve(definition).with_context(|| format!("macros.{}", name))?;
+            profile.macros.insert(button, definition);
+        }
+        Ok(profile)
+    }
+
+    /// What the pad looks like with `input` held on the physical controller
+    /// and `keys` held on the keyboard.
+    ///
+    /// When several inputs land on the same axis, the one pushed furthest
+    /// wins.
+    pub fn apply(&self, input: &ControllerState, keys: &HashSet<Scancode>) -> ControllerState {
+        let mut input = *input;
+        self.analog.apply(&mut input);
+        let mut output = ControllerState::default();
+
+        for axis in ControllerState::AXES {
+            let value = input.axis(axis);
+            let binding = self.axes.get(&axis).copied().unwrap_or(AxisBinding::Axis {
+                axis,
+                invert: false,
+            });
+            match binding {
+                AxisBinding::Axis { axis: to, invert } => {
+                    let value = if invert {
+                        invert_axis(to, value)
+                    } else {
+                        value
+                    };
+                    merge_axis(&mut output, to, value);
+                }
+                AxisBinding::Button { button, threshold } => {
+                    let past = if threshold < 0 {
+                        value <= threshold
+                    } else {
+                        value >= threshold
+                    };
+                    if past {
+                        output.set_button(button, true);
+                    }
+                }
+                AxisBinding::Disabled => {}
+            }
+        }
+
+        for button in ControllerState::BUTTONS {
+            if input.button(button) {
+                let binding = self.buttons.get(&button).copied();
+                press(&mut output, binding.unwrap_or(Binding::Button(button)));
+            }
+        }
+
+        for scancode in keys {
+            if let Some(&binding) = self.keys.get(scancode) {
+                press(&mut output, binding);
+            }
+        }
+
+        output
+    }
+}
+
+fn press(output: &mut ControllerState, binding: Binding) {
+    match binding {
+        Binding::Button(button) => output.set_button(button, true),
+        Binding::Axis { axis, value } => merge_axis(output, axis, value),
+        Binding::Disabled => {}
+    }
+}
+
+/// Triggers only go one way, so a stick pushed left or a negative `value`
+/// bound to one leaves it released.
+fn merge_axis(output: &mut ControllerState, axis: Axis, value: i16) {
+    let value = match axis {
+        Axis::TriggerLeft | Axis::TriggerRight => value.max(0),
+        _ => value,
+    };
+    if value.unsigned_abs() > output.axis(axis).unsigned_abs() {
+        output.set_axis(axis, value);
+    }
+}
+
+/// Flips a stick around its centre, or a trigger end to end.
+fn invert_axis(axis: Axis, value: i16) -> i16 {
+    match axis {
+        Axis::TriggerLeft | Axis::TriggerRight => i16::MAX - value.max(0),
+        _ => value.saturating_neg(),
+    }
+}
+
+fn parse_button(name: &str) -> anyhow::Result<Button> {
+    Button::from_string(name).ok_or_else(|| anyhow!("unknown button {:?}", name))
+}
+
+fn parse_axis(name: &str) -> anyhow::Result<Axis> {
+    Axis::from_string(name).ok_or_else(|| anyhow!("unknown axis {:?}", name))
+}
+
+fn resolve_binding(raw: RawBinding) -> anyhow::Result<Binding> {
+    match raw {
+        RawBinding::Name(name) if name == "none" => Ok(Binding::Disabled),
+        RawBinding::Name(name) => match Button::from_string(&name) {
+            Some(button) => Ok(Binding::Button(button)),
+            None => Ok(Binding::Axis {
+                axis: parse_axis(&name)
+                    .map_err(|_| anyhow!("unknown button or axis {:?}", name))?,
+                value: i16::MAX,
+            }),
+        },
+        RawBinding::Table(target) => {
+            if target.threshold.is_some() || target.invert.is_some() {
+                bail!("`threshold` and `invert` only apply to axes");
+            }
+            match (target.button, target.axis) {
+                (Some(button), None) if target.value.is_none() => {
+                    Ok(Binding::Button(parse_button(&button)?))
+                }
+                (None, Some(axis)) => Ok(Binding::Axis {
+                    axis: parse_axis(&axis)?,
+                    value: target.value.unwrap_or(i16::MAX),
+                }),
+                _ => bail!("expected either `button` or `axis` and `value`"),
+            }
+        }
+    }
+}
+
+fn resolve_axis_binding(from: Axis, raw: RawBinding) -> anyhow::Result<AxisBinding> {
+    match raw {
+        RawBinding::Name(name) if name == "none" => Ok(AxisBinding::Disabled),
+        RawBinding::Name(name) => match Axis::from_string(&name) {
+            Some(axis) => Ok(AxisBinding::Axis {
+                axis,
+                invert: false,
+            }),
+            None => Ok(AxisBinding::Button {
+                button: parse_button(&name)
+                    .map_err(|_| anyhow!("unknown button or axis {:?}", name))?,
+                threshold: DEFAULT_THRESHOLD,
+            }),
+        },
+        RawBinding::Table(target) => {
+            if target.value.is_some() {
+                bail!("`value` only applies to buttons and keys");
+            }
+            match (target.button, target.axis) {
+                (Some(button), None) if target.invert.is_none() => Ok(AxisBinding::Button {
+                    button: parse_button(&button)?,
+                    threshold: target.threshold.unwrap_or(DEFAULT_THRESHOLD),
+                }),
+                (None, axis) if target.threshold.is_none() => Ok(AxisBinding::Axis {
+                    axis: axis.as_deref().map(parse_axis).transpose()?.unwrap_or(from),
+                    invert: target.invert.unwrap_or(false),
+                }),
+                _ => bail!("expected either `button` and `threshold` or `axis` and `invert`"),
+            }
+        }
+    }
+}
+
+/// A profile loaded from disk and shared with everything using it, so
+/// changes to the file are picked up by [`ProfileFile::reload_if_changed`].
+pub struct ProfileFile {
+    path: PathBuf,
+    modified: Option<SystemTime>,
+    profile: Rc<RefCell<Profile>>,
+}
+
+impl ProfileFile {
+    pub fn load(path: impl Into<PathBuf>) -> anyhow::Result<Self> {
+        let path = path.into();
+        let modified = modified(&path);
+        let profile = Profile::load(&path)?;
+        Ok(ProfileFile {
+            path,
+            modified,
+            profile: Rc::new(RefCell::new(profile)),
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// The profile as of the last successful load.
+    pub fn profile(&self) -> Rc<RefCell<Profile>> {
+        self.profile.clone()
+    }
+
+    /// Reloads the profile if the file was modified since the last load,
+    /// returning whether it did. A file that doesn't parse leaves the
+    /// previous profile in place.
+    pub fn reload_if_changed(&mut self) -> anyhow::Result<bool> {
+        let modified = modified(&self.path);
+        if modified == self.modified {
+            return Ok(false);
+        }
+        self.modified = modified;
+        *self.profile.borrow_mut() = Profile::load(&self.path)?;
+        Ok(true)
+    }
+}
+
+fn modified(path: &Path) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+/// Tracks what is held on a physical controller and the keyboard, and what
+/// the pad should look like once remapped.
+#[derive(Default)]
+pub struct Remapper {
+    profile: Option<Rc<RefCell<Profile>>>,
+    input: ControllerState,
+    keys: HashSet<Scancode>,
+    socd: SocdResolver,
+}
+
+impl Remapper {
+    pub fn new(profile: Option<Rc<RefCell<Profile>>>) -> Self {
+        Remapper {
+            profile,
+            ..Default::default()
+        }
+    }
+
+    pub fn set_profile(&mut self, profile: Option<Rc<RefCell<Profile>>>) {
+        self.profile = profile;
+        self.keys.clear();
+    }
+
+    pub fn set_button(&mut self, button: Button, value: bool) {
+        self.input.set_button(button, value);
+    }
+
+    pub fn set_axis(&mut self, axis: Axis, value: i16) {
+        self.input.set_axis(axis, value);
+    }
+
+    pub fn set_state(&mut self, state: ControllerState) {
+        self.input = state;
+    }
+
+    /// Applies a keyboard key if the profile binds it, returning whether it
+    /// did. Keys the profile doesn't bind should go to the keyboard instead.
+    pub fn set_key(&mut self, scancode: Scancode, value: bool) -> bool {
+        if !value {
+            // Also lets go of keys whose binding was reloaded away since.
+            return self.keys.remove(&scancode);
+        }
+        let bound = self
+            .profile
+            .as_ref()
+            .is_some_and(|profile| profile.borrow().keys.contains_key(&scancode));
+        if bound {
+            self.keys.insert(scancode);
+        }
+        bound
+    }
+
+    /// What the pad should currently report.
+    pub fn output(&mut self) -> ControllerState {
+        let Some(profile) = &self.profile else {
+            return self.input;
+        };
+        let profile = profile.borrow();
+        let mut output = profile.apply(&self.input, &self.keys);
+        self.socd.resolve(profile.socd, &mut output);
+        output
+    }
+}
+
+/// A [`VirtualPad`] whose inputs go through a [`Remapper`] and then the
+/// profile's turbo buttons and macros.
+pub struct RemappedPad {
+    pad: Box<dyn VirtualPad>,
+    profile: Rc<RefCell<Profile>>,
+    remapper: Remapper,
+    macros: MacroEngine,
+    /// What was last written to the pad.
+    output: Option<ControllerState>,
+}
+
+impl RemappedPad {
+    pub fn new(pad: Box<dyn VirtualPad>, profile: Rc<RefCell<Profile>>) -> Self {
+        RemappedPad {
+            pad,
+            remapper: Remapper::new(Some(profile.clone())),
+            profile,
+            macros: MacroEngine::default(),
+            output: None,
+        }
+    }
+
+    fn write(&mut self, output: ControllerState) {
+        for button in ControllerState::BUTTONS {
+            self.pad.set_button(button, output.button(button));
+        }
+        for axis in ControllerState::AXES {
+            self.pad.set_axis(axis, output.axis(axis));
+        }
+        self.pad.submit_report();
+        self.output = Some(output);
+    }
+}
+
+impl VirtualPad for RemappedPad {
+    fn set_button(&mut self, button: Button, value: bool) {
+        self.remapper.set_button(button, value);
+    }
+
+    fn set_axis(&mut self, axis: Axis, value: i16) {
+        self.remapper.set_axis(axis, value);
+    }
+
+    fn submit_report(&mut self) {
+        let input = self.remapper.output();
+        let output = self
+            .macros
+            .apply(&self.profile.borrow(), input, Instant::now());
+        self.write(output);
+    }
+
+    fn tick(&mut self, now: Instant) -> Option<Instant> {
+        let profile = self.profile.clone();
+        let profile = profile.borrow();
+        let input = self.remapper.output();
+        let output = self.macros.apply(&profile, input, now);
+        if self.output != Some(output) {
+            self.write(output);
+        }
+        self.macros.next_change(&profile, now)
+    }
+
+    fn set_motion(&mut self, timestamp: u64, gyro: [f32; 3], accel: [f32; 3]) {
+        self.pad.set_motion(timestamp, gyro, accel);
+    }
+
+    fn set_touch(&mut self, point: TouchPoint) {
+        self.pad.set_touch(point);
+    }
+
+    fn set_key(&mut self, scancode: Scancode, value: bool) -> bool {
+        self.remapper.set_key(scancode, value)
+    }
+
+    fn unplug(&mut self) {
+        self.pad.unplug();
+    }
+
+    fn poll_feedback(&mut self) -> Option<Feedback> {
+        self.pad.poll_feedback()
+    }
+}
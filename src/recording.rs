@@ -0,0 +1,241 @@
+//! Recording of the events a broadcaster sends and their replay against a
+//! listener, e.g. to reproduce a bug.
+//!
+//! A recording is a sequence of [`Record`]s, stored as JSON lines when the
+//! file name ends in `.jsonl` and as COBS framed postcard otherwise.
+
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::{self, BufRead, BufReader, BufWriter, Write},
+    net::SocketAddr,
+    ops::RangeInclusive,
+    path::Path,
+    thread,
+    time::{Duration, Instant},
+};
+
+use anyhow::{bail, Context};
+use log::warn;
+use postcard::{from_bytes_cobs, to_allocvec_cobs};
+use serde::{Deserialize, Serialize};
+
+use crate::{client::Client, IolEvent, PadTarget};
+
+/// Longest a replay sleeps at once, so the session stays alive through long
+/// pauses.
+const REPLAY_SLEEP: Duration = Duration::from_millis(100);
+
+/// The replay speeds [`replay`] accepts, as a factor of the recorded one.
+pub const REPLAY_SPEEDS: RangeInclusive<f64> = 0.01..=1000.0;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Postcard,
+    JsonLines,
+}
+
+impl Format {
+    pub fn of(path: &Path) -> Self {
+        match path.extension() {
+            Some(extension) if extension == "jsonl" => Format::JsonLines,
+            _ => Format::Postcard,
+        }
+    }
+}
+
+/// An event as it was sent.
+#[derive(Deserialize, Debug)]
+pub struct Record {
+    /// Since the recording started, in microseconds.
+    pub time: u64,
+    pub event: IolEvent,
+}
+
+/// Same layout as [`Record`], without taking the event.
+#[derive(Serialize)]
+struct RecordRef<'a> {
+    time: u64,
+    event: &'a IolEvent,
+}
+
+/// Writes events to a recording as they are sent.
+pub struct Recorder {
+    writer: BufWriter<File>,
+    format: Format,
+    start: Instant,
+}
+
+impl Recorder {
+    pub fn create(path: impl AsRef<Path>) -> io::Result<Self> {
+        let path = path.as_ref();
+        Ok(Recorder {
+            writer: BufWriter::new(File::create(path)?),
+            format: Format::of(path),
+            start: Instant::now(),
+        })
+    }
+
+    pub fn record(&mut self, event: &IolEvent) -> io::Result<()> {
+        let record = RecordRef {
+            time: self.start.elapsed().as_micros() as u64,
+            event,
+        };
+        match self.format {
+            Format::Postcard => {
+                let bytes = to_allocvec_cobs(&record)
+                    .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+                self.writer.write_all(&bytes)
+            }
+            Format::JsonLines => {
+                serde_json::to_writer(&mut self.writer, &record)?;
+                self.writer.write_all(b"\n")
+            }
+        }
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+/// Reads back the records of a recording, in order.
+pub struct Recording {
+    reader: BufReader<File>,
+    format: Format,
+    buf: Vec<u8>,
+}
+
+impl Recording {
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        let path = path.as_ref();
+        Ok(Recording {
+            reader: BufReader::new(File::open(path)?),
+            format: Format::of(path),
+            buf: vec![],
+        })
+    }
+
+    fn read(&mut self) -> anyhow::Result<Option<Record>> {
+        let delimiter = match self.format {
+            Format::Postcard => 0,
+            Format::JsonLines => b'\n',
+        };
+        loop {
+            self.buf.clear();
+            if self.reader.read_until(delimiter, &mut self.buf)? == 0 {
+                return Ok(None);
+            }
+            if self.buf.iter().all(u8::is_ascii_whitespace) {
+                continue;
+            }
+            let record = match self.format {
+                Format::Postcard => from_bytes_cobs(&mut self.buf)?,
+                Format::JsonLines => serde_json::from_slice(&self.buf)?,
+            };
+            return Ok(Some(record));
+        }
+    }
+}
+
+impl Iterator for Recording {
+    type Item = anyhow::Result<Record>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.read().transpose()
+    }
+}
+
+/// Resends `records` to the listener at `server_address` through `client`
+/// with their original timing, `speed` times as fast.
+///
+/// The recorded handshakes are replayed too, and the pads the recording
+/// registered are registered again under whatever ids the listener hands
+/// out this time.
+pub fn replay(
+    client: &mut Client,
+    server_address: SocketAddr,
+    records: impl IntoIterator<Item = anyhow::Result<Record>>,
+    speed: f64,
+) -> anyhow::Result<()> {
+    if !REPLAY_SPEEDS.contains(&speed) {
+        bail!(
+            "the replay speed must be from {} to {}",
+            REPLAY_SPEEDS.start(),
+            REPLAY_SPEEDS.end()
+        );
+    }
+
+    let start = Instant::now();
+    // Recorded network id of each pad to the one it was given now.
+    let mut ids: HashMap<u32, u32> = HashMap::new();
+    // What each recorded pad asked for, by `which`.
+    let mut devices: HashMap<u32, (Option<PadTarget>, String)> = HashMap::new();
+
+    for record in records {
+        let mut record = record.context("unable to read the recording")?;
+        let due = start + Duration::from_micros(record.time).div_f64(speed);
+        loop {
+            client.keep_alive()?;
+            while client.try_recv()?.is_some() {}
+            let now = Instant::now();
+            if now >= due {
+                break;
+            }
+            thread::sleep((due - now).min(REPLAY_SLEEP));
+        }
+
+        match record.event {
+            IolEvent::Hello { capabilities, .. } => {
+                client.connect(server_address, capabilities)?;
+                ids.clear();
+                devices.clear();
+            }
+            IolEvent::Disconnect => client.disconnect(),
+            IolEvent::Heartbeat => {}
+            IolEvent::PhysicalDeviceAdded {
+                which,
+                target,
+                name,
+            } => {
+                devices.insert(which, (target, name));
+            }
+            IolEvent::VirtualDeviceAdded { id, which } => {
+                let (target, name) = devices.get(&which).cloned().unwrap_or_default();
+                ids.insert(id, client.register_device(which, target, &name)?);
+            }
+            ref mut event => {
+                if !client.is_connected() {
+                    bail!("the recording sends {:?} before connecting", event);
+                }
+                if let Some(id) = device_id(event) {
+                    match ids.get(id) {
+                        Some(&new_id) => *id = new_id,
+                        None => {
+                            warn!("Skipping {:?} for a pad that was never registered", event);
+                            continue;
+                        }
+                    }
+                }
+                client.send(record.event)?;
+            }
+        }
+    }
+
+    client.disconnect();
+    Ok(())
+}
+
+/// The network id of the pad `event` is about, if any.
+fn device_id(event: &mut IolEvent) -> Option<&mut u32> {
+    match event {
+        IolEvent::ButtonUp { id, .. }
+        | IolEvent::ButtonDown { id, .. }
+        | IolEvent::AxisMotion { id, .. }
+        | IolEvent::PhysicalDeviceRemoved { id }
+        | IolEvent::ControllerState { id, .. }
+        | IolEvent::Motion { id, .. }
+        | IolEvent::Touch { id, .. } => Some(id),
+        _ => None,
+    }
+}
@@ -0,0 +1,56 @@
+use std::{net::SocketAddr, path::PathBuf};
+
+use clap::Parser;
+use iol::{
+    client::{resolve_address, Client},
+    recording::{self, Recording, REPLAY_SPEEDS},
+};
+
+/// Replays a recording made with `iol-broadcast --record` against a listener.
+#[derive(Parser)]
+#[command(version)]
+struct Args {
+    /// The recording, JSON lines if it ends in `.jsonl` and postcard otherwise.
+    recording: PathBuf,
+    /// Address or host name of the listener, e.g. 192.168.1.12:4863 or
+    /// desk.local, on port 4863 unless given.
+    server: String,
+    /// How many times faster than recorded to replay, from 0.01 to 1000.
+    #[arg(long, default_value_t = 1.0, value_parser = parse_speed)]
+    speed: f64,
+    /// Local address to send from.
+    #[arg(long, default_value = "0.0.0.0:0")]
+    bind: SocketAddr,
+}
+
+fn parse_speed(s: &str) -> Result<f64, String> {
+    let speed: f64 = s.parse().map_err(|e| format!("{}", e))?;
+    if !REPLAY_SPEEDS.contains(&speed) {
+        return Err(format!(
+            "expected a speed from {} to {}",
+            REPLAY_SPEEDS.start(),
+            REPLAY_SPEEDS.end()
+        ));
+    }
+    Ok(speed)
+}
+
+fn main() -> anyhow::Result<()> {
+    env_logger::init();
+
+    let args = Args::parse();
+    let server_address = resolve_address(&args.server)?;
+    let recording = Recording::open(&args.recording)?;
+    let mut client = Client::bind(args.bind)?;
+
+    println!(
+        "Replaying {} to {} at {}x speed",
+        args.recording.display(),
+        server_address,
+        args.speed
+    );
+    recording::replay(&mut client, server_address, recording, args.speed)?;
+    println!("Done.");
+
+    Ok(())
+}
@@ -0,0 +1,253 @@
+use std::mem;
+
+use sdl2::{keyboard::Scancode, mouse::MouseButton};
+use windows_sys::Win32::UI::Input::KeyboardAndMouse::{
+    SendInput, INPUT, INPUT_0, INPUT_KEYBOARD, INPUT_MOUSE, KEYBDINPUT, KEYBD_EVENT_FLAGS,
+    KEYEVENTF_EXTENDEDKEY, KEYEVENTF_KEYUP, KEYEVENTF_SCANCODE, MOUSEEVENTF_HWHEEL,
+    MOUSEEVENTF_LEFTDOWN, MOUSEEVENTF_LEFTUP, MOUSEEVENTF_MIDDLEDOWN, MOUSEEVENTF_MIDDLEUP,
+    MOUSEEVENTF_MOVE, MOUSEEVENTF_RIGHTDOWN, MOUSEEVENTF_RIGHTUP, MOUSEEVENTF_WHEEL,
+    MOUSEEVENTF_XDOWN, MOUSEEVENTF_XUP, MOUSEINPUT, MOUSE_EVENT_FLAGS, VIRTUAL_KEY, VK_PAUSE,
+};
+
+// Not exported by windows-sys under the features we enable.
+const WHEEL_DELTA: i32 = 120;
+const XBUTTON1: i32 = 0x0001;
+const XBUTTON2: i32 = 0x0002;
+
+/// Injects a key press or release through `SendInput`.
+///
+/// Keys are sent as hardware scancodes rather than virtual keys, so games
+/// reading raw input or DirectInput see them too.
+pub fn send_key(scancode: Scancode, value: bool) {
+    // Pause is `E1 1D 45`, which `KEYEVENTF_SCANCODE` has no way to express,
+    // so it goes through its virtual key instead.
+    if scancode == Scancode::Pause {
+        let flags = if value { 0 } else { KEYEVENTF_KEYUP };
+        send_keyboard(VK_PAUSE, 0x45, flags);
+        return;
+    }
+
+    let Some((scan, extended)) = scancode_to_set1(scancode) else {
+        return;
+    };
+
+    let mut flags = KEYEVENTF_SCANCODE;
+    if extended {
+        flags |= KEYEVENTF_EXTENDEDKEY;
+    }
+    if !value {
+        flags |= KEYEVENTF_KEYUP;
+    }
+
+    send_keyboard(0, scan, flags);
+}
+
+fn send_keyboard(vk: VIRTUAL_KEY, scan: u16, flags: KEYBD_EVENT_FLAGS) {
+    let input = INPUT {
+        r#type: INPUT_KEYBOARD,
+        Anonymous: INPUT_0 {
+            ki: KEYBDINPUT {
+                wVk: vk,
+                wScan: scan,
+                dwFlags: flags,
+                time: 0,
+                dwExtraInfo: 0,
+            },
+        },
+    };
+
+    send(&input);
+}
+
+pub fn send_mouse_motion(dx: i32, dy: i32) {
+    send_mouse(dx, dy, 0, MOUSEEVENTF_MOVE);
+}
+
+pub fn send_mouse_button(button: MouseButton, value: bool) {
+    let (flags, data) = match (button, value) {
+        (MouseButton::Left, true) => (MOUSEEVENTF_LEFTDOWN, 0),
+        (MouseButton::Left, false) => (MOUSEEVENTF_LEFTUP, 0),
+        (MouseButton::Right, true) => (MOUSEEVENTF_RIGHTDOWN, 0),
+        (MouseButton::Right, false) => (MOUSEEVENTF_RIGHTUP, 0),
+        (MouseButton::Middle, true) => (MOUSEEVENTF_MIDDLEDOWN, 0),
+        (MouseButton::Middle, false) => (MOUSEEVENTF_MIDDLEUP, 0),
+        (MouseButton::X1, true) => (MOUSEEVENTF_XDOWN, XBUTTON1),
+        (MouseButton::X1, false) => (MOUSEEVENTF_XUP, XBUTTON1),
+        (MouseButton::X2, true) => (MOUSEEVENTF_XDOWN, XBUTTON2),
+        (MouseButton::X2, false) => (MOUSEEVENTF_XUP, XBUTTON2),
+        (MouseButton::Unknown, _) => return,
+    };
+    send_mouse(0, 0, data, flags);
+}
+
+pub fn send_mouse_wheel(x: i32, y: i32) {
+    if y != 0 {
+        send_mouse(0, 0, y * WHEEL_DELTA, MOUSEEVENTF_WHEEL);
+    }
+    if x != 0 {
+        send_mouse(0, 0, x * WHEEL_DELTA, MOUSEEVENTF_HWHEEL);
+    }
+}
+
+fn send_mouse(dx: i32, dy: i32, data: i32, flags: MOUSE_EVENT_FLAGS) {
+    let input = INPUT {
+        r#type: INPUT_MOUSE,
+        Anonymous: INPUT_0 {
+            mi: MOUSEINPUT {
+                dx,
+                dy,
+                mouseData: data,
+                dwFlags: flags,
+                time: 0,
+                dwExtraInfo: 0,
+            },
+        },
+    };
+
+    send(&input);
+}
+
+fn send(input: &INPUT) {
+    unsafe {
+        SendInput(1, input, mem::size_of::<INPUT>() as i32);
+    }
+}
+
+/// Maps an SDL2 (USB HID usage) scancode onto a PS/2 set 1 scancode and
+/// whether it needs the `0xE0` extended prefix.
+pub fn scancode_to_set1(scancode: Scancode) -> Option<(u16, bool)> {
+    let key = match scancode {
+        Scancode::A => (0x1e, false),
+        Scancode::B => (0x30, false),
+        Scancode::C => (0x2e, false),
+        Scancode::D => (0x20, false),
+        Scancode::E => (0x12, false),
+        Scancode::F => (0x21, false),
+        Scancode::G => (0x22, false),
+        Scancode::H => (0x23, false),
+        Scancode::I => (0x17, false),
+        Scancode::J => (0x24, false),
+        Scancode::K => (0x25, false),
+        Scancode::L => (0x26, false),
+        Scancode::M => (0x32, false),
+        Scancode::N => (0x31, false),
+        Scancode::O => (0x18, false),
+        Scancode::P => (0x19, false),
+        Scancode::Q => (0x10, false),
+        Scancode::R => (0x13, false),
+        Scancode::S => (0x1f, false),
+        Scancode::T => (0x14, false),
+        Scancode::U => (0x16, false),
+        Scancode::V => (0x2f, false),
+        Scancode::W => (0x11, false),
+        Scancode::X => (0x2d, false),
+        Scancode::Y => (0x15, false),
+        Scancode::Z => (0x2c, false),
+        Scancode::Num1 => (0x02, false),
+        Scancode::Num2 => (0x03, false),
+        Scancode::Num3 => (0x04, false),
+        Scancode::Num4 => (0x05, false),
+        Scancode::Num5 => (0x06, false),
+        Scancode::Num6 => (0x07, false),
+        Scancode::Num7 => (0x08, false),
+        Scancode::Num8 => (0x09, false),
+        Scancode::Num9 => (0x0a, false),
+        Scancode::Num0 => (0x0b, false),
+        Scancode::Return => (0x1c, false),
+        Scancode::Escape => (0x01, false),
+        Scancode::Backspace => (0x0e, false),
+        Scancode::Tab => (0x0f, false),
+        Scancode::Space => (0x39, false),
+        Scancode::Minus => (0x0c, false),
+        Scancode::Equals => (0x0d, false),
+        Scancode::LeftBracket => (0x1a, false),
+        Scancode::RightBracket => (0x1b, false),
+        Scancode::Backslash => (0x2b, false),
+        Scancode::NonUsHash => (0x2b, false),
+        Scancode::Semicolon => (0x27, false),
+        Scancode::Apostrophe => (0x28, false),
+        Scancode::Grave => (0x29, false),
+        Scancode::Comma => (0x33, false),
+        Scancode::Period => (0x34, false),
+        Scancode::Slash => (0x35, false),
+        Scancode::CapsLock => (0x3a, false),
+        Scancode::F1 => (0x3b, false),
+        Scancode::F2 => (0x3c, false),
+        Scancode::F3 => (0x3d, false),
+        Scancode::F4 => (0x3e, false),
+        Scancode::F5 => (0x3f, false),
+        Scancode::F6 => (0x40, false),
+        Scancode::F7 => (0x41, false),
+        Scancode::F8 => (0x42, false),
+        Scancode::F9 => (0x43, false),
+        Scancode::F10 => (0x44, false),
+        Scancode::F11 => (0x57, false),
+        Scancode::F12 => (0x58, false),
+        Scancode::PrintScreen => (0x37, true),
+        Scancode::ScrollLock => (0x46, false),
+        Scancode::Insert => (0x52, true),
+        Scancode::Home => (0x47, true),
+        Scancode::PageUp => (0x49, true),
+        Scancode::Delete => (0x53, true),
+        Scancode::End => (0x4f, true),
+        Scancode::PageDown => (0x51, true),
+        Scancode::Right => (0x4d, true),
+        Scancode::Left => (0x4b, true),
+        Scancode::Down => (0x50, true),
+        Scancode::Up => (0x48, true),
+        Scancode::NumLockClear => (0x45, true),
+        Scancode::KpDivide => (0x35, true),
+        Scancode::KpMultiply => (0x37, false),
+        Scancode::KpMinus => (0x4a, false),
+        Scancode::KpPlus => (0x4e, false),
+        Scancode::KpEnter => (0x1c, true),
+        Scancode::Kp1 => (0x4f, false),
+        Scancode::Kp2 => (0x50, false),
+        Scancode::Kp3 => (0x51, false),
+        Scancode::Kp4 => (0x4b, false),
+        Scancode::Kp5 => (0x4c, false),
+        Scancode::Kp6 => (0x4d, false),
+        Scancode::Kp7 => (0x47, false),
+        Scancode::Kp8 => (0x48, false),
+        Scancode::Kp9 => (0x49, false),
+        Scancode::Kp0 => (0x52, false),
+        Scancode::KpPeriod => (0x53, false),
+        Scancode::KpEquals => (0x59, false),
+        Scancode::NonUsBackslash => (0x56, false),
+        Scancode::Application => (0x5d, true),
+        Scancode::F13 => (0x64, false),
+        Scancode::F14 => (0x65, false),
+        Scancode::F15 => (0x66, false),
+        Scancode::F16 => (0x67, false),
+        Scancode::F17 => (0x68, false),
+        Scancode::F18 => (0x69, false),
+        Scancode::F19 => (0x6a, false),
+        Scancode::F20 => (0x6b, false),
+        Scancode::F21 => (0x6c, false),
+        Scancode::F22 => (0x6d, false),
+        Scancode::F23 => (0x6e, false),
+        Scancode::F24 => (0x76, false),
+        Scancode::International1 => (0x73, false),
+        Scancode::International2 => (0x70, false),
+        Scancode::International3 => (0x7d, false),
+        Scancode::International4 => (0x79, false),
+        Scancode::International5 => (0x7b, false),
+        Scancode::LCtrl => (0x1d, false),
+        Scancode::LShift => (0x2a, false),
+        Scancode::LAlt => (0x38, false),
+        Scancode::LGui => (0x5b, true),
+        Scancode::RCtrl => (0x1d, true),
+        Scancode::RShift => (0x36, false),
+        Scancode::RAlt => (0x38, true),
+        Scancode::RGui => (0x5c, true),
+        Scancode::Mute | Scancode::AudioMute => (0x20, true),
+        Scancode::VolumeDown => (0x2e, true),
+        Scancode::VolumeUp => (0x30, true),
+        Scancode::AudioNext => (0x19, true),
+        Scancode::AudioPrev => (0x10, true),
+        Scancode::AudioStop => (0x24, true),
+        Scancode::AudioPlay => (0x22, true),
+        _ => return None,
+    };
+    Some(key)
+}
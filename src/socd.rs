@@ -0,0 +1,156 @@
+//! Cleaning of simultaneous opposing cardinal directions (SOCD), e.g. Left
+//! and Right held together on a hitbox or an arcade stick.
+
+use std::{cell::Cell, rc::Rc, time::Instant};
+
+use sdl2::{
+    controller::{Axis, Button},
+    keyboard::Scancode,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    backend::{Feedback, VirtualPad},
+    ControllerState, TouchPoint,
+};
+
+/// What a pair of opposing directions resolves to while both are held.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "kebab-case")]
+pub enum SocdPolicy {
+    /// Both directions go through untouched.
+    #[default]
+    Off,
+    /// Neither direction is held.
+    Neutral,
+    /// The direction pressed most recently wins.
+    LastInputWins,
+    /// Up wins over Down. Left and Right cancel out as with `Neutral`.
+    UpPriority,
+}
+
+/// The policy of each axis of the D-pad.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(default, deny_unknown_fields)]
+pub struct Socd {
+    pub horizontal: SocdPolicy,
+    pub vertical: SocdPolicy,
+}
+
+/// Opposing D-pad buttons, the first one being the one up priority favours.
+const AXES: [[Button; 2]; 2] = [
+    [Button::DPadLeft, Button::DPadRight],
+    [Button::DPadUp, Button::DPadDown],
+];
+const VERTICAL: usize = 1;
+
+/// Applies a [`Socd`] to successive states of the same pad, remembering
+/// which direction was pressed last.
+#[derive(Debug, Default)]
+pub struct SocdResolver {
+    /// The raw directions of each axis, as of the last call.
+    held: [[bool; 2]; 2],
+    /// Index in [`AXES`] of the direction pressed last on each axis.
+    last: [Option<usize>; 2],
+}
+
+impl SocdResolver {
+    /// Rewrites the D-pad of `state` according to `socd`.
+    pub fn resolve(&mut self, socd: Socd, state: &mut ControllerState) {
+        let policies = [socd.horizontal, socd.vertical];
+        for (axis, buttons) in AXES.iter().enumerate() {
+            let held = buttons.map(|button| state.button(button));
+            for (side, &pressed) in held.iter().enumerate() {
+                if pressed && !self.held[axis][side] {
+                    self.last[axis] = Some(side);
+                }
+            }
+            if !held[0] && !held[1] {
+                self.last[axis] = None;
+            }
+            self.held[axis] = held;
+
+            if !(held[0] && held[1]) {
+                continue;
+            }
+            let winner = match policies[axis] {
+                SocdPolicy::Off => continue,
+                SocdPolicy::Neutral => None,
+                SocdPolicy::LastInputWins => self.last[axis],
+                SocdPolicy::UpPriority if axis == VERTICAL => Some(0),
+                SocdPolicy::UpPriority => None,
+            };
+            for (side, &button) in buttons.iter().enumerate() {
+                state.set_button(button, winner == Some(side));
+            }
+        }
+    }
+}
+
+/// A [`VirtualPad`] whose D-pad is cleaned according to a policy that can be
+/// changed while it is plugged in.
+pub struct SocdPad {
+    pad: Box<dyn VirtualPad>,
+    socd: Rc<Cell<Socd>>,
+    resolver: SocdResolver,
+    /// The D-pad as received, before cleaning.
+    dpad: ControllerState,
+}
+
+impl SocdPad {
+    pub fn new(pad: Box<dyn VirtualPad>, socd: Rc<Cell<Socd>>) -> Self {
+        SocdPad {
+            pad,
+            socd,
+            resolver: SocdResolver::default(),
+            dpad: ControllerState::default(),
+        }
+    }
+}
+
+impl VirtualPad for SocdPad {
+    fn set_button(&mut self, button: Button, value: bool) {
+        if AXES.iter().flatten().any(|&direction| direction == button) {
+            self.dpad.set_button(button, value);
+        } else {
+            self.pad.set_button(button, value);
+        }
+    }
+
+    fn set_axis(&mut self, axis: Axis, value: i16) {
+        self.pad.set_axis(axis, value);
+    }
+
+    fn submit_report(&mut self) {
+        let mut dpad = self.dpad;
+        self.resolver.resolve(self.socd.get(), &mut dpad);
+        for &button in AXES.iter().flatten() {
+            self.pad.set_button(button, dpad.button(button));
+        }
+        self.pad.submit_report();
+    }
+
+    fn set_motion(&mut self, timestamp: u64, gyro: [f32; 3], accel: [f32; 3]) {
+        self.pad.set_motion(timestamp, gyro, accel);
+    }
+
+    fn set_touch(&mut self, point: TouchPoint) {
+        self.pad.set_touch(point);
+    }
+
+    fn set_key(&mut self, scancode: Scancode, value: bool) -> bool {
+        self.pad.set_key(scancode, value)
+    }
+
+    fn tick(&mut self, now: Instant) -> Option<Instant> {
+        self.pad.tick(now)
+    }
+
+    fn unplug(&mut self) {
+        self.pad.unplug();
+    }
+
+    fn poll_feedback(&mut self) -> Option<Feedback> {
+        self.pad.poll_feedback()
+    }
+}
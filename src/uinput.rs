@@ -0,0 +1,678 @@
+use std::{collections::HashMap, io};
+
+use evdev::{
+    uinput::{VirtualDevice, VirtualDeviceBuilder},
+    AbsInfo, AbsoluteAxisType, AttributeSet, BusType, EventType, InputEvent, InputId, Key,
+    MiscType, PropType, RelativeAxisType, UinputAbsSetup,
+};
+use log::warn;
+use sdl2::{
+    controller::{Axis, Button},
+    keyboard::Scancode,
+    mouse::MouseButton,
+};
+
+use crate::{
+    backend::{OutputBackend, VirtualPad},
+    Capabilities, PadTarget, TouchPoint,
+};
+
+// Report ourselves as a wired Xbox 360 pad so SDL, Steam and games pick up
+// the standard mapping for it.
+const XBOX360_VENDOR: u16 = 0x045e;
+const XBOX360_PRODUCT: u16 = 0x028e;
+
+// Motion sensor resolutions used by the kernel's hid-playstation driver, in
+// units per g and per degree per second.
+const ACCEL_RESOLUTION: i32 = 8192;
+const ACCEL_RANGE: i32 = 4 * ACCEL_RESOLUTION;
+const GYRO_RESOLUTION: i32 = 1024;
+const GYRO_RANGE: i32 = 2048 * GYRO_RESOLUTION;
+const STANDARD_GRAVITY: f32 = 9.80665;
+
+// Touchpad size of a DualShock 4 and how many fingers it tracks.
+const TOUCHPAD_WIDTH: i32 = 1920;
+const TOUCHPAD_HEIGHT: i32 = 943;
+const TOUCHPAD_FINGERS: usize = 2;
+
+const GAMEPAD_BUTTONS: [Key; 11] = [
+    Key::BTN_SOUTH,
+    Key::BTN_EAST,
+    Key::BTN_NORTH,
+    Key::BTN_WEST,
+    Key::BTN_TL,
+    Key::BTN_TR,
+    Key::BTN_SELECT,
+    Key::BTN_START,
+    Key::BTN_MODE,
+    Key::BTN_THUMBL,
+    Key::BTN_THUMBR,
+];
+
+pub struct UInputBackend {
+    keyboard: UInputKeyboard,
+    mouse: UInputMouse,
+}
+
+impl UInputBackend {
+    pub fn new() -> io::Result<Self> {
+        Ok(UInputBackend {
+            keyboard: UInputKeyboard::new()?,
+            mouse: UInputMouse::new()?,
+        })
+    }
+}
+
+impl OutputBackend for UInputBackend {
+    fn capabilities(&self) -> Capabilities {
+        Capabilities::GAMEPAD
+            .union(Capabilities::KEYBOARD)
+            .union(Capabilities::MOUSE)
+            .union(Capabilities::MOTION)
+    }
+
+    fn plug(&mut self, target: PadTarget) -> anyhow::Result<Box<dyn VirtualPad>> {
+        if target != PadTarget::Xbox360 {
+            warn!(
+                "uinput can't emulate {:?}, plugging in an Xbox 360 pad",
+                target
+            );
+        }
+        Ok(Box::new(UInputState::new()?))
+    }
+
+    fn set_key(&mut self, scancode: Scancode, value: bool) {
+        self.keyboard.from_sdl2_scancode(scancode, value);
+    }
+
+    fn move_mouse(&mut self, dx: i32, dy: i32) {
+        self.mouse.move_relative(dx, dy);
+    }
+
+    fn set_mouse_button(&mut self, button: MouseButton, value: bool) {
+        self.mouse.from_sdl2_button(button, value);
+    }
+
+    fn scroll_mouse(&mut self, x: i32, y: i32) {
+        self.mouse.scroll(x, y);
+    }
+}
+
+/// Input state of the virtual pad, laid out like `vigem_client::XGamepad`.
+///
+/// Thumbstick values use the XInput convention (positive Y is up) and are
+/// converted to the evdev convention when the report is submitted.
+#[derive(Default, Clone, Copy, Debug)]
+pub struct UInputGamepad {
+    pub thumb_lx: i16,
+    pub thumb_ly: i16,
+    pub thumb_rx: i16,
+    pub thumb_ry: i16,
+    pub left_trigger: u8,
+    pub right_trigger: u8,
+    pub dpad_up: bool,
+    pub dpad_down: bool,
+    pub dpad_left: bool,
+    pub dpad_right: bool,
+}
+
+pub struct UInputState {
+    device: VirtualDevice,
+    /// Separate sensor device, paired with the pad by its matching id the
+    /// same way the kernel exposes real motion-capable controllers.
+    motion: VirtualDevice,
+    touchpad: VirtualDevice,
+    /// Tracking id of the contact in each touchpad slot, if touching.
+    fingers: [Option<i32>; TOUCHPAD_FINGERS],
+    next_tracking_id: i32,
+    pub button_state: HashMap<Key, bool>,
+    pub gamepad: UInputGamepad,
+}
+
+impl UInputState {
+    pub fn new() -> io::Result<Self> {
+        let keys: AttributeSet<Key> = GAMEPAD_BUTTONS.iter().collect();
+
+        let stick = AbsInfo::new(0, i16::MIN as i32, i16::MAX as i32, 16, 128, 0);
+        let trigger = AbsInfo::new(0, 0, u8::MAX as i32, 0, 0, 0);
+        let hat = AbsInfo::new(0, -1, 1, 0, 0, 0);
+
+        let input_id = InputId::new(BusType::BUS_USB, XBOX360_VENDOR, XBOX360_PRODUCT, 0x0110);
+
+        let device = VirtualDeviceBuilder::new()?
+            .name("iol Xbox 360 Controller")
+            .input_id(input_id.clone())
+            .with_keys(&keys)?
+            .with_absolute_axis(&UinputAbsSetup::new(AbsoluteAxisType::ABS_X, stick))?
+            .with_absolute_axis(&UinputAbsSetup::new(AbsoluteAxisType::ABS_Y, stick))?
+            .with_absolute_axis(&UinputAbsSetup::new(AbsoluteAxisType::ABS_RX, stick))?
+            .with_absolute_axis(&UinputAbsSetup::new(AbsoluteAxisType::ABS_RY, stick))?
+            .with_absolute_axis(&UinputAbsSetup::new(AbsoluteAxisType::ABS_Z, trigger))?
+            .with_absolute_axis(&UinputAbsSetup::new(AbsoluteAxisType::ABS_RZ, trigger))?
+            .with_absolute_axis(&UinputAbsSetup::new(AbsoluteAxisType::ABS_HAT0X, hat))?
+            .with_absolute_axis(&UinputAbsSetup::new(AbsoluteAxisType::ABS_HAT0Y, hat))?
+            .build()?;
+
+        let accel = AbsInfo::new(0, -ACCEL_RANGE, ACCEL_RANGE, 4, 0, ACCEL_RESOLUTION);
+        let gyro = AbsInfo::new(0, -GYRO_RANGE, GYRO_RANGE, 16, 0, GYRO_RESOLUTION);
+        let properties: AttributeSet<PropType> = [PropType::ACCELEROMETER].iter().collect();
+        let misc: AttributeSet<MiscType> = [MiscType::MSC_TIMESTAMP].iter().collect();
+
+        let motion = VirtualDeviceBuilder::new()?
+            .name("iol Xbox 360 Controller Motion Sensors")
+            .input_id(input_id.clone())
+            .with_properties(&properties)?
+            .with_msc(&misc)?
+            .with_absolute_axis(&UinputAbsSetup::new(AbsoluteAxisType::ABS_X, accel))?
+            .with_absolute_axis(&UinputAbsSetup::new(AbsoluteAxisType::ABS_Y, accel))?
+            .with_absolute_axis(&UinputAbsSetup::new(AbsoluteAxisType::ABS_Z, accel))?
+            .with_absolute_axis(&UinputAbsSetup::new(AbsoluteAxisType::ABS_RX, gyro))?
+            .with_absolute_axis(&UinputAbsSetup::new(AbsoluteAxisType::ABS_RY, gyro))?
+            .with_absolute_axis(&UinputAbsSetup::new(AbsoluteAxisType::ABS_RZ, gyro))?
+            .build()?;
+
+        let touch_keys: AttributeSet<Key> = [
+            Key::BTN_TOUCH,
+            Key::BTN_TOOL_FINGER,
+            Key::BTN_TOOL_DOUBLETAP,
+        ]
+        .iter()
+        .collect();
+        let touch_properties: AttributeSet<PropType> = [PropType::POINTER].iter().collect();
+        let x = AbsInfo::new(0, 0, TOUCHPAD_WIDTH - 1, 0, 0, 0);
+        let y = AbsInfo::new(0, 0, TOUCHPAD_HEIGHT - 1, 0, 0, 0);
+        let slot = AbsInfo::new(0, 0, TOUCHPAD_FINGERS as i32 - 1, 0, 0, 0);
+        let tracking_id = AbsInfo::new(0, -1, i32::MAX, 0, 0, 0);
+
+        let touchpad = VirtualDeviceBuilder::new()?
+            .name("iol Xbox 360 Controller Touchpad")
+            .input_id(input_id)
+            .with_properties(&touch_properties)?
+            .with_keys(&touch_keys)?
+            .with_absolute_axis(&UinputAbsSetup::new(AbsoluteAxisType::ABS_X, x))?
+            .with_absolute_axis(&UinputAbsSetup::new(AbsoluteAxisType::ABS_Y, y))?
+            .with_absolute_axis(&UinputAbsSetup::new(AbsoluteAxisType::ABS_MT_SLOT, slot))?
+            .with_absolute_axis(&UinputAbsSetup::new(
+                AbsoluteAxisType::ABS_MT_TRACKING_ID,
+                tracking_id,
+            ))?
+            .with_absolute_axis(&UinputAbsSetup::new(AbsoluteAxisType::ABS_MT_POSITION_X, x))?
+            .with_absolute_axis(&UinputAbsSetup::new(AbsoluteAxisType::ABS_MT_POSITION_Y, y))?
+            .build()?;
+
+        let button_state = GAMEPAD_BUTTONS.iter().map(|&key| (key, false)).collect();
+
+        let mut state = UInputState {
+            device,
+            motion,
+            touchpad,
+            fingers: [None; TOUCHPAD_FINGERS],
+            next_tracking_id: 0,
+            button_state,
+            gamepad: UInputGamepad::default(),
+        };
+        state.submit_report();
+
+        Ok(state)
+    }
+
+    pub fn submit_report(&mut self) {
+        let gamepad = self.gamepad;
+        let mut messages: Vec<InputEvent> = self
+            .button_state
+            .iter()
+            .map(|(key, &value)| InputEvent::new(EventType::KEY, key.code(), value as i32))
+            .collect();
+
+        let axes = [
+            (AbsoluteAxisType::ABS_X, gamepad.thumb_lx as i32),
+            (AbsoluteAxisType::ABS_Y, invert(gamepad.thumb_ly) as i32),
+            (AbsoluteAxisType::ABS_RX, gamepad.thumb_rx as i32),
+            (AbsoluteAxisType::ABS_RY, invert(gamepad.thumb_ry) as i32),
+            (AbsoluteAxisType::ABS_Z, gamepad.left_trigger as i32),
+            (AbsoluteAxisType::ABS_RZ, gamepad.right_trigger as i32),
+            (
+                AbsoluteAxisType::ABS_HAT0X,
+                hat_value(gamepad.dpad_left, gamepad.dpad_right),
+            ),
+            (
+                AbsoluteAxisType::ABS_HAT0Y,
+                hat_value(gamepad.dpad_up, gamepad.dpad_down),
+            ),
+        ];
+        messages.extend(
+            axes.iter()
+                .map(|(axis, value)| InputEvent::new(EventType::ABSOLUTE, axis.0, *value)),
+        );
+
+        let _ = self.device.emit(&messages);
+    }
+
+    pub fn update_button(&mut self, button: &Key, value: bool) {
+        *self.button_state.get_mut(button).unwrap() = value
+    }
+
+    fn map_sdl2_button(&mut self, button: Button, value: bool) {
+        match button {
+            Button::A => self.update_button(&Key::BTN_SOUTH, value),
+            Button::B => self.update_button(&Key::BTN_EAST, value),
+            Button::X => self.update_button(&Key::BTN_WEST, value),
+            Button::Y => self.update_button(&Key::BTN_NORTH, value),
+            Button::LeftShoulder => self.update_button(&Key::BTN_TL, value),
+            Button::RightShoulder => self.update_button(&Key::BTN_TR, value),
+            Button::LeftStick => self.update_button(&Key::BTN_THUMBL, value),
+            Button::RightStick => self.update_button(&Key::BTN_THUMBR, value),
+            Button::DPadLeft => self.gamepad.dpad_left = value,
+            Button::DPadRight => self.gamepad.dpad_right = value,
+            Button::DPadUp => self.gamepad.dpad_up = value,
+            Button::DPadDown => self.gamepad.dpad_down = value,
+            Button::Guide => self.update_button(&Key::BTN_MODE, value),
+            Button::Back => self.update_button(&Key::BTN_SELECT, value),
+            Button::Start => self.update_button(&Key::BTN_START, value),
+            _ => {}
+        }
+    }
+
+    pub fn from_sdl2_button(&mut self, button: Button, value: bool) {
+        self.map_sdl2_button(button, value);
+        self.submit_report();
+    }
+
+    fn map_sdl2_axis(&mut self, axis: Axis, value: i16) {
+        match axis {
+            Axis::LeftX => self.gamepad.thumb_lx = value,
+            Axis::LeftY => self.gamepad.thumb_ly = value,
+            Axis::RightX => self.gamepad.thumb_rx = value,
+            Axis::RightY => self.gamepad.thumb_ry = value,
+            Axis::TriggerLeft => self.gamepad.left_trigger = (value.max(0) / (32767 / 255)) as u8,
+            Axis::TriggerRight => self.gamepad.right_trigger = (value.max(0) / (32767 / 255)) as u8,
+        }
+    }
+
+    pub fn from_sdl2_axis(&mut self, axis: Axis, value: i16) {
+        self.map_sdl2_axis(axis, value);
+        self.submit_report();
+    }
+}
+
+impl VirtualPad for UInputState {
+    fn set_button(&mut self, button: Button, value: bool) {
+        self.map_sdl2_button(button, value);
+    }
+
+    fn set_axis(&mut self, axis: Axis, value: i16) {
+        self.map_sdl2_axis(axis, value);
+    }
+
+    fn submit_report(&mut self) {
+        UInputState::submit_report(self);
+    }
+
+    fn set_motion(&mut self, timestamp: u64, gyro: [f32; 3], accel: [f32; 3]) {
+        let accel = accel.map(|value| (value / STANDARD_GRAVITY * ACCEL_RESOLUTION as f32) as i32);
+        let gyro = gyro.map(|value| (value.to_degrees() * GYRO_RESOLUTION as f32) as i32);
+
+        let axes = [
+            (AbsoluteAxisType::ABS_X, accel[0]),
+            (AbsoluteAxisType::ABS_Y, accel[1]),
+            (AbsoluteAxisType::ABS_Z, accel[2]),
+            (AbsoluteAxisType::ABS_RX, gyro[0]),
+            (AbsoluteAxisType::ABS_RY, gyro[1]),
+            (AbsoluteAxisType::ABS_RZ, gyro[2]),
+        ];
+        let mut messages: Vec<InputEvent> = axes
+            .iter()
+            .map(|(axis, value)| InputEvent::new(EventType::ABSOLUTE, axis.0, *value))
+            .collect();
+        // MSC_TIMESTAMP is a wrapping 32 bit microsecond counter.
+        messages.push(InputEvent::new(
+            EventType::MISC,
+            MiscType::MSC_TIMESTAMP.0,
+            timestamp as u32 as i32,
+        ));
+
+        let _ = self.motion.emit(&messages);
+    }
+
+    fn set_touch(&mut self, point: TouchPoint) {
+        let slot = point.finger as usize;
+        if point.touchpad != 0 || slot >= TOUCHPAD_FINGERS {
+            return;
+        }
+
+        let mut messages = vec![InputEvent::new(
+            EventType::ABSOLUTE,
+            AbsoluteAxisType::ABS_MT_SLOT.0,
+            slot as i32,
+        )];
+        if point.touching {
+            if self.fingers[slot].is_none() {
+                self.fingers[slot] = Some(self.next_tracking_id);
+                messages.push(InputEvent::new(
+                    EventType::ABSOLUTE,
+                    AbsoluteAxisType::ABS_MT_TRACKING_ID.0,
+                    self.next_tracking_id,
+                ));
+                self.next_tracking_id = self.next_tracking_id.wrapping_add(1) & i32::MAX;
+            }
+            let x = (point.x.clamp(0.0, 1.0) * (TOUCHPAD_WIDTH - 1) as f32) as i32;
+            let y = (point.y.clamp(0.0, 1.0) * (TOUCHPAD_HEIGHT - 1) as f32) as i32;
+            messages.extend([
+                InputEvent::new(
+                    EventType::ABSOLUTE,
+                    AbsoluteAxisType::ABS_MT_POSITION_X.0,
+                    x,
+                ),
+                InputEvent::new(
+                    EventType::ABSOLUTE,
+                    AbsoluteAxisType::ABS_MT_POSITION_Y.0,
+                    y,
+                ),
+                InputEvent::new(EventType::ABSOLUTE, AbsoluteAxisType::ABS_X.0, x),
+                InputEvent::new(EventType::ABSOLUTE, AbsoluteAxisType::ABS_Y.0, y),
+            ]);
+        } else {
+            self.fingers[slot] = None;
+            messages.push(InputEvent::new(
+                EventType::ABSOLUTE,
+                AbsoluteAxisType::ABS_MT_TRACKING_ID.0,
+                -1,
+            ));
+        }
+
+        let count = self.fingers.iter().flatten().count();
+        messages.extend([
+            InputEvent::new(EventType::KEY, Key::BTN_TOUCH.code(), (count > 0) as i32),
+            InputEvent::new(
+                EventType::KEY,
+                Key::BTN_TOOL_FINGER.code(),
+                (count == 1) as i32,
+            ),
+            InputEvent::new(
+                EventType::KEY,
+                Key::BTN_TOOL_DOUBLETAP.code(),
+                (count == 2) as i32,
+            ),
+        ]);
+
+        let _ = self.touchpad.emit(&messages);
+    }
+
+    /// The uinput device itself is destroyed when the state is dropped, so
+    /// this only releases every input to leave nothing held on the host.
+    fn unplug(&mut self) {
+        for value in self.button_state.values_mut() {
+            *value = false;
+        }
+        self.gamepad = UInputGamepad::default();
+        UInputState::submit_report(self);
+    }
+}
+
+pub struct UInputKeyboard {
+    device: VirtualDevice,
+}
+
+impl UInputKeyboard {
+    pub fn new() -> io::Result<Self> {
+        let keys: AttributeSet<Key> = (1..=Key::KEY_MICMUTE.code()).map(Key::new).collect();
+
+        let device = VirtualDeviceBuilder::new()?
+            .name("iol Keyboard")
+            .with_keys(&keys)?
+            .build()?;
+
+        Ok(UInputKeyboard { device })
+    }
+
+    pub fn from_sdl2_scancode(&mut self, scancode: Scancode, value: bool) {
+        if let Some(key) = scancode_to_key(scancode) {
+            let _ = self
+                .device
+                .emit(&[InputEvent::new(EventType::KEY, key.code(), value as i32)]);
+        }
+    }
+}
+
+pub struct UInputMouse {
+    device: VirtualDevice,
+}
+
+impl UInputMouse {
+    pub fn new() -> io::Result<Self> {
+        let keys: AttributeSet<Key> = [
+            Key::BTN_LEFT,
+            Key::BTN_RIGHT,
+            Key::BTN_MIDDLE,
+            Key::BTN_SIDE,
+            Key::BTN_EXTRA,
+        ]
+        .iter()
+        .collect();
+        let axes: AttributeSet<RelativeAxisType> = [
+            RelativeAxisType::REL_X,
+            RelativeAxisType::REL_Y,
+            RelativeAxisType::REL_WHEEL,
+            RelativeAxisType::REL_HWHEEL,
+        ]
+        .iter()
+        .collect();
+
+        let device = VirtualDeviceBuilder::new()?
+            .name("iol Mouse")
+            .with_keys(&keys)?
+            .with_relative_axes(&axes)?
+            .build()?;
+
+        Ok(UInputMouse { device })
+    }
+
+    pub fn move_relative(&mut self, dx: i32, dy: i32) {
+        let _ = self.device.emit(&[
+            InputEvent::new(EventType::RELATIVE, RelativeAxisType::REL_X.0, dx),
+            InputEvent::new(EventType::RELATIVE, RelativeAxisType::REL_Y.0, dy),
+        ]);
+    }
+
+    pub fn from_sdl2_button(&mut self, button: MouseButton, value: bool) {
+        let key = match button {
+            MouseButton::Left => Key::BTN_LEFT,
+            MouseButton::Right => Key::BTN_RIGHT,
+            MouseButton::Middle => Key::BTN_MIDDLE,
+            MouseButton::X1 => Key::BTN_SIDE,
+            MouseButton::X2 => Key::BTN_EXTRA,
+            MouseButton::Unknown => return,
+        };
+        let _ = self
+            .device
+            .emit(&[InputEvent::new(EventType::KEY, key.code(), value as i32)]);
+    }
+
+    pub fn scroll(&mut self, x: i32, y: i32) {
+        let _ = self.device.emit(&[
+            InputEvent::new(EventType::RELATIVE, RelativeAxisType::REL_WHEEL.0, y),
+            InputEvent::new(EventType::RELATIVE, RelativeAxisType::REL_HWHEEL.0, x),
+        ]);
+    }
+}
+
+/// Negates an axis value without overflowing on `i16::MIN`.
+fn invert(value: i16) -> i16 {
+    value.saturating_neg()
+}
+
+fn hat_value(negative: bool, positive: bool) -> i32 {
+    positive as i32 - negative as i32
+}
+
+/// Maps an SDL2 (USB HID usage) scancode onto the matching Linux key code.
+pub fn scancode_to_key(scancode: Scancode) -> Option<Key> {
+    let key = match scancode {
+        Scancode::A => Key::KEY_A,
+        Scancode::B => Key::KEY_B,
+        Scancode::C => Key::KEY_C,
+        Scancode::D => Key::KEY_D,
+        Scancode::E => Key::KEY_E,
+        Scancode::F => Key::KEY_F,
+        Scancode::G => Key::KEY_G,
+        Scancode::H => Key::KEY_H,
+        Scancode::I => Key::KEY_I,
+        Scancode::J => Key::KEY_J,
+        Scancode::K => Key::KEY_K,
+        Scancode::L => Key::KEY_L,
+        Scancode::M => Key::KEY_M,
+        Scancode::N => Key::KEY_N,
+        Scancode::O => Key::KEY_O,
+        Scancode::P => Key::KEY_P,
+        Scancode::Q => Key::KEY_Q,
+        Scancode::R => Key::KEY_R,
+        Scancode::S => Key::KEY_S,
+        Scancode::T => Key::KEY_T,
+        Scancode::U => Key::KEY_U,
+        Scancode::V => Key::KEY_V,
+        Scancode::W => Key::KEY_W,
+        Scancode::X => Key::KEY_X,
+        Scancode::Y => Key::KEY_Y,
+        Scancode::Z => Key::KEY_Z,
+        Scancode::Num1 => Key::KEY_1,
+        Scancode::Num2 => Key::KEY_2,
+        Scancode::Num3 => Key::KEY_3,
+        Scancode::Num4 => Key::KEY_4,
+        Scancode::Num5 => Key::KEY_5,
+        Scancode::Num6 => Key::KEY_6,
+        Scancode::Num7 => Key::KEY_7,
+        Scancode::Num8 => Key::KEY_8,
+        Scancode::Num9 => Key::KEY_9,
+        Scancode::Num0 => Key::KEY_0,
+        Scancode::Return => Key::KEY_ENTER,
+        Scancode::Escape => Key::KEY_ESC,
+        Scancode::Backspace => Key::KEY_BACKSPACE,
+        Scancode::Tab => Key::KEY_TAB,
+        Scancode::Space => Key::KEY_SPACE,
+        Scancode::Minus => Key::KEY_MINUS,
+        Scancode::Equals => Key::KEY_EQUAL,
+        Scancode::LeftBracket => Key::KEY_LEFTBRACE,
+        Scancode::RightBracket => Key::KEY_RIGHTBRACE,
+        Scancode::Backslash => Key::KEY_BACKSLASH,
+        Scancode::NonUsHash => Key::KEY_BACKSLASH,
+        Scancode::Semicolon => Key::KEY_SEMICOLON,
+        Scancode::Apostrophe => Key::KEY_APOSTROPHE,
+        Scancode::Grave => Key::KEY_GRAVE,
+        Scancode::Comma => Key::KEY_COMMA,
+        Scancode::Period => Key::KEY_DOT,
+        Scancode::Slash => Key::KEY_SLASH,
+        Scancode::CapsLock => Key::KEY_CAPSLOCK,
+        Scancode::F1 => Key::KEY_F1,
+        Scancode::F2 => Key::KEY_F2,
+        Scancode::F3 => Key::KEY_F3,
+        Scancode::F4 => Key::KEY_F4,
+        Scancode::F5 => Key::KEY_F5,
+        Scancode::F6 => Key::KEY_F6,
+        Scancode::F7 => Key::KEY_F7,
+        Scancode::F8 => Key::KEY_F8,
+        Scancode::F9 => Key::KEY_F9,
+        Scancode::F10 => Key::KEY_F10,
+        Scancode::F11 => Key::KEY_F11,
+        Scancode::F12 => Key::KEY_F12,
+        Scancode::PrintScreen => Key::KEY_SYSRQ,
+        Scancode::ScrollLock => Key::KEY_SCROLLLOCK,
+        Scancode::Pause => Key::KEY_PAUSE,
+        Scancode::Insert => Key::KEY_INSERT,
+        Scancode::Home => Key::KEY_HOME,
+        Scancode::PageUp => Key::KEY_PAGEUP,
+        Scancode::Delete => Key::KEY_DELETE,
+        Scancode::End => Key::KEY_END,
+        Scancode::PageDown => Key::KEY_PAGEDOWN,
+        Scancode::Right => Key::KEY_RIGHT,
+        Scancode::Left => Key::KEY_LEFT,
+        Scancode::Down => Key::KEY_DOWN,
+        Scancode::Up => Key::KEY_UP,
+        Scancode::NumLockClear => Key::KEY_NUMLOCK,
+        Scancode::KpDivide => Key::KEY_KPSLASH,
+        Scancode::KpMultiply => Key::KEY_KPASTERISK,
+        Scancode::KpMinus => Key::KEY_KPMINUS,
+        Scancode::KpPlus => Key::KEY_KPPLUS,
+        Scancode::KpEnter => Key::KEY_KPENTER,
+        Scancode::Kp1 => Key::KEY_KP1,
+        Scancode::Kp2 => Key::KEY_KP2,
+        Scancode::Kp3 => Key::KEY_KP3,
+        Scancode::Kp4 => Key::KEY_KP4,
+        Scancode::Kp5 => Key::KEY_KP5,
+        Scancode::Kp6 => Key::KEY_KP6,
+        Scancode::Kp7 => Key::KEY_KP7,
+        Scancode::Kp8 => Key::KEY_KP8,
+        Scancode::Kp9 => Key::KEY_KP9,
+        Scancode::Kp0 => Key::KEY_KP0,
+        Scancode::KpPeriod => Key::KEY_KPDOT,
+        Scancode::KpEquals => Key::KEY_KPEQUAL,
+        Scancode::KpComma => Key::KEY_KPCOMMA,
+        Scancode::NonUsBackslash => Key::KEY_102ND,
+        Scancode::Application => Key::KEY_COMPOSE,
+        Scancode::Power => Key::KEY_POWER,
+        Scancode::F13 => Key::KEY_F13,
+        Scancode::F14 => Key::KEY_F14,
+        Scancode::F15 => Key::KEY_F15,
+        Scancode::F16 => Key::KEY_F16,
+        Scancode::F17 => Key::KEY_F17,
+        Scancode::F18 => Key::KEY_F18,
+        Scancode::F19 => Key::KEY_F19,
+        Scancode::F20 => Key::KEY_F20,
+        Scancode::F21 => Key::KEY_F21,
+        Scancode::F22 => Key::KEY_F22,
+        Scancode::F23 => Key::KEY_F23,
+        Scancode::F24 => Key::KEY_F24,
+        Scancode::Help => Key::KEY_HELP,
+        Scancode::Menu => Key::KEY_MENU,
+        Scancode::Stop => Key::KEY_STOP,
+        Scancode::Again => Key::KEY_AGAIN,
+        Scancode::Undo => Key::KEY_UNDO,
+        Scancode::Cut => Key::KEY_CUT,
+        Scancode::Copy => Key::KEY_COPY,
+        Scancode::Paste => Key::KEY_PASTE,
+        Scancode::Find => Key::KEY_FIND,
+        Scancode::Mute => Key::KEY_MUTE,
+        Scancode::VolumeUp => Key::KEY_VOLUMEUP,
+        Scancode::VolumeDown => Key::KEY_VOLUMEDOWN,
+        Scancode::International1 => Key::KEY_RO,
+        Scancode::International2 => Key::KEY_KATAKANAHIRAGANA,
+        Scancode::International3 => Key::KEY_YEN,
+        Scancode::International4 => Key::KEY_HENKAN,
+        Scancode::International5 => Key::KEY_MUHENKAN,
+        Scancode::Lang1 => Key::KEY_HANGEUL,
+        Scancode::Lang2 => Key::KEY_HANJA,
+        Scancode::SysReq => Key::KEY_SYSRQ,
+        Scancode::LCtrl => Key::KEY_LEFTCTRL,
+        Scancode::LShift => Key::KEY_LEFTSHIFT,
+        Scancode::LAlt => Key::KEY_LEFTALT,
+        Scancode::LGui => Key::KEY_LEFTMETA,
+        Scancode::RCtrl => Key::KEY_RIGHTCTRL,
+        Scancode::RShift => Key::KEY_RIGHTSHIFT,
+        Scancode::RAlt => Key::KEY_RIGHTALT,
+        Scancode::RGui => Key::KEY_RIGHTMETA,
+        Scancode::AudioNext => Key::KEY_NEXTSONG,
+        Scancode::AudioPrev => Key::KEY_PREVIOUSSONG,
+        Scancode::AudioStop => Key::KEY_STOPCD,
+        Scancode::AudioPlay => Key::KEY_PLAYPAUSE,
+        Scancode::AudioMute => Key::KEY_MUTE,
+        Scancode::Mail => Key::KEY_MAIL,
+        Scancode::Calculator => Key::KEY_CALC,
+        Scancode::Computer => Key::KEY_COMPUTER,
+        Scancode::AcSearch => Key::KEY_SEARCH,
+        Scancode::AcHome => Key::KEY_HOMEPAGE,
+        Scancode::AcBack => Key::KEY_BACK,
+        Scancode::AcForward => Key::KEY_FORWARD,
+        Scancode::AcStop => Key::KEY_STOP,
+        Scancode::AcRefresh => Key::KEY_REFRESH,
+        Scancode::AcBookmarks => Key::KEY_BOOKMARKS,
+        Scancode::BrightnessDown => Key::KEY_BRIGHTNESSDOWN,
+        Scancode::BrightnessUp => Key::KEY_BRIGHTNESSUP,
+        Scancode::DisplaySwitch => Key::KEY_SWITCHVIDEOMODE,
+        Scancode::KbdIllumToggle => Key::KEY_KBDILLUMTOGGLE,
+        Scancode::KbdIllumDown => Key::KEY_KBDILLUMDOWN,
+        Scancode::KbdIllumUp => Key::KEY_KBDILLUMUP,
+        Scancode::Eject => Key::KEY_EJECTCD,
+        Scancode::Sleep => Key::KEY_SLEEP,
+        _ => return None,
+    };
+    Some(key)
+}
@@ -1,26 +1,99 @@
-use std::{collections::HashMap, rc::Rc};
+use std::{
+    collections::HashMap,
+    rc::Rc,
+    sync::mpsc::{self, Receiver},
+};
 
-use sdl2::controller::{Axis, Button};
+use sdl2::{
+    controller::{Axis, Button},
+    keyboard::Scancode,
+    mouse::MouseButton,
+};
+
+use crate::{
+    backend::{Feedback, OutputBackend, VirtualPad},
+    sendinput, Capabilities, PadTarget, TouchPoint,
+};
+
+/// Plugs Xbox 360 and DualShock 4 pads into the ViGEm bus, and sends keys
+/// and mouse input through `SendInput`.
+///
+/// Motion and touchpad fingers are not supported: they only fit in the DS4's
+/// extended report, which vigem-client 0.1 can't submit. Forwarding them to
+/// DS4 pads is left to the uinput backend until it can.
+pub struct ViGEMBackend {
+    client: Rc<vigem_client::Client>,
+}
+
+impl ViGEMBackend {
+    pub fn new() -> Result<Self, vigem_client::Error> {
+        Ok(ViGEMBackend {
+            client: Rc::new(vigem_client::Client::connect()?),
+        })
+    }
+}
+
+impl OutputBackend for ViGEMBackend {
+    /// No MOTION, see [`ViGEMBackend`]. Broadcasters are told so when they
+    /// connect.
+    fn capabilities(&self) -> Capabilities {
+        Capabilities::GAMEPAD
+            .union(Capabilities::KEYBOARD)
+            .union(Capabilities::MOUSE)
+            .union(Capabilities::FEEDBACK)
+    }
+
+    /// vigem-client only hands out rumble and LED notifications for Xbox 360
+    /// targets, so DS4 pads get no feedback.
+    fn target_capabilities(&self, target: PadTarget) -> Capabilities {
+        match target {
+            PadTarget::Xbox360 => self.capabilities(),
+            PadTarget::DualShock4 => self.capabilities().difference(Capabilities::FEEDBACK),
+        }
+    }
+
+    fn plug(&mut self, target: PadTarget) -> anyhow::Result<Box<dyn VirtualPad>> {
+        Ok(match target {
+            PadTarget::Xbox360 => Box::new(ViGEMState::new(self.client.clone())?),
+            PadTarget::DualShock4 => Box::new(DS4State::new(self.client.clone())?),
+        })
+    }
+
+    fn set_key(&mut self, scancode: Scancode, value: bool) {
+        sendinput::send_key(scancode, value);
+    }
+
+    fn move_mouse(&mut self, dx: i32, dy: i32) {
+        sendinput::send_mouse_motion(dx, dy);
+    }
+
+    fn set_mouse_button(&mut self, button: MouseButton, value: bool) {
+        sendinput::send_mouse_button(button, value);
+    }
+
+    fn scroll_mouse(&mut self, x: i32, y: i32) {
+        sendinput::send_mouse_wheel(x, y);
+    }
+}
 
 pub struct ViGEMState {
     target: vigem_client::Xbox360Wired<Rc<vigem_client::Client>>,
     pub button_state: HashMap<u16, bool>,
     pub gamepad: vigem_client::XGamepad,
-    pub socd_horizontal: bool,
-    pub socd_vertical: bool,
+    feedback: Receiver<Feedback>,
 }
 
 impl ViGEMState {
-    pub fn new(client: Rc<vigem_client::Client>) -> Self {
+    pub fn new(client: Rc<vigem_client::Client>) -> Result<Self, vigem_client::Error> {
         // Create the virtual controller target
         let id = vigem_client::TargetId::XBOX360_WIRED;
         let mut target = vigem_client::Xbox360Wired::new(client, id);
 
         // Plugin the virtual controller
-        target.plugin().unwrap();
+        target.plugin()?;
 
         // Wait for the virtual controller to be ready to accept updates
-        target.wait_ready().unwrap();
+        target.wait_ready()?;
 
         // The input state of the virtual controller
         let gamepad = vigem_client::XGamepad {
@@ -30,6 +103,30 @@ impl ViGEMState {
 
         let _ = target.update(&gamepad);
 
+        // Notifications arrive on their own thread whenever the host changes
+        // the motor speeds or the LED, and always carry both.
+        let (sender, feedback) = mpsc::channel();
+        let mut motors = (0, 0);
+        let mut led_number = None;
+        target
+            .request_notification()?
+            .spawn_thread(move |_, notification| {
+                let new_motors = (notification.large_motor, notification.small_motor);
+                if new_motors != motors {
+                    motors = new_motors;
+                    let _ = sender.send(Feedback::Rumble {
+                        large_motor: notification.large_motor,
+                        small_motor: notification.small_motor,
+                    });
+                }
+                if led_number != Some(notification.led_number) {
+                    led_number = Some(notification.led_number);
+                    let _ = sender.send(Feedback::Led {
+                        number: notification.led_number,
+                    });
+                }
+            });
+
         let mut button_state = HashMap::default();
         button_state.insert(vigem_client::XButtons::UP, false);
         button_state.insert(vigem_client::XButtons::DOWN, false);
@@ -37,6 +134,7 @@ impl ViGEMState {
         button_state.insert(vigem_client::XButtons::LEFT, false);
         button_state.insert(vigem_client::XButtons::START, false);
         button_state.insert(vigem_client::XButtons::BACK, false);
+        button_state.insert(vigem_client::XButtons::GUIDE, false);
         button_state.insert(vigem_client::XButtons::LTHUMB, false);
         button_state.insert(vigem_client::XButtons::RTHUMB, false);
         button_state.insert(vigem_client::XButtons::LB, false);
@@ -46,13 +144,12 @@ impl ViGEMState {
         button_state.insert(vigem_client::XButtons::X, false);
         button_state.insert(vigem_client::XButtons::Y, false);
 
-        ViGEMState {
+        Ok(ViGEMState {
             target: target,
             button_state: button_state,
             gamepad: gamepad,
-            socd_vertical: false,
-            socd_horizontal: false,
-        }
+            feedback,
+        })
     }
 
     pub fn submit_report(&mut self) {
@@ -70,7 +167,7 @@ impl ViGEMState {
         *self.button_state.get_mut(button).unwrap() = value
     }
 
-    pub fn from_sdl2_button(&mut self, button: Button, value: bool) {
+    fn map_sdl2_button(&mut self, button: Button, value: bool) {
         match button {
             Button::A => self.update_button(&vigem_client::XButtons::A, value),
             Button::B => self.update_button(&vigem_client::XButtons::B, value),
@@ -89,18 +186,187 @@ impl ViGEMState {
             Button::Start => self.update_button(&vigem_client::XButtons::START, value),
             _ => {}
         }
+    }
+
+    pub fn from_sdl2_button(&mut self, button: Button, value: bool) {
+        self.map_sdl2_button(button, value);
         self.submit_report();
     }
 
-    pub fn from_sdl2_axis(&mut self, axis: Axis, value: i16) {
+    fn map_sdl2_axis(&mut self, axis: Axis, value: i16) {
         match axis {
             Axis::LeftX => self.gamepad.thumb_lx = value,
             Axis::LeftY => self.gamepad.thumb_ly = value,
             Axis::RightX => self.gamepad.thumb_rx = value,
             Axis::RightY => self.gamepad.thumb_ry = value,
-            Axis::TriggerLeft => self.gamepad.left_trigger = (value / (32767 / 255)) as u8,
-            Axis::TriggerRight => self.gamepad.right_trigger = (value / (32767 / 255)) as u8,
+            Axis::TriggerLeft => self.gamepad.left_trigger = (value.max(0) / (32767 / 255)) as u8,
+            Axis::TriggerRight => self.gamepad.right_trigger = (value.max(0) / (32767 / 255)) as u8,
         }
+    }
+
+    pub fn from_sdl2_axis(&mut self, axis: Axis, value: i16) {
+        self.map_sdl2_axis(axis, value);
         let _ = self.target.update(&self.gamepad);
     }
 }
+
+impl VirtualPad for ViGEMState {
+    fn set_button(&mut self, button: Button, value: bool) {
+        self.map_sdl2_button(button, value);
+    }
+
+    fn set_axis(&mut self, axis: Axis, value: i16) {
+        self.map_sdl2_axis(axis, value);
+    }
+
+    fn submit_report(&mut self) {
+        ViGEMState::submit_report(self);
+    }
+
+    fn unplug(&mut self) {
+        let _ = self.target.unplug();
+    }
+
+    fn poll_feedback(&mut self) -> Option<Feedback> {
+        self.feedback.try_recv().ok()
+    }
+}
+
+/// Analog trigger travel past which the DS4's digital L2/R2 bits are set.
+const DS4_TRIGGER_THRESHOLD: u8 = 30;
+
+pub struct DS4State {
+    target: vigem_client::DualShock4Wired<Rc<vigem_client::Client>>,
+    pub report: vigem_client::DS4Report,
+    dpad: [bool; 4],
+    /// Whether the user was told touchpad fingers are dropped.
+    touch_dropped: bool,
+}
+
+impl DS4State {
+    pub fn new(client: Rc<vigem_client::Client>) -> Result<Self, vigem_client::Error> {
+        let id = vigem_client::TargetId::DUALSHOCK4_WIRED;
+        let mut target = vigem_client::DualShock4Wired::new(client, id);
+
+        target.plugin()?;
+        target.wait_ready()?;
+
+        let report = vigem_client::DS4Report::default();
+        let _ = target.update(&report);
+
+        Ok(DS4State {
+            target,
+            report,
+            dpad: [false; 4],
+            touch_dropped: false,
+        })
+    }
+
+    fn update_button(&mut self, button: u16, value: bool) {
+        if value {
+            self.report.buttons |= button;
+        } else {
+            self.report.buttons &= !button;
+        }
+    }
+
+    fn update_special(&mut self, button: u8, value: bool) {
+        if value {
+            self.report.special |= button;
+        } else {
+            self.report.special &= !button;
+        }
+    }
+
+    /// The D-pad is a hat in the low nibble of the buttons, 0 is north and
+    /// values go clockwise in eighths, 8 is released.
+    fn dpad_hat(&self) -> u16 {
+        let [up, down, left, right] = self.dpad;
+        match (up && !down, down && !up, left && !right, right && !left) {
+            (true, _, false, true) => 1,
+            (true, _, true, false) => 7,
+            (true, _, _, _) => 0,
+            (_, true, false, true) => 3,
+            (_, true, true, false) => 5,
+            (_, true, _, _) => 4,
+            (_, _, _, true) => 2,
+            (_, _, true, _) => 6,
+            _ => 8,
+        }
+    }
+}
+
+/// Maps an XInput style stick value onto the DS4's unsigned byte.
+fn ds4_stick(value: i16) -> u8 {
+    ((value as i32 + 32768) >> 8) as u8
+}
+
+impl VirtualPad for DS4State {
+    fn set_button(&mut self, button: Button, value: bool) {
+        use vigem_client::{DS4Buttons, DS4SpecialButtons};
+
+        match button {
+            Button::A => self.update_button(DS4Buttons::CROSS, value),
+            Button::B => self.update_button(DS4Buttons::CIRCLE, value),
+            Button::X => self.update_button(DS4Buttons::SQUARE, value),
+            Button::Y => self.update_button(DS4Buttons::TRIANGLE, value),
+            Button::LeftShoulder => self.update_button(DS4Buttons::SHOULDER_LEFT, value),
+            Button::RightShoulder => self.update_button(DS4Buttons::SHOULDER_RIGHT, value),
+            Button::LeftStick => self.update_button(DS4Buttons::THUMB_LEFT, value),
+            Button::RightStick => self.update_button(DS4Buttons::THUMB_RIGHT, value),
+            Button::Back => self.update_button(DS4Buttons::SHARE, value),
+            Button::Start => self.update_button(DS4Buttons::OPTIONS, value),
+            Button::Guide => self.update_special(DS4SpecialButtons::PS, value),
+            Button::Touchpad | Button::Misc1 => {
+                self.update_special(DS4SpecialButtons::TOUCHPAD, value)
+            }
+            Button::DPadUp => self.dpad[0] = value,
+            Button::DPadDown => self.dpad[1] = value,
+            Button::DPadLeft => self.dpad[2] = value,
+            Button::DPadRight => self.dpad[3] = value,
+            _ => {}
+        }
+    }
+
+    fn set_axis(&mut self, axis: Axis, value: i16) {
+        use vigem_client::DS4Buttons;
+
+        match axis {
+            Axis::LeftX => self.report.thumb_lx = ds4_stick(value),
+            // The DS4 reports Y growing downwards.
+            Axis::LeftY => self.report.thumb_ly = 255 - ds4_stick(value),
+            Axis::RightX => self.report.thumb_rx = ds4_stick(value),
+            Axis::RightY => self.report.thumb_ry = 255 - ds4_stick(value),
+            Axis::TriggerLeft => {
+                self.report.trigger_l = (value.max(0) / (32767 / 255)) as u8;
+                let pressed = self.report.trigger_l > DS4_TRIGGER_THRESHOLD;
+                self.update_button(DS4Buttons::TRIGGER_LEFT, pressed);
+            }
+            Axis::TriggerRight => {
+                self.report.trigger_r = (value.max(0) / (32767 / 255)) as u8;
+                let pressed = self.report.trigger_r > DS4_TRIGGER_THRESHOLD;
+                self.update_button(DS4Buttons::TRIGGER_RIGHT, pressed);
+            }
+        }
+    }
+
+    /// Not supported, see [`ViGEMBackend`]. Clicking the touchpad still goes
+    /// through as a button.
+    fn set_touch(&mut self, _point: TouchPoint) {
+        if !self.touch_dropped {
+            self.touch_dropped = true;
+            println!(
+                "Touchpad fingers can't be reproduced on ViGEm DualShock 4 pads, only clicks."
+            );
+        }
+    }
+
+    fn submit_report(&mut self) {
+        self.report.buttons = (self.report.buttons & !0xf) | self.dpad_hat();
+        let _ = self.target.update(&self.report);
+    }
+
+    fn unplug(&mut self) {
+        let _ = self.target.unplug();
+    }
+}
@@ -0,0 +1,124 @@
+use iol::{profile::Profile, ControllerState};
+use sdl2::controller::Axis;
+
+fn analog(text: &str) -> Profile {
+    Profile::from_toml(text).unwrap()
+}
+
+fn apply(profile: &Profile, axes: &[(Axis, i16)]) -> ControllerState {
+    let mut state = ControllerState::default();
+    for &(axis, value) in axes {
+        state.set_axis(axis, value);
+    }
+    profile.analog.apply(&mut state);
+    state
+}
+
+#[test]
+fn no_analog_section_passes_through() {
+    let profile = analog("");
+    let state = apply(&profile, &[(Axis::LeftX, -32768), (Axis::TriggerLeft, 5)]);
+    assert_eq!(state.axis(Axis::LeftX), -32768);
+    assert_eq!(state.axis(Axis::TriggerLeft), 5);
+}
+
+#[test]
+fn radial_deadzone_keeps_direction() {
+    let profile = analog(
+        r#"
+        [analog.left_stick]
+        inner = 0.2
+        outer = 0.2
+        "#,
+    );
+
+    // Each axis is past the deadzone on its own, but not the stick.
+    let state = apply(&profile, &[(Axis::LeftX, 4000), (Axis::LeftY, 4000)]);
+    assert_eq!(state.axis(Axis::LeftX), 0);
+    assert_eq!(state.axis(Axis::LeftY), 0);
+
+    let state = apply(&profile, &[(Axis::LeftX, 16384)]);
+    assert_eq!(state.axis(Axis::LeftX), 16384);
+    assert_eq!(state.axis(Axis::LeftY), 0);
+
+    // The outer deadzone already counts as fully pushed.
+    let state = apply(&profile, &[(Axis::LeftX, -27000)]);
+    assert_eq!(state.axis(Axis::LeftX), -32767);
+}
+
+#[test]
+fn axial_deadzone_applies_per_axis() {
+    let profile = analog(
+        r#"
+        [analog.right_stick]
+        deadzone = "axial"
+        inner = 0.2
+        "#,
+    );
+
+    let state = apply(&profile, &[(Axis::RightX, 32767), (Axis::RightY, 6000)]);
+    assert_eq!(state.axis(Axis::RightX), 32767);
+    assert_eq!(state.axis(Axis::RightY), 0);
+}
+
+#[test]
+fn anti_deadzone_and_curves() {
+    let profile = analog(
+        r#"
+        [analog.left_stick]
+        deadzone = "axial"
+        anti_deadzone = 0.25
+        curve = { exponential = 2.0 }
+
+        [analog.right_stick]
+        deadzone = "axial"
+        curve = { points = [[0.0, 0.0], [0.5, 0.25], [1.0, 1.0]] }
+        "#,
+    );
+
+    let state = apply(
+        &profile,
+        &[
+            (Axis::LeftX, 1),
+            (Axis::LeftY, -16384),
+            (Axis::RightX, 8192),
+            (Axis::RightY, 24576),
+        ],
+    );
+    // Barely moved, yet already past the anti-deadzone.
+    assert!(state.axis(Axis::LeftX) >= 8191);
+    // 0.25 + 0.75 * 0.5²
+    assert!((state.axis(Axis::LeftY) + 14336).abs() <= 1);
+    assert!((state.axis(Axis::RightX) - 4096).abs() <= 1);
+    assert!((state.axis(Axis::RightY) - 20480).abs() <= 1);
+}
+
+#[test]
+fn trigger_threshold_turns_it_digital() {
+    let profile = analog(
+        r#"
+        [analog.right_trigger]
+        threshold = 0.25
+        "#,
+    );
+
+    let state = apply(&profile, &[(Axis::TriggerRight, 8000)]);
+    assert_eq!(state.axis(Axis::TriggerRight), 0);
+    let state = apply(&profile, &[(Axis::TriggerRight, 8200)]);
+    assert_eq!(state.axis(Axis::TriggerRight), 32767);
+}
+
+#[test]
+fn invalid_analog_settings_are_rejected() {
+    assert!(Profile::from_toml("[analog.left_stick]\ninner = 1.5").is_err());
+    assert!(Profile::from_toml("[analog.left_stick]\ninner = 0.6\nouter = 0.4").is_err());
+    assert!(Profile::from_toml("[analog.left_stick]\nthreshold = 0.5").is_err());
+    assert!(Profile::from_toml("[analog.left_trigger]\ndeadzone = \"axial\"").is_err());
+    assert!(
+        Profile::from_toml("[analog.left_trigger]\ncurve = { points = [[0.5, 0.5]] }").is_err()
+    );
+    assert!(Profile::from_toml(
+        "[analog.left_trigger]\ncurve = { points = [[0.5, 0.5], [0.2, 1.0]] }"
+    )
+    .is_err());
+}
@@ -0,0 +1,107 @@
+mod common;
+
+use std::{
+    net::{Ipv6Addr, SocketAddr},
+    thread,
+    time::{Duration, Instant},
+};
+
+use common::BackgroundListener;
+use iol::{
+    client::{resolve_address, Client},
+    config::DEFAULT_PORT,
+    Capabilities,
+};
+
+#[test]
+fn addresses_are_parsed() {
+    let parse = |address: &str| resolve_address(address).unwrap();
+
+    assert_eq!(
+        parse("192.168.1.12:5000"),
+        "192.168.1.12:5000".parse().unwrap()
+    );
+    assert_eq!(
+        parse(" 192.168.1.12 "),
+        SocketAddr::new([192, 168, 1, 12].into(), DEFAULT_PORT)
+    );
+    assert_eq!(parse("[::1]:5000"), "[::1]:5000".parse().unwrap());
+    assert_eq!(
+        parse("[::1]"),
+        SocketAddr::new(Ipv6Addr::LOCALHOST.into(), DEFAULT_PORT)
+    );
+    assert_eq!(
+        parse("::1"),
+        SocketAddr::new(Ipv6Addr::LOCALHOST.into(), DEFAULT_PORT)
+    );
+}
+
+#[test]
+fn host_names_are_resolved() {
+    let address = resolve_address("localhost:5000").unwrap();
+    assert!(address.ip().is_loopback());
+    assert_eq!(address.port(), 5000);
+
+    let address = resolve_address("localhost").unwrap();
+    assert!(address.ip().is_loopback());
+    assert_eq!(address.port(), DEFAULT_PORT);
+}
+
+#[test]
+fn bad_addresses_are_errors() {
+    assert!(resolve_address("").is_err());
+    assert!(resolve_address("   ").is_err());
+    assert!(resolve_address(":4863").is_err());
+    assert!(resolve_address("localhost:").is_err());
+    assert!(resolve_address("localhost:port").is_err());
+    assert!(resolve_address("localhost:99999").is_err());
+    assert!(resolve_address("::1:4863:x").is_err());
+}
+
+#[test]
+fn clients_reach_ipv6_listeners() {
+    // No IPv6 loopback in this environment.
+    let Some(listener) = BackgroundListener::spawn("[::1]:0") else {
+        return;
+    };
+
+    // Bound to IPv4, the client has to switch over to reach the listener.
+    let mut client = Client::bind("127.0.0.1:0".parse().unwrap()).unwrap();
+    let session = client
+        .connect(listener.address, Capabilities::GAMEPAD)
+        .unwrap();
+    assert_eq!(session.server_address, listener.address);
+    client.disconnect();
+
+    listener.stop();
+}
+
+#[test]
+fn sessions_are_dropped_once_the_listener_goes_silent() {
+    let listener = BackgroundListener::spawn("127.0.0.1:0").unwrap();
+    let mut client = Client::bind("127.0.0.1:0".parse().unwrap()).unwrap();
+    client.set_timeout(Duration::from_millis(150));
+    client
+        .connect(listener.address, Capabilities::GAMEPAD)
+        .unwrap();
+
+    let keep_alive_for = |client: &mut Client, duration: Duration| {
+        let deadline = Instant::now() + duration;
+        while Instant::now() < deadline {
+            client.keep_alive().unwrap();
+            while client.try_recv().unwrap().is_some() {}
+            thread::sleep(Duration::from_millis(5));
+        }
+    };
+
+    // The listener echoes the heartbeats.
+    keep_alive_for(&mut client, Duration::from_millis(400));
+    assert!(client.is_alive());
+    assert!(client.is_connected());
+
+    listener.stop();
+    keep_alive_for(&mut client, Duration::from_millis(300));
+    assert!(!client.is_alive());
+    assert!(!client.is_connected());
+    assert_eq!(client.last_heard(), None);
+}
@@ -0,0 +1,101 @@
+//! Helpers shared by the integration tests, each of which only uses some.
+#![allow(dead_code)]
+
+use std::{
+    env,
+    net::SocketAddr,
+    path::PathBuf,
+    process,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        mpsc, Arc,
+    },
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+use iol::{
+    listener::Listener,
+    mock::{MockBackend, MockReport},
+    PadTarget,
+};
+use sdl2::keyboard::Scancode;
+
+/// A file name in the temporary directory unique to this test process.
+pub fn temp_path(name: &str) -> PathBuf {
+    env::temp_dir().join(format!("iol-{}-{}", process::id(), name))
+}
+
+/// What a listener's backend went through, handed back once it stops.
+pub struct Outcome {
+    pub targets: Vec<PadTarget>,
+    pub reports: Vec<Vec<MockReport>>,
+    pub key_log: Vec<(Scancode, bool)>,
+}
+
+/// A listener with a mock backend, polled on its own thread so a
+/// [`Client`](iol::client::Client) can block on it.
+pub struct BackgroundListener {
+    pub address: SocketAddr,
+    stop: Arc<AtomicBool>,
+    thread: JoinHandle<Outcome>,
+}
+
+impl BackgroundListener {
+    /// Returns `None` if `address` can't be bound.
+    pub fn spawn(address: &str) -> Option<Self> {
+        let address: SocketAddr = address.parse().unwrap();
+        let stop = Arc::new(AtomicBool::new(false));
+        let (address_tx, address_rx) = mpsc::channel();
+        let thread = thread::spawn({
+            let stop = stop.clone();
+            move || {
+                let backend = MockBackend::new();
+                let mut listener = match Listener::bind(address, Box::new(backend.clone())) {
+                    Ok(listener) => listener,
+                    Err(_) => {
+                        address_tx.send(None).unwrap();
+                        return Outcome {
+                            targets: vec![],
+                            reports: vec![],
+                            key_log: vec![],
+                        };
+                    }
+                };
+                address_tx
+                    .send(Some(listener.local_addr().unwrap()))
+                    .unwrap();
+                while !stop.load(Ordering::SeqCst) {
+                    listener.poll_once(Some(Duration::from_millis(10))).unwrap();
+                }
+                let state = backend.state();
+                Outcome {
+                    targets: state.pads.iter().map(|pad| pad.borrow().target).collect(),
+                    reports: state
+                        .pads
+                        .iter()
+                        .map(|pad| pad.borrow().reports.clone())
+                        .collect(),
+                    key_log: state.key_log.clone(),
+                }
+            }
+        });
+        let address = address_rx.recv().unwrap()?;
+        Some(BackgroundListener {
+            address,
+            stop,
+            thread,
+        })
+    }
+
+    pub fn stop(self) -> Outcome {
+        self.stop.store(true, Ordering::SeqCst);
+        self.thread.join().unwrap()
+    }
+
+    /// Gives the listener time to take in what was sent, then stops it.
+    pub fn finish(self) -> Outcome {
+        thread::sleep(Duration::from_millis(50));
+        self.stop()
+    }
+}
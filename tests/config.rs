@@ -0,0 +1,122 @@
+use std::{
+    net::{IpAddr, Ipv4Addr},
+    path::PathBuf,
+    time::Duration,
+};
+
+use iol::{
+    config::{Backend, Config, DeviceConfig, DEFAULT_PORT},
+    socd::{Socd, SocdPolicy},
+    PadTarget,
+};
+
+fn layer(text: &str) -> toml::Table {
+    text.parse().unwrap()
+}
+
+#[test]
+fn defaults_match_the_old_hardcoded_values() {
+    let config = Config::from_layers([]).unwrap();
+    assert_eq!(config.listen.bind, IpAddr::V4(Ipv4Addr::UNSPECIFIED));
+    assert_eq!(config.listen.port, DEFAULT_PORT);
+    assert_eq!(config.listen.client_timeout, 3.0);
+    assert_eq!(config.listen.max_pads, None);
+    assert_eq!(config.broadcast.bind, "0.0.0.0:5864".parse().unwrap());
+    assert_eq!(config.broadcast.server, "192.168.1.12:4863");
+    assert_eq!(config.broadcast.snapshot_rate, 10);
+    assert_eq!(config.broadcast.motion_rate, 60);
+    assert_eq!(config.broadcast.profile, None);
+}
+
+#[test]
+fn later_layers_override_earlier_ones() {
+    let config = Config::from_layers([
+        layer(
+            r#"
+            [listen]
+            port = 5000
+            backend = "uinput"
+            socd = { horizontal = "neutral", vertical = "up-priority" }
+
+            [[listen.devices]]
+            name = "PS4 Controller"
+            profile = "southpaw.toml"
+
+            [broadcast]
+            server = "10.0.0.2:5000"
+            motion_rate = 120
+            "#,
+        ),
+        layer(
+            r#"
+            [listen]
+            target = "DualShock4"
+            socd = { vertical = "last-input-wins" }
+
+            [broadcast]
+            motion_rate = 30
+            "#,
+        ),
+    ])
+    .unwrap();
+
+    assert_eq!(config.listen.port, 5000);
+    assert_eq!(config.listen.backend, Some(Backend::Uinput));
+    assert_eq!(config.listen.target, PadTarget::DualShock4);
+    // Tables are merged key by key.
+    assert_eq!(
+        config.listen.socd,
+        Socd {
+            horizontal: SocdPolicy::Neutral,
+            vertical: SocdPolicy::LastInputWins,
+        }
+    );
+    assert_eq!(
+        config.listen.devices,
+        vec![DeviceConfig {
+            name: "PS4 Controller".to_owned(),
+            profile: Some(PathBuf::from("southpaw.toml")),
+            socd: None,
+        }]
+    );
+    assert_eq!(config.broadcast.server, "10.0.0.2:5000");
+    assert_eq!(config.broadcast.motion_rate, 30);
+    assert_eq!(config.broadcast.snapshot_rate, 10);
+}
+
+#[test]
+fn invalid_configs_are_rejected() {
+    assert!(Config::from_layers([layer("[listen]\nprot = 5000")]).is_err());
+    assert!(Config::from_layers([layer("[listen]\nbackend = \"sdl\"")]).is_err());
+    assert!(Config::from_layers([layer("[broadcast]\nbind = \"nowhere\"")]).is_err());
+    assert!(
+        Config::from_layers([layer("[listen]\nport = 5000"), layer("[listen]\nport = -1")])
+            .is_err()
+    );
+}
+
+#[test]
+fn client_timeouts_must_be_positive() {
+    let mut config = Config::from_layers([]).unwrap().listen;
+    assert_eq!(
+        config.client_timeout_duration().unwrap(),
+        Duration::from_secs(3)
+    );
+
+    for timeout in [0.0, -1.0, f64::NAN] {
+        config.client_timeout = timeout;
+        assert!(config.client_timeout_duration().is_err(), "{}", timeout);
+    }
+    config.client_timeout = 0.25;
+    assert_eq!(
+        config.client_timeout_duration().unwrap(),
+        Duration::from_millis(250)
+    );
+}
+
+#[test]
+fn pad_targets_parse_from_the_command_line() {
+    assert_eq!("dualshock4".parse(), Ok(PadTarget::DualShock4));
+    assert_eq!("Xbox360".parse(), Ok(PadTarget::Xbox360));
+    assert!("switch".parse::<PadTarget>().is_err());
+}
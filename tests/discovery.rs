@@ -0,0 +1,69 @@
+use std::{thread, time::Duration};
+
+use iol::{
+    discovery::{Discovered, Discovery},
+    listener::Listener,
+    mock::MockBackend,
+    Capabilities, PadTarget, PROTOCOL_VERSION,
+};
+
+const TIMEOUT: Duration = Duration::from_millis(200);
+
+/// Probes `listener` and returns what answered.
+fn probe(listener: &mut Listener) -> Vec<Discovered> {
+    let mut discovery = Discovery::bind("127.0.0.1:0".parse().unwrap()).unwrap();
+    assert!(discovery.listeners().is_empty());
+    discovery.probe(listener.local_addr().unwrap()).unwrap();
+    listener.poll_once(Some(TIMEOUT)).unwrap();
+
+    let mut found = vec![];
+    for _ in 0..20 {
+        discovery.poll().unwrap();
+        found = discovery.listeners().into_iter().cloned().collect();
+        if !found.is_empty() {
+            break;
+        }
+        thread::sleep(Duration::from_millis(10));
+    }
+    found
+}
+
+#[test]
+fn listeners_answer_probes() {
+    let backend = MockBackend::with_capabilities(Capabilities::GAMEPAD);
+    let mut listener = Listener::bind("127.0.0.1:0".parse().unwrap(), Box::new(backend)).unwrap();
+    listener.set_name("living room".to_owned());
+    listener.set_max_pads(Some(4));
+    let address = listener.local_addr().unwrap();
+
+    let found = probe(&mut listener);
+
+    assert_eq!(found.len(), 1);
+    let found = &found[0];
+    assert_eq!(found.address, address);
+    assert_eq!(found.name, "living room");
+    assert_eq!(found.version, PROTOCOL_VERSION);
+    assert!(found.is_compatible());
+    assert_eq!(found.capabilities, Capabilities::GAMEPAD);
+    assert_eq!((found.clients, found.pads), (0, 0));
+    assert_eq!(found.capacity, Some(4));
+}
+
+#[test]
+fn replies_advertise_the_default_target() {
+    let mut backend = MockBackend::new();
+    backend.set_target_capabilities(
+        PadTarget::DualShock4,
+        Capabilities::ALL.difference(Capabilities::FEEDBACK),
+    );
+    let mut listener = Listener::bind("127.0.0.1:0".parse().unwrap(), Box::new(backend)).unwrap();
+    listener.set_default_target(PadTarget::DualShock4);
+
+    let found = probe(&mut listener);
+
+    assert_eq!(found.len(), 1);
+    assert_eq!(
+        found[0].capabilities,
+        Capabilities::ALL.difference(Capabilities::FEEDBACK)
+    );
+}
@@ -0,0 +1,1089 @@
+mod common;
+
+use std::{fs, net::UdpSocket, path::PathBuf, thread, time::Duration};
+
+use common::temp_path;
+
+use iol::{
+    backend::Feedback,
+    listener::Listener,
+    mock::MockBackend,
+    profile::ProfileFile,
+    socd::{Socd, SocdPolicy},
+    Capabilities, ControllerState, Envelope, IolEvent, PadTarget, RejectReason, TouchPoint,
+    PROTOCOL_VERSION,
+};
+use postcard::{from_bytes, to_allocvec};
+use sdl2::{
+    controller::{Axis, Button},
+    keyboard::Scancode,
+    mouse::MouseButton,
+};
+
+const TIMEOUT: Duration = Duration::from_millis(200);
+const SESSION: u32 = 0x10e;
+const PAD_NAME: &str = "Xbox 360 Controller";
+
+struct Harness {
+    backend: MockBackend,
+    listener: Listener,
+    client: UdpSocket,
+    sequence: u64,
+}
+
+impl Harness {
+    fn new() -> Self {
+        let mut harness = Harness::unconnected(MockBackend::new());
+        let capabilities = harness.hello(PROTOCOL_VERSION, Capabilities::ALL);
+        assert_eq!(capabilities, Capabilities::ALL);
+        harness
+    }
+
+    fn unconnected(backend: MockBackend) -> Self {
+        let listener =
+            Listener::bind("127.0.0.1:0".parse().unwrap(), Box::new(backend.clone())).unwrap();
+        let client = UdpSocket::bind("127.0.0.1:0").unwrap();
+        client.set_read_timeout(Some(TIMEOUT)).unwrap();
+        client.connect(listener.local_addr().unwrap()).unwrap();
+
+        Harness {
+            backend,
+            listener,
+            client,
+            sequence: 0,
+        }
+    }
+
+    /// Performs the handshake and returns the negotiated capabilities.
+    fn hello(&mut self, version: u16, capabilities: Capabilities) -> Capabilities {
+        self.send(IolEvent::Hello {
+            version,
+            capabilities,
+        });
+        match self.recv() {
+            IolEvent::Welcome {
+                version,
+                capabilities,
+            } => {
+                assert_eq!(version, PROTOCOL_VERSION);
+                capabilities
+            }
+            event => panic!("unexpected reply {:?}", event),
+        }
+    }
+
+    fn send(&mut self, event: IolEvent) {
+        self.sequence += 1;
+        self.send_envelope(Envelope::new(SESSION, self.sequence, event));
+    }
+
+    fn send_envelope(&mut self, envelope: Envelope) {
+        self.client.send(&to_allocvec(&envelope).unwrap()).unwrap();
+        self.listener.poll_once(Some(TIMEOUT)).unwrap();
+    }
+
+    fn recv(&mut self) -> IolEvent {
+        let mut buf = [0; 1 << 16];
+        let size = self.client.recv(&mut buf).unwrap();
+        let envelope: Envelope = from_bytes(&buf[..size]).unwrap();
+        assert_eq!(envelope.session, SESSION);
+        envelope.event
+    }
+
+    fn add_device(&mut self, which: u32) -> u32 {
+        self.add_named_device(which, PAD_NAME)
+    }
+
+    fn add_named_device(&mut self, which: u32, name: &str) -> u32 {
+        self.send(IolEvent::PhysicalDeviceAdded {
+            which,
+            target: None,
+            name: name.to_owned(),
+        });
+        match self.recv() {
+            IolEvent::VirtualDeviceAdded { id, which: w } => {
+                assert_eq!(w, which);
+                id
+            }
+            event => panic!("unexpected reply {:?}", event),
+        }
+    }
+}
+
+#[test]
+fn device_added_plugs_a_virtual_pad() {
+    let mut harness = Harness::new();
+
+    let id = harness.add_device(7);
+
+    assert_eq!(id, 0);
+    let pad = harness.backend.pad(0).unwrap();
+    assert!(pad.borrow().plugged);
+}
+
+#[test]
+fn buttons_and_axes_reach_the_pad() {
+    let mut harness = Harness::new();
+    let id = harness.add_device(0);
+
+    harness.send(IolEvent::ButtonDown {
+        id,
+        button: Button::A,
+    });
+    harness.send(IolEvent::AxisMotion {
+        id,
+        axis: Axis::LeftX,
+        value: -12000,
+    });
+
+    let pad = harness.backend.pad(0).unwrap();
+    let current = pad.borrow().current();
+    assert!(current.button(Button::A));
+    assert_eq!(current.axis(Axis::LeftX), -12000);
+
+    harness.send(IolEvent::ButtonUp {
+        id,
+        button: Button::A,
+    });
+    assert!(!pad.borrow().current().button(Button::A));
+    assert_eq!(pad.borrow().reports.len(), 3);
+}
+
+#[test]
+fn events_are_routed_by_device_id() {
+    let mut harness = Harness::new();
+    let first = harness.add_device(0);
+    let second = harness.add_device(1);
+    assert_ne!(first, second);
+
+    harness.send(IolEvent::ButtonDown {
+        id: second,
+        button: Button::B,
+    });
+
+    assert!(harness.backend.pad(0).unwrap().borrow().reports.is_empty());
+    assert!(harness
+        .backend
+        .pad(1)
+        .unwrap()
+        .borrow()
+        .current()
+        .button(Button::B));
+}
+
+#[test]
+fn device_removed_unplugs_the_pad() {
+    let mut harness = Harness::new();
+    let id = harness.add_device(0);
+
+    harness.send(IolEvent::PhysicalDeviceRemoved { id });
+    harness.send(IolEvent::ButtonDown {
+        id,
+        button: Button::A,
+    });
+
+    let pad = harness.backend.pad(0).unwrap();
+    assert!(!pad.borrow().plugged);
+    assert!(pad.borrow().reports.is_empty());
+
+    // Ids are not reused after a removal.
+    assert_eq!(harness.add_device(0), id + 1);
+}
+
+#[test]
+fn malformed_packets_are_ignored() {
+    let mut harness = Harness::new();
+
+    harness.client.send(&[0xff, 0xff, 0xff]).unwrap();
+    harness.listener.poll_once(Some(TIMEOUT)).unwrap();
+
+    assert_eq!(harness.add_device(0), 0);
+}
+
+#[test]
+fn keys_are_forwarded_to_the_backend() {
+    let mut harness = Harness::new();
+
+    harness.send(IolEvent::KeyDown {
+        scancode: Scancode::W,
+        repeat: false,
+    });
+    assert!(harness.backend.state().keys.contains(&Scancode::W));
+
+    harness.send(IolEvent::KeyUp {
+        scancode: Scancode::W,
+    });
+    assert!(harness.backend.state().keys.is_empty());
+}
+
+#[test]
+fn disconnect_releases_held_keys_and_devices() {
+    let mut harness = Harness::new();
+    harness.add_device(0);
+
+    harness.send(IolEvent::KeyDown {
+        scancode: Scancode::LShift,
+        repeat: false,
+    });
+    harness.send(IolEvent::KeyDown {
+        scancode: Scancode::A,
+        repeat: false,
+    });
+    harness.send(IolEvent::KeyUp {
+        scancode: Scancode::A,
+    });
+    harness.send(IolEvent::Disconnect);
+
+    let state = harness.backend.state();
+    assert!(state.keys.is_empty());
+    assert_eq!(state.key_log.last(), Some(&(Scancode::LShift, false)));
+    assert!(!state.pads[0].borrow().plugged);
+}
+
+#[test]
+fn mouse_events_are_forwarded_to_the_backend() {
+    let mut harness = Harness::new();
+
+    harness.send(IolEvent::MouseMotion { dx: 5, dy: -3 });
+    harness.send(IolEvent::MouseMotion { dx: 2, dy: 1 });
+    harness.send(IolEvent::MouseWheel { x: 0, y: -2 });
+    harness.send(IolEvent::MouseButtonDown {
+        button: MouseButton::Right,
+    });
+
+    {
+        let state = harness.backend.state();
+        assert_eq!(state.mouse_position, (7, -2));
+        assert_eq!(state.wheel, (0, -2));
+        assert!(state.mouse_buttons.contains(&MouseButton::Right));
+    }
+
+    harness.send(IolEvent::Disconnect);
+    assert!(harness.backend.state().mouse_buttons.is_empty());
+}
+
+#[test]
+fn mouse_releases_without_a_press_are_dropped() {
+    let mut harness = Harness::new();
+
+    harness.send(IolEvent::MouseButtonUp {
+        button: MouseButton::Left,
+    });
+    assert!(harness.backend.state().mouse_button_log.is_empty());
+
+    harness.send(IolEvent::MouseButtonDown {
+        button: MouseButton::Left,
+    });
+    harness.send(IolEvent::MouseButtonUp {
+        button: MouseButton::Left,
+    });
+    assert_eq!(
+        harness.backend.state().mouse_button_log,
+        [(MouseButton::Left, true), (MouseButton::Left, false)]
+    );
+}
+
+#[test]
+fn mismatched_protocol_version_is_rejected() {
+    let mut harness = Harness::unconnected(MockBackend::new());
+
+    harness.send(IolEvent::Hello {
+        version: PROTOCOL_VERSION + 1,
+        capabilities: Capabilities::ALL,
+    });
+
+    match harness.recv() {
+        IolEvent::Rejected { version, reason } => {
+            assert_eq!(version, PROTOCOL_VERSION);
+            assert_eq!(reason, RejectReason::VersionMismatch);
+        }
+        event => panic!("unexpected reply {:?}", event),
+    }
+    harness.send(IolEvent::PhysicalDeviceAdded {
+        which: 0,
+        target: None,
+        name: PAD_NAME.to_owned(),
+    });
+    assert!(harness.backend.pad(0).is_none());
+}
+
+#[test]
+fn events_before_the_handshake_are_ignored() {
+    let mut harness = Harness::unconnected(MockBackend::new());
+
+    harness.send(IolEvent::PhysicalDeviceAdded {
+        which: 0,
+        target: None,
+        name: PAD_NAME.to_owned(),
+    });
+    harness.send(IolEvent::KeyDown {
+        scancode: Scancode::A,
+        repeat: false,
+    });
+
+    assert!(harness.backend.pad(0).is_none());
+    assert!(harness.backend.state().keys.is_empty());
+}
+
+#[test]
+fn capabilities_are_downgraded_to_what_both_sides_support() {
+    let mut harness = Harness::unconnected(MockBackend::with_capabilities(Capabilities::GAMEPAD));
+
+    let capabilities = harness.hello(PROTOCOL_VERSION, Capabilities::ALL);
+    assert_eq!(capabilities, Capabilities::GAMEPAD);
+
+    harness.send(IolEvent::KeyDown {
+        scancode: Scancode::A,
+        repeat: false,
+    });
+    harness.send(IolEvent::MouseMotion { dx: 5, dy: 5 });
+    let id = harness.add_device(0);
+    harness.send(IolEvent::ButtonDown {
+        id,
+        button: Button::A,
+    });
+
+    let state = harness.backend.state();
+    assert!(state.keys.is_empty());
+    assert_eq!(state.mouse_position, (0, 0));
+    drop(state);
+    assert!(harness
+        .backend
+        .pad(0)
+        .unwrap()
+        .borrow()
+        .current()
+        .button(Button::A));
+}
+
+#[test]
+fn no_common_capabilities_is_rejected() {
+    let mut harness = Harness::unconnected(MockBackend::with_capabilities(Capabilities::GAMEPAD));
+
+    harness.send(IolEvent::Hello {
+        version: PROTOCOL_VERSION,
+        capabilities: Capabilities::KEYBOARD,
+    });
+
+    match harness.recv() {
+        IolEvent::Rejected { reason, .. } => {
+            assert_eq!(reason, RejectReason::NoCommonCapabilities)
+        }
+        event => panic!("unexpected reply {:?}", event),
+    }
+}
+
+#[test]
+fn stale_packets_are_discarded() {
+    let mut harness = Harness::new();
+    let id = harness.add_device(0);
+
+    harness.send_envelope(Envelope::new(
+        SESSION,
+        20,
+        IolEvent::ButtonDown {
+            id,
+            button: Button::A,
+        },
+    ));
+    harness.send_envelope(Envelope::new(
+        SESSION,
+        21,
+        IolEvent::ButtonUp {
+            id,
+            button: Button::A,
+        },
+    ));
+    // Arrives late, after the release it preceded.
+    harness.send_envelope(Envelope::new(
+        SESSION,
+        19,
+        IolEvent::ButtonDown {
+            id,
+            button: Button::B,
+        },
+    ));
+
+    let pad = harness.backend.pad(0).unwrap();
+    let pad = pad.borrow();
+    assert_eq!(pad.reports.len(), 2);
+    assert!(!pad.current().button(Button::A));
+    assert!(!pad.current().button(Button::B));
+}
+
+#[test]
+fn duplicate_packets_are_applied_once() {
+    let mut harness = Harness::new();
+
+    let key_down = || IolEvent::KeyDown {
+        scancode: Scancode::A,
+        repeat: false,
+    };
+    harness.send_envelope(Envelope::new(SESSION, 10, key_down()));
+    harness.send_envelope(Envelope::new(
+        SESSION,
+        11,
+        IolEvent::KeyUp {
+            scancode: Scancode::A,
+        },
+    ));
+    harness.send_envelope(Envelope::new(SESSION, 10, key_down()));
+
+    assert_eq!(
+        harness.backend.state().key_log,
+        vec![(Scancode::A, true), (Scancode::A, false)]
+    );
+}
+
+#[test]
+fn duplicate_device_added_replies_with_the_existing_pad() {
+    let mut harness = Harness::new();
+
+    let id = harness.add_device(3);
+    assert_eq!(harness.add_device(3), id);
+    let other = harness.add_device(4);
+
+    assert_ne!(other, id);
+    assert_eq!(harness.backend.state().pads.len(), 2);
+}
+
+#[test]
+fn device_added_after_its_removal_is_discarded() {
+    let mut harness = Harness::new();
+    let added = |sequence| {
+        Envelope::new(
+            SESSION,
+            sequence,
+            IolEvent::PhysicalDeviceAdded {
+                which: 3,
+                target: None,
+                name: PAD_NAME.to_owned(),
+            },
+        )
+    };
+
+    harness.send_envelope(added(10));
+    let id = match harness.recv() {
+        IolEvent::VirtualDeviceAdded { id, .. } => id,
+        event => panic!("unexpected reply {:?}", event),
+    };
+    harness.send_envelope(Envelope::new(
+        SESSION,
+        12,
+        IolEvent::PhysicalDeviceRemoved { id },
+    ));
+    // A duplicate of the first add, delayed past the removal.
+    harness.send_envelope(added(10));
+    harness.send_envelope(added(11));
+    assert_eq!(harness.backend.state().pads.len(), 1);
+    assert!(!harness.backend.pad(0).unwrap().borrow().plugged);
+
+    // Plugging the device back in still works.
+    harness.send_envelope(added(13));
+    assert!(matches!(
+        harness.recv(),
+        IolEvent::VirtualDeviceAdded { which: 3, .. }
+    ));
+    assert_eq!(harness.backend.state().pads.len(), 2);
+}
+
+#[test]
+fn pads_past_the_limit_are_refused() {
+    let mut harness = Harness::new();
+    harness.listener.set_max_pads(Some(1));
+
+    harness.add_device(0);
+    harness.send(IolEvent::PhysicalDeviceAdded {
+        which: 1,
+        target: None,
+        name: PAD_NAME.to_owned(),
+    });
+
+    assert!(matches!(
+        harness.recv(),
+        IolEvent::Rejected {
+            reason: RejectReason::PadLimitReached,
+            ..
+        }
+    ));
+    assert_eq!(harness.backend.state().pads.len(), 1);
+}
+
+#[test]
+fn ordering_is_tracked_per_device() {
+    let mut harness = Harness::new();
+    let first = harness.add_device(0);
+    let second = harness.add_device(1);
+
+    harness.send_envelope(Envelope::new(
+        SESSION,
+        30,
+        IolEvent::ButtonDown {
+            id: first,
+            button: Button::A,
+        },
+    ));
+    harness.send_envelope(Envelope::new(
+        SESSION,
+        29,
+        IolEvent::ButtonDown {
+            id: second,
+            button: Button::A,
+        },
+    ));
+
+    for index in 0..2 {
+        let pad = harness.backend.pad(index).unwrap();
+        assert!(pad.borrow().current().button(Button::A));
+    }
+}
+
+#[test]
+fn packets_from_an_old_session_are_discarded() {
+    let mut harness = Harness::new();
+
+    harness.send_envelope(Envelope::new(
+        SESSION + 1,
+        100,
+        IolEvent::KeyDown {
+            scancode: Scancode::A,
+            repeat: false,
+        },
+    ));
+
+    assert!(harness.backend.state().key_log.is_empty());
+}
+
+#[test]
+fn duplicate_hello_keeps_the_session() {
+    let mut harness = Harness::new();
+    harness.add_device(0);
+
+    harness.hello(PROTOCOL_VERSION, Capabilities::ALL);
+
+    assert!(harness.backend.pad(0).unwrap().borrow().plugged);
+}
+
+#[test]
+fn controller_state_is_applied_as_one_report() {
+    let mut harness = Harness::new();
+    let id = harness.add_device(0);
+    harness.send(IolEvent::ButtonDown {
+        id,
+        button: Button::A,
+    });
+    harness.send(IolEvent::AxisMotion {
+        id,
+        axis: Axis::LeftX,
+        value: 12000,
+    });
+
+    // The ButtonUp for A and the stick returning to centre were lost.
+    let mut state = ControllerState::default();
+    state.set_button(Button::B, true);
+    state.set_axis(Axis::TriggerLeft, 255);
+    harness.send(IolEvent::ControllerState { id, state });
+
+    let pad = harness.backend.pad(0).unwrap();
+    let pad = pad.borrow();
+    assert_eq!(pad.reports.len(), 3);
+    let report = pad.current();
+    assert!(!report.button(Button::A));
+    assert!(report.button(Button::B));
+    assert_eq!(report.axis(Axis::LeftX), 0);
+    assert_eq!(report.axis(Axis::TriggerLeft), 255);
+}
+
+#[test]
+fn controller_state_round_trips_every_button() {
+    let mut state = ControllerState::default();
+    for button in ControllerState::BUTTONS {
+        state.set_button(button, true);
+        assert!(state.button(button));
+    }
+    for button in ControllerState::BUTTONS {
+        state.set_button(button, false);
+    }
+    assert_eq!(state, ControllerState::default());
+}
+
+#[test]
+fn silent_clients_are_neutralized_then_unplugged() {
+    let mut harness = Harness::new();
+    harness
+        .listener
+        .set_client_timeout(Duration::from_millis(50));
+    let id = harness.add_device(0);
+    harness.send(IolEvent::ButtonDown {
+        id,
+        button: Button::A,
+    });
+    harness.send(IolEvent::KeyDown {
+        scancode: Scancode::W,
+        repeat: false,
+    });
+
+    thread::sleep(Duration::from_millis(60));
+    harness.listener.poll_once(Some(Duration::ZERO)).unwrap();
+
+    let pad = harness.backend.pad(0).unwrap();
+    assert!(pad.borrow().plugged);
+    assert!(!pad.borrow().current().button(Button::A));
+    assert!(harness.backend.state().keys.is_empty());
+
+    thread::sleep(Duration::from_millis(50));
+    harness.listener.poll_once(Some(Duration::ZERO)).unwrap();
+
+    assert!(!pad.borrow().plugged);
+}
+
+#[test]
+fn heartbeats_keep_the_client_alive() {
+    let mut harness = Harness::new();
+    harness
+        .listener
+        .set_client_timeout(Duration::from_millis(50));
+    let id = harness.add_device(0);
+    harness.send(IolEvent::ButtonDown {
+        id,
+        button: Button::A,
+    });
+
+    for _ in 0..4 {
+        thread::sleep(Duration::from_millis(30));
+        harness.send(IolEvent::Heartbeat);
+    }
+
+    let pad = harness.backend.pad(0).unwrap();
+    assert!(pad.borrow().plugged);
+    assert!(pad.borrow().current().button(Button::A));
+}
+
+#[test]
+fn heartbeats_are_echoed() {
+    let mut harness = Harness::new();
+
+    harness.send(IolEvent::Heartbeat);
+
+    assert!(matches!(harness.recv(), IolEvent::Heartbeat));
+}
+
+#[test]
+fn rumble_is_forwarded_to_the_owner() {
+    let mut harness = Harness::new();
+    let id = harness.add_device(0);
+    let pad = harness.backend.pad(0).unwrap();
+
+    pad.borrow_mut().feedback.push_back(Feedback::Rumble {
+        large_motor: 255,
+        small_motor: 128,
+    });
+    harness.listener.poll_once(Some(Duration::ZERO)).unwrap();
+
+    match harness.recv() {
+        IolEvent::Rumble {
+            id: rumbled,
+            low,
+            high,
+            duration,
+        } => {
+            assert_eq!(rumbled, id);
+            assert_eq!(low, u16::MAX);
+            assert_eq!(high, 128 * 257);
+            assert!(duration > 0);
+        }
+        event => panic!("unexpected event {:?}", event),
+    }
+
+    pad.borrow_mut().feedback.push_back(Feedback::Rumble {
+        large_motor: 0,
+        small_motor: 0,
+    });
+    harness.listener.poll_once(Some(Duration::ZERO)).unwrap();
+
+    match harness.recv() {
+        IolEvent::Rumble {
+            low: 0,
+            high: 0,
+            duration: 0,
+            ..
+        } => {}
+        event => panic!("unexpected event {:?}", event),
+    }
+}
+
+#[test]
+fn rumble_needs_the_feedback_capability() {
+    let mut harness = Harness::unconnected(MockBackend::new());
+    harness.hello(PROTOCOL_VERSION, Capabilities::GAMEPAD);
+    harness.add_device(0);
+
+    harness
+        .backend
+        .pad(0)
+        .unwrap()
+        .borrow_mut()
+        .feedback
+        .push_back(Feedback::Rumble {
+            large_motor: 255,
+            small_motor: 255,
+        });
+    harness.listener.poll_once(Some(Duration::ZERO)).unwrap();
+
+    let mut buf = [0; 1 << 16];
+    assert!(harness.client.recv(&mut buf).is_err());
+}
+
+#[test]
+fn player_slots_are_forwarded_to_the_owner() {
+    let mut harness = Harness::new();
+    let id = harness.add_device(0);
+
+    harness
+        .backend
+        .pad(0)
+        .unwrap()
+        .borrow_mut()
+        .feedback
+        .push_back(Feedback::Led { number: 2 });
+    harness.listener.poll_once(Some(Duration::ZERO)).unwrap();
+
+    match harness.recv() {
+        IolEvent::PlayerSlot { id: slotted, slot } => {
+            assert_eq!(slotted, id);
+            assert_eq!(slot, 2);
+        }
+        event => panic!("unexpected event {:?}", event),
+    }
+}
+
+#[test]
+fn capabilities_follow_the_default_target() {
+    let mut backend = MockBackend::new();
+    backend.set_target_capabilities(
+        PadTarget::DualShock4,
+        Capabilities::ALL.difference(Capabilities::FEEDBACK),
+    );
+    let mut harness = Harness::unconnected(backend);
+    harness.listener.set_default_target(PadTarget::DualShock4);
+
+    let capabilities = harness.hello(PROTOCOL_VERSION, Capabilities::ALL);
+
+    assert!(!capabilities.contains(Capabilities::FEEDBACK));
+    assert!(capabilities.contains(Capabilities::GAMEPAD));
+}
+
+#[test]
+fn devices_use_the_requested_target() {
+    let mut harness = Harness::new();
+    harness.listener.set_default_target(PadTarget::DualShock4);
+
+    harness.add_device(0);
+    harness.send(IolEvent::PhysicalDeviceAdded {
+        which: 1,
+        target: Some(PadTarget::Xbox360),
+        name: PAD_NAME.to_owned(),
+    });
+
+    let target = |index| harness.backend.pad(index).unwrap().borrow().target;
+    assert_eq!(target(0), PadTarget::DualShock4);
+    assert_eq!(target(1), PadTarget::Xbox360);
+}
+
+#[test]
+fn motion_reaches_the_pad() {
+    let mut harness = Harness::new();
+    let id = harness.add_device(0);
+
+    harness.send(IolEvent::Motion {
+        id,
+        timestamp: 1_000,
+        gyro: [0.5, -0.25, 0.0],
+        accel: [0.0, 9.81, 0.0],
+    });
+
+    let pad = harness.backend.pad(0).unwrap();
+    let motion = pad.borrow().motion.clone();
+    assert_eq!(motion.len(), 1);
+    assert_eq!(motion[0].timestamp, 1_000);
+    assert_eq!(motion[0].gyro, [0.5, -0.25, 0.0]);
+    assert_eq!(motion[0].accel, [0.0, 9.81, 0.0]);
+}
+
+#[test]
+fn motion_needs_the_motion_capability() {
+    let mut harness = Harness::unconnected(MockBackend::new());
+    harness.hello(PROTOCOL_VERSION, Capabilities::GAMEPAD);
+    let id = harness.add_device(0);
+
+    harness.send(IolEvent::Motion {
+        id,
+        timestamp: 0,
+        gyro: [1.0; 3],
+        accel: [1.0; 3],
+    });
+
+    assert!(harness.backend.pad(0).unwrap().borrow().motion.is_empty());
+}
+
+#[test]
+fn touch_reaches_the_pad() {
+    let mut harness = Harness::new();
+    let id = harness.add_device(0);
+
+    let down = TouchPoint {
+        touchpad: 0,
+        finger: 1,
+        touching: true,
+        x: 0.25,
+        y: 0.75,
+        pressure: 1.0,
+    };
+    let up = TouchPoint {
+        touching: false,
+        ..down
+    };
+    harness.send(IolEvent::Touch { id, point: down });
+    harness.send(IolEvent::Touch { id, point: up });
+
+    let pad = harness.backend.pad(0).unwrap();
+    assert_eq!(pad.borrow().touches, vec![down, up]);
+}
+
+fn write_profile(name: &str, text: &str) -> PathBuf {
+    let path = temp_path(name);
+    fs::write(&path, text).unwrap();
+    path
+}
+
+#[test]
+fn profile_remaps_pad_and_keys() {
+    let path = write_profile(
+        "listener.toml",
+        r#"
+        [buttons]
+        a = "b"
+
+        [keys]
+        Space = "x"
+        "#,
+    );
+    let mut harness = Harness::new();
+    harness
+        .listener
+        .set_profile(Some(ProfileFile::load(&path).unwrap()));
+    let id = harness.add_device(0);
+
+    harness.send(IolEvent::ButtonDown {
+        id,
+        button: Button::A,
+    });
+    harness.send(IolEvent::KeyDown {
+        scancode: Scancode::Space,
+        repeat: false,
+    });
+    harness.send(IolEvent::KeyDown {
+        scancode: Scancode::Return,
+        repeat: false,
+    });
+
+    let pad = harness.backend.pad(0).unwrap();
+    let report = pad.borrow().current();
+    assert!(!report.button(Button::A));
+    assert!(report.button(Button::B));
+    assert!(report.button(Button::X));
+    // Only keys the profile doesn't bind reach the keyboard.
+    assert_eq!(
+        harness.backend.state().key_log,
+        vec![(Scancode::Return, true)]
+    );
+
+    fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn keys_drive_the_d_pad_through_socd() {
+    let path = write_profile(
+        "keyboard.toml",
+        r#"
+        [keys]
+        A = "dpleft"
+        D = "dpright"
+        W = "dpup"
+        S = "dpdown"
+
+        [socd]
+        horizontal = "last-input-wins"
+        vertical = "up-priority"
+        "#,
+    );
+    let mut harness = Harness::new();
+    harness
+        .listener
+        .set_profile(Some(ProfileFile::load(&path).unwrap()));
+    harness.add_device(0);
+
+    for scancode in [Scancode::A, Scancode::D, Scancode::S, Scancode::W] {
+        harness.send(IolEvent::KeyDown {
+            scancode,
+            repeat: false,
+        });
+    }
+    let pad = harness.backend.pad(0).unwrap();
+    let report = pad.borrow().current();
+    assert!(!report.button(Button::DPadLeft));
+    assert!(report.button(Button::DPadRight));
+    assert!(report.button(Button::DPadUp));
+    assert!(!report.button(Button::DPadDown));
+
+    harness.send(IolEvent::KeyUp {
+        scancode: Scancode::D,
+    });
+    let report = pad.borrow().current();
+    assert!(report.button(Button::DPadLeft));
+    assert!(!report.button(Button::DPadRight));
+    assert!(harness.backend.state().key_log.is_empty());
+
+    fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn socd_is_set_per_device() {
+    let mut harness = Harness::new();
+    harness.listener.set_socd(Socd {
+        horizontal: SocdPolicy::Neutral,
+        vertical: SocdPolicy::Neutral,
+    });
+    harness.listener.set_device_socd(
+        "Arcade Stick",
+        Socd {
+            horizontal: SocdPolicy::LastInputWins,
+            vertical: SocdPolicy::Off,
+        },
+    );
+    let first = harness.add_device(0);
+    let second = harness.add_named_device(1, "Arcade Stick");
+
+    for id in [first, second] {
+        for button in [Button::DPadLeft, Button::DPadRight] {
+            harness.send(IolEvent::ButtonDown { id, button });
+        }
+    }
+
+    let dpad = |backend: &MockBackend, index| {
+        let report = backend.pad(index).unwrap().borrow().current();
+        (
+            report.button(Button::DPadLeft),
+            report.button(Button::DPadRight),
+        )
+    };
+    assert_eq!(dpad(&harness.backend, 0), (false, false));
+    assert_eq!(dpad(&harness.backend, 1), (false, true));
+
+    // A snapshot goes through the same cleaning.
+    let mut state = ControllerState::default();
+    state.set_button(Button::DPadLeft, true);
+    state.set_button(Button::DPadRight, true);
+    harness.send(IolEvent::ControllerState { id: first, state });
+    assert_eq!(dpad(&harness.backend, 0), (false, false));
+
+    harness.listener.set_device_socd(
+        PAD_NAME,
+        Socd {
+            horizontal: SocdPolicy::Off,
+            vertical: SocdPolicy::Off,
+        },
+    );
+    harness.send(IolEvent::ControllerState { id: first, state });
+    assert_eq!(dpad(&harness.backend, 0), (true, true));
+
+    // Plugged back in, the controller gets another id but keeps its policy.
+    harness.send(IolEvent::PhysicalDeviceRemoved { id: second });
+    let third = harness.add_named_device(2, "Arcade Stick");
+    harness.send(IolEvent::ControllerState { id: third, state });
+    assert_eq!(dpad(&harness.backend, 2), (false, true));
+}
+
+#[test]
+fn profile_is_set_per_device() {
+    let path = write_profile(
+        "deadzone.toml",
+        r#"
+        [analog.left_stick]
+        inner = 0.5
+        "#,
+    );
+    let mut harness = Harness::new();
+    harness
+        .listener
+        .set_device_profile("Arcade Stick", ProfileFile::load(&path).unwrap());
+    let first = harness.add_device(0);
+    let second = harness.add_named_device(1, "Arcade Stick");
+
+    // Unplugged and plugged back in, the controller gets another id but
+    // keeps its profile.
+    harness.send(IolEvent::PhysicalDeviceRemoved { id: second });
+    let third = harness.add_named_device(2, "Arcade Stick");
+
+    for id in [first, third] {
+        harness.send(IolEvent::AxisMotion {
+            id,
+            axis: Axis::LeftX,
+            value: 10000,
+        });
+    }
+
+    let left_x = |index| {
+        harness
+            .backend
+            .pad(index)
+            .unwrap()
+            .borrow()
+            .current()
+            .axis(Axis::LeftX)
+    };
+    assert_eq!(left_x(0), 10000);
+    assert_eq!(left_x(2), 0);
+
+    fs::remove_file(path).unwrap();
+}
+
+#[test]
+fn macros_are_played_from_the_poll_loop() {
+    let path = write_profile(
+        "macro.toml",
+        r#"
+        [macros.paddle1]
+        steps = [
+            { buttons = ["a"], duration = 40 },
+            { buttons = ["b"], duration = 40 },
+        ]
+        "#,
+    );
+    let mut harness = Harness::new();
+    harness
+        .listener
+        .set_profile(Some(ProfileFile::load(&path).unwrap()));
+    let id = harness.add_device(0);
+
+    harness.send(IolEvent::ButtonDown {
+        id,
+        button: Button::Paddle1,
+    });
+    let pad = harness.backend.pad(0).unwrap();
+    let report = pad.borrow().current();
+    assert!(report.button(Button::A));
+    assert!(!report.button(Button::Paddle1));
+
+    thread::sleep(Duration::from_millis(60));
+    harness.listener.poll_once(Some(Duration::ZERO)).unwrap();
+    let report = pad.borrow().current();
+    assert!(!report.button(Button::A));
+    assert!(report.button(Button::B));
+
+    thread::sleep(Duration::from_millis(40));
+    harness.listener.poll_once(Some(Duration::ZERO)).unwrap();
+    assert!(!pad.borrow().current().button(Button::B));
+
+    fs::remove_file(path).unwrap();
+}
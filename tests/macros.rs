@@ -0,0 +1,113 @@
+use std::time::{Duration, Instant};
+
+use iol::{macros::MacroEngine, profile::Profile, ControllerState};
+use sdl2::controller::{Axis, Button};
+
+const MS: Duration = Duration::from_millis(1);
+
+fn held(buttons: &[Button]) -> ControllerState {
+    let mut state = ControllerState::default();
+    for &button in buttons {
+        state.set_button(button, true);
+    }
+    state
+}
+
+#[test]
+fn turbo_toggles_while_held() {
+    let profile = Profile::from_toml("[turbo]\na = 10.0").unwrap();
+    let mut engine = MacroEngine::default();
+    let start = Instant::now();
+    let input = held(&[Button::A, Button::B]);
+
+    let output = engine.apply(&profile, input, start);
+    assert!(output.button(Button::A));
+    assert!(output.button(Button::B));
+    assert_eq!(engine.next_change(&profile, start), Some(start + 50 * MS));
+
+    let output = engine.apply(&profile, input, start + 60 * MS);
+    assert!(!output.button(Button::A));
+    assert!(output.button(Button::B));
+    assert_eq!(
+        engine.next_change(&profile, start + 60 * MS),
+        Some(start + 100 * MS)
+    );
+
+    let output = engine.apply(&profile, input, start + 100 * MS);
+    assert!(output.button(Button::A));
+
+    // Letting go stops the turbo, pressing again starts it over.
+    let output = engine.apply(&profile, held(&[]), start + 120 * MS);
+    assert!(!output.button(Button::A));
+    assert_eq!(engine.next_change(&profile, start + 120 * MS), None);
+    let output = engine.apply(&profile, input, start + 170 * MS);
+    assert!(output.button(Button::A));
+}
+
+#[test]
+fn macro_plays_its_steps_once() {
+    let profile = Profile::from_toml(
+        r#"
+        [macros.paddle1]
+        steps = [
+            { buttons = ["dpdown"], duration = 10 },
+            { buttons = ["dpright", "x"], axes = { righttrigger = 32767 }, duration = 20 },
+        ]
+        "#,
+    )
+    .unwrap();
+    let mut engine = MacroEngine::default();
+    let start = Instant::now();
+    let trigger = held(&[Button::Paddle1]);
+
+    let output = engine.apply(&profile, trigger, start);
+    assert_eq!(output, held(&[Button::DPadDown]));
+    assert_eq!(engine.next_change(&profile, start), Some(start + 10 * MS));
+
+    let output = engine.apply(&profile, trigger, start + 10 * MS);
+    assert!(output.button(Button::DPadRight));
+    assert!(output.button(Button::X));
+    assert!(!output.button(Button::DPadDown));
+    assert_eq!(output.axis(Axis::TriggerRight), 32767);
+    assert_eq!(
+        engine.next_change(&profile, start + 10 * MS),
+        Some(start + 30 * MS)
+    );
+
+    // Releasing the trigger early doesn't cut the macro short.
+    let output = engine.apply(&profile, held(&[]), start + 20 * MS);
+    assert!(output.button(Button::X));
+
+    let output = engine.apply(&profile, held(&[]), start + 30 * MS);
+    assert_eq!(output, ControllerState::default());
+    assert_eq!(engine.next_change(&profile, start + 30 * MS), None);
+
+    // Holding the trigger past the end doesn't replay it.
+    engine.apply(&profile, trigger, start + 40 * MS);
+    let output = engine.apply(&profile, trigger, start + 80 * MS);
+    assert_eq!(output, ControllerState::default());
+}
+
+#[test]
+fn invalid_macros_are_rejected() {
+    assert!(Profile::from_toml("[turbo]\na = 0.0").is_err());
+    assert!(Profile::from_toml("[turbo]\na = 1e-20").is_err());
+    assert!(Profile::from_toml("[turbo]\na = 1e9").is_err());
+    assert!(Profile::from_toml("[turbo]\na = nan").is_err());
+    assert!(Profile::from_toml("[turbo]\na = 0.1").is_ok());
+    assert!(Profile::from_toml("[macros.a]\nsteps = []").is_err());
+    assert!(
+        Profile::from_toml("[macros.a]\nsteps = [{ buttons = [\"b\"], duration = 0 }]").is_err()
+    );
+    assert!(
+        Profile::from_toml("[macros.a]\nsteps = [{ buttons = [\"q\"], duration = 5 }]").is_err()
+    );
+    assert!(Profile::from_toml(
+        "[macros.a]\nsteps = [{ axes = { lefttrigger = -128 }, duration = 5 }]"
+    )
+    .is_err());
+    assert!(
+        Profile::from_toml("[macros.a]\nsteps = [{ axes = { leftx = -128 }, duration = 5 }]")
+            .is_ok()
+    );
+}
@@ -0,0 +1,183 @@
+use std::{net::Ipv4Addr, time::Duration};
+
+use iol::mdns::{
+    self, Message, Question, Record, RecordData, Responder, Service, SERVICE, TYPE_A, TYPE_ANY,
+    TYPE_PTR, TYPE_SRV,
+};
+
+const TIMEOUT: Duration = Duration::from_millis(500);
+
+fn service() -> Service {
+    Service {
+        instance: "living room".to_owned(),
+        host: "htpc.local".to_owned(),
+        address: Some(Ipv4Addr::new(10, 0, 0, 2)),
+        port: 4863,
+        txt: vec!["version=3".to_owned()],
+    }
+}
+
+/// Sends a one-shot query to `responder` and returns what it answered.
+fn ask(responder: &mut Responder, name: &str, qtype: u16) -> Vec<Record> {
+    let address = responder.local_addr().unwrap();
+    let name = name.to_owned();
+    let asking = std::thread::spawn(move || mdns::query(address, &name, qtype, TIMEOUT));
+    for _ in 0..50 {
+        responder.respond().unwrap();
+        if asking.is_finished() {
+            break;
+        }
+        std::thread::sleep(Duration::from_millis(10));
+    }
+    asking.join().unwrap().unwrap()
+}
+
+#[test]
+fn messages_round_trip() {
+    let message = Message {
+        id: 7,
+        response: true,
+        questions: vec![Question {
+            name: SERVICE.to_owned(),
+            qtype: TYPE_PTR,
+            unicast: true,
+        }],
+        records: vec![
+            Record {
+                name: SERVICE.to_owned(),
+                ttl: 120,
+                data: RecordData::Ptr(format!("living room.{}", SERVICE)),
+            },
+            Record {
+                name: "htpc.local".to_owned(),
+                ttl: 120,
+                data: RecordData::A(Ipv4Addr::new(10, 0, 0, 2)),
+            },
+            Record {
+                name: format!("living room.{}", SERVICE),
+                ttl: 120,
+                data: RecordData::Txt(vec!["version=3".to_owned(), "a=b".to_owned()]),
+            },
+        ],
+    };
+    assert_eq!(Message::decode(&message.encode()).unwrap(), message);
+    assert!(Message::decode(&message.encode()[..20]).is_err());
+}
+
+#[test]
+fn compressed_names_are_read() {
+    // A response for "htpc.local" whose answer points back at the question.
+    let mut packet = vec![0, 1, 0x84, 0, 0, 1, 0, 1, 0, 0, 0, 0];
+    packet.extend(b"\x04htpc\x05local\x00\x00\x01\x00\x01");
+    packet.extend([0xc0, 12, 0, 1, 0, 1, 0, 0, 0, 120, 0, 4, 10, 0, 0, 3]);
+    let message = Message::decode(&packet).unwrap();
+    assert_eq!(message.questions[0].name, "htpc.local");
+    assert_eq!(
+        message.records,
+        vec![Record {
+            name: "htpc.local".to_owned(),
+            ttl: 120,
+            data: RecordData::A(Ipv4Addr::new(10, 0, 0, 3)),
+        }]
+    );
+
+    // A pointer to itself must not hang the decoder.
+    let mut packet = vec![0, 1, 0, 0, 0, 1, 0, 0, 0, 0, 0, 0];
+    packet.extend([0xc0, 12, 0, 1, 0, 1]);
+    assert!(Message::decode(&packet).is_err());
+}
+
+#[test]
+fn responder_answers_browsers() {
+    let mut responder = Responder::bind_to("127.0.0.1:0".parse().unwrap(), service()).unwrap();
+    let instance = format!("living room.{}", SERVICE);
+
+    // One-shot queries get answers that are only cached briefly.
+    let records = ask(&mut responder, SERVICE, TYPE_PTR);
+    assert!(records.contains(&Record {
+        name: SERVICE.to_owned(),
+        ttl: 10,
+        data: RecordData::Ptr(instance.clone()),
+    }));
+    assert!(records.contains(&Record {
+        name: instance.clone(),
+        ttl: 10,
+        data: RecordData::Srv {
+            port: 4863,
+            target: "htpc.local".to_owned(),
+        },
+    }));
+    assert!(records.contains(&Record {
+        name: "htpc.local".to_owned(),
+        ttl: 10,
+        data: RecordData::A(Ipv4Addr::new(10, 0, 0, 2)),
+    }));
+
+    let records = ask(&mut responder, &instance, TYPE_SRV);
+    assert!(records
+        .iter()
+        .any(|record| matches!(record.data, RecordData::Srv { port: 4863, .. })));
+
+    let records = ask(&mut responder, "HTPC.local", TYPE_A);
+    assert!(records
+        .iter()
+        .any(|record| record.data == RecordData::A(Ipv4Addr::new(10, 0, 0, 2))));
+    assert!(ask(&mut responder, "other.local", TYPE_ANY).is_empty());
+}
+
+#[test]
+fn host_names_answered_elsewhere_are_left_alone() {
+    let mut responder = Responder::bind_to("127.0.0.1:0".parse().unwrap(), service()).unwrap();
+    let address = responder.local_addr().unwrap();
+
+    let asking = std::thread::spawn(move || {
+        let mut ours = service();
+        ours.defer_host(address, TIMEOUT).map(|()| ours)
+    });
+    while !asking.is_finished() {
+        responder.respond().unwrap();
+        std::thread::sleep(Duration::from_millis(10));
+    }
+    let ours = asking.join().unwrap().unwrap();
+    assert_eq!(ours.address, None);
+
+    // Without an address, only the service itself is answered for.
+    let mut deferring = Responder::bind_to("127.0.0.1:0".parse().unwrap(), ours).unwrap();
+    assert!(ask(&mut deferring, "htpc.local", TYPE_A).is_empty());
+    let records = ask(&mut deferring, SERVICE, TYPE_PTR);
+    assert!(!records.is_empty());
+    assert!(!records
+        .iter()
+        .any(|record| matches!(record.data, RecordData::A(_))));
+
+    // Nobody else answers for a free host name.
+    let mut free = service();
+    free.host = "den.local".to_owned();
+    free.defer_host(address, Duration::from_millis(100))
+        .unwrap();
+    assert!(free.address.is_some());
+}
+
+#[test]
+fn taken_instance_names_are_numbered() {
+    let mut responder = Responder::bind_to("127.0.0.1:0".parse().unwrap(), service()).unwrap();
+    let address = responder.local_addr().unwrap();
+
+    let asking = std::thread::spawn(move || {
+        let mut ours = service();
+        ours.deconflict(address, TIMEOUT).map(|()| ours)
+    });
+    while !asking.is_finished() {
+        responder.respond().unwrap();
+        std::thread::sleep(Duration::from_millis(10));
+    }
+    let ours = asking.join().unwrap().unwrap();
+    assert_eq!(ours.instance, "living room (2)");
+
+    // Nobody answers for a free name.
+    let mut free = service();
+    free.instance = "den".to_owned();
+    free.deconflict(address, Duration::from_millis(100))
+        .unwrap();
+    assert_eq!(free.instance, "den");
+}
@@ -0,0 +1,172 @@
+mod common;
+
+use std::{
+    collections::HashSet,
+    fs,
+    time::{Duration, SystemTime},
+};
+
+use common::temp_path;
+use iol::{
+    profile::{AxisBinding, Binding, Profile, ProfileFile},
+    ControllerState,
+};
+use sdl2::{
+    controller::{Axis, Button},
+    keyboard::Scancode,
+};
+
+#[test]
+fn toml_and_json_profiles_match() {
+    let toml = Profile::from_toml(
+        r#"
+        [buttons]
+        a = "b"
+        guide = "none"
+        leftshoulder = { axis = "lefttrigger" }
+
+        [axes]
+        leftx = { invert = true }
+        righttrigger = { button = "rightshoulder", threshold = 8000 }
+
+        [keys]
+        W = { axis = "lefty", value = 32767 }
+        "#,
+    )
+    .unwrap();
+    let json = Profile::from_json(
+        r#"{
+            "buttons": { "a": "b", "guide": "none", "leftshoulder": { "axis": "lefttrigger" } },
+            "axes": {
+                "leftx": { "invert": true },
+                "righttrigger": { "button": "rightshoulder", "threshold": 8000 }
+            },
+            "keys": { "W": { "axis": "lefty", "value": 32767 } }
+        }"#,
+    )
+    .unwrap();
+
+    assert_eq!(toml, json);
+    assert_eq!(toml.buttons[&Button::A], Binding::Button(Button::B));
+    assert_eq!(toml.buttons[&Button::Guide], Binding::Disabled);
+    assert_eq!(
+        toml.axes[&Axis::TriggerRight],
+        AxisBinding::Button {
+            button: Button::RightShoulder,
+            threshold: 8000
+        }
+    );
+}
+
+#[test]
+fn invalid_profiles_are_rejected() {
+    assert!(Profile::from_toml("[buttons]\nnot_a_button = \"a\"").is_err());
+    assert!(Profile::from_toml("[buttons]\na = { button = \"b\", invert = true }").is_err());
+    assert!(Profile::from_toml("[sticks]\nleftx = \"rightx\"").is_err());
+}
+
+#[test]
+fn apply_combines_every_source() {
+    let profile = Profile::from_toml(
+        r#"
+        [buttons]
+        a = "b"
+        b = "a"
+        dpleft = { axis = "leftx", value = -32768 }
+
+        [axes]
+        lefty = { invert = true }
+        lefttrigger = "leftshoulder"
+
+        [keys]
+        Space = "y"
+        "#,
+    )
+    .unwrap();
+
+    let mut input = ControllerState::default();
+    input.set_button(Button::A, true);
+    input.set_button(Button::DPadLeft, true);
+    input.set_axis(Axis::LeftX, 1000);
+    input.set_axis(Axis::LeftY, 2000);
+    input.set_axis(Axis::TriggerLeft, 20000);
+    let keys = HashSet::from([Scancode::Space]);
+
+    let output = profile.apply(&input, &keys);
+    assert!(output.button(Button::B));
+    assert!(!output.button(Button::A));
+    assert!(output.button(Button::Y));
+    assert!(output.button(Button::LeftShoulder));
+    // The D-pad pushes further than the stick, so it wins.
+    assert_eq!(output.axis(Axis::LeftX), -32768);
+    assert_eq!(output.axis(Axis::LeftY), -2000);
+    assert_eq!(output.axis(Axis::TriggerLeft), 0);
+}
+
+#[test]
+fn triggers_never_go_negative() {
+    let profile = Profile::from_toml(
+        r#"
+        [axes]
+        leftx = "lefttrigger"
+
+        [keys]
+        Space = { axis = "righttrigger", value = -5000 }
+        "#,
+    )
+    .unwrap();
+
+    let mut input = ControllerState::default();
+    input.set_axis(Axis::LeftX, -20000);
+    input.set_axis(Axis::TriggerLeft, 10000);
+    let keys = HashSet::from([Scancode::Space]);
+
+    let output = profile.apply(&input, &keys);
+    // The stick pushed left leaves the trigger alone rather than wrapping
+    // around to a full pull.
+    assert_eq!(output.axis(Axis::TriggerLeft), 10000);
+    assert_eq!(output.axis(Axis::TriggerRight), 0);
+
+    input.set_axis(Axis::LeftX, 20000);
+    let output = profile.apply(&input, &HashSet::new());
+    assert_eq!(output.axis(Axis::TriggerLeft), 20000);
+}
+
+#[test]
+fn profile_file_reloads_on_change() {
+    let path = temp_path("reload.toml");
+    fs::write(&path, "[buttons]\na = \"b\"").unwrap();
+    let mut file = ProfileFile::load(&path).unwrap();
+    let profile = file.profile();
+    assert!(!file.reload_if_changed().unwrap());
+
+    fs::write(&path, "[buttons]\na = \"x\"").unwrap();
+    let later = SystemTime::now() + Duration::from_secs(5);
+    fs::File::options()
+        .write(true)
+        .open(&path)
+        .unwrap()
+        .set_modified(later)
+        .unwrap();
+    assert!(file.reload_if_changed().unwrap());
+    assert_eq!(
+        profile.borrow().buttons[&Button::A],
+        Binding::Button(Button::X)
+    );
+
+    // A broken edit keeps the last good profile.
+    fs::write(&path, "[buttons]\na = ").unwrap();
+    fs::File::options()
+        .write(true)
+        .open(&path)
+        .unwrap()
+        .set_modified(later + Duration::from_secs(5))
+        .unwrap();
+    assert!(file.reload_if_changed().is_err());
+    assert_eq!(
+        profile.borrow().buttons[&Button::A],
+        Binding::Button(Button::X)
+    );
+
+    fs::remove_file(path).unwrap();
+}
@@ -0,0 +1,148 @@
+mod common;
+
+use std::{
+    fs,
+    path::PathBuf,
+    thread,
+    time::{Duration, Instant},
+};
+
+use common::{temp_path, BackgroundListener, Outcome};
+use iol::{
+    client::Client,
+    recording::{self, Record, Recorder, Recording},
+    Capabilities, IolEvent, PadTarget,
+};
+use sdl2::{
+    controller::{Axis, Button},
+    keyboard::Scancode,
+};
+
+fn record_session(path: &PathBuf) -> Outcome {
+    let listener = BackgroundListener::spawn("127.0.0.1:0").unwrap();
+    let mut client = Client::bind("127.0.0.1:0".parse().unwrap()).unwrap();
+    client.set_recorder(Some(Recorder::create(path).unwrap()));
+
+    client.connect(listener.address, Capabilities::ALL).unwrap();
+    let id = client
+        .register_device(7, Some(PadTarget::DualShock4), "PS4 Controller")
+        .unwrap();
+    client
+        .send(IolEvent::ButtonDown {
+            id,
+            button: Button::A,
+        })
+        .unwrap();
+    thread::sleep(Duration::from_millis(100));
+    client
+        .send(IolEvent::AxisMotion {
+            id,
+            axis: Axis::LeftX,
+            value: 1000,
+        })
+        .unwrap();
+    client
+        .send(IolEvent::KeyDown {
+            scancode: Scancode::Q,
+            repeat: false,
+        })
+        .unwrap();
+    client.disconnect();
+
+    listener.finish()
+}
+
+#[test]
+fn records_what_is_sent() {
+    for name in ["session.jsonl", "session.bin"] {
+        let path = temp_path(name);
+        record_session(&path);
+
+        let records: Vec<Record> = Recording::open(&path)
+            .unwrap()
+            .collect::<anyhow::Result<_>>()
+            .unwrap();
+        fs::remove_file(&path).unwrap();
+
+        let events: Vec<String> = records
+            .iter()
+            .map(|record| format!("{:?}", record.event))
+            .collect();
+        assert_eq!(records.len(), 7, "{:?}", events);
+        assert!(matches!(records[0].event, IolEvent::Hello { .. }));
+        assert!(matches!(
+            records[1].event,
+            IolEvent::PhysicalDeviceAdded {
+                which: 7,
+                target: Some(PadTarget::DualShock4),
+                ..
+            }
+        ));
+        assert!(matches!(
+            records[2].event,
+            IolEvent::VirtualDeviceAdded { which: 7, .. }
+        ));
+        assert!(matches!(records[3].event, IolEvent::ButtonDown { .. }));
+        assert!(matches!(records[6].event, IolEvent::Disconnect));
+        assert!(records[4].time - records[3].time >= 100_000);
+        assert!(records.windows(2).all(|pair| pair[0].time <= pair[1].time));
+    }
+}
+
+#[test]
+fn replay_reproduces_the_session() {
+    let path = temp_path("replay.jsonl");
+    let recorded = record_session(&path);
+
+    let listener = BackgroundListener::spawn("127.0.0.1:0").unwrap();
+    let mut client = Client::bind("127.0.0.1:0".parse().unwrap()).unwrap();
+    let start = Instant::now();
+    recording::replay(
+        &mut client,
+        listener.address,
+        Recording::open(&path).unwrap(),
+        1.0,
+    )
+    .unwrap();
+    assert!(start.elapsed() >= Duration::from_millis(100));
+    let replayed = listener.finish();
+    fs::remove_file(&path).unwrap();
+
+    assert_eq!(replayed.targets, vec![PadTarget::DualShock4]);
+    assert_eq!(replayed.targets, recorded.targets);
+    assert_eq!(replayed.reports, recorded.reports);
+    assert_eq!(replayed.key_log, recorded.key_log);
+    let reports = &replayed.reports[0];
+    assert!(reports
+        .iter()
+        .any(|report| report.button(Button::A) && report.axis(Axis::LeftX) == 1000));
+}
+
+#[test]
+fn replay_speed_scales_the_timing() {
+    let path = temp_path("fast.jsonl");
+    record_session(&path);
+
+    let listener = BackgroundListener::spawn("127.0.0.1:0").unwrap();
+    let mut client = Client::bind("127.0.0.1:0".parse().unwrap()).unwrap();
+    let start = Instant::now();
+    recording::replay(
+        &mut client,
+        listener.address,
+        Recording::open(&path).unwrap(),
+        4.0,
+    )
+    .unwrap();
+    assert!(start.elapsed() < Duration::from_millis(90));
+    for speed in [0.0, 1e-300, f64::INFINITY, f64::NAN] {
+        assert!(recording::replay(
+            &mut client,
+            listener.address,
+            Recording::open(&path).unwrap(),
+            speed
+        )
+        .is_err());
+    }
+    listener.finish();
+    fs::remove_file(&path).unwrap();
+}
@@ -0,0 +1,154 @@
+use iol::{
+    socd::{Socd, SocdPolicy, SocdResolver},
+    ControllerState,
+};
+use sdl2::controller::Button;
+
+const LEFT: Button = Button::DPadLeft;
+const RIGHT: Button = Button::DPadRight;
+const UP: Button = Button::DPadUp;
+const DOWN: Button = Button::DPadDown;
+
+/// Presses or releases one direction per step and checks both directions of
+/// its axis afterwards.
+fn check(socd: Socd, steps: &[(Button, bool, [bool; 2])]) {
+    let mut resolver = SocdResolver::default();
+    let mut held = ControllerState::default();
+    for (step, &(button, pressed, expected)) in steps.iter().enumerate() {
+        held.set_button(button, pressed);
+        let mut state = held;
+        resolver.resolve(socd, &mut state);
+
+        let pair = if button == LEFT || button == RIGHT {
+            [LEFT, RIGHT]
+        } else {
+            [UP, DOWN]
+        };
+        assert_eq!(
+            pair.map(|button| state.button(button)),
+            expected,
+            "step {} with {:?}",
+            step,
+            socd
+        );
+    }
+}
+
+fn horizontal(policy: SocdPolicy) -> Socd {
+    Socd {
+        horizontal: policy,
+        vertical: SocdPolicy::Off,
+    }
+}
+
+fn vertical(policy: SocdPolicy) -> Socd {
+    Socd {
+        horizontal: SocdPolicy::Off,
+        vertical: policy,
+    }
+}
+
+#[test]
+fn off_passes_both_directions() {
+    check(
+        horizontal(SocdPolicy::Off),
+        &[
+            (LEFT, true, [true, false]),
+            (RIGHT, true, [true, true]),
+            (LEFT, false, [false, true]),
+            (RIGHT, false, [false, false]),
+        ],
+    );
+}
+
+#[test]
+fn neutral_cancels_opposing_directions() {
+    check(
+        horizontal(SocdPolicy::Neutral),
+        &[
+            (LEFT, true, [true, false]),
+            (RIGHT, true, [false, false]),
+            (LEFT, false, [false, true]),
+            (LEFT, true, [false, false]),
+            (RIGHT, false, [true, false]),
+            (LEFT, false, [false, false]),
+        ],
+    );
+    check(
+        vertical(SocdPolicy::Neutral),
+        &[
+            (DOWN, true, [false, true]),
+            (UP, true, [false, false]),
+            (DOWN, false, [true, false]),
+        ],
+    );
+}
+
+#[test]
+fn last_input_wins() {
+    check(
+        horizontal(SocdPolicy::LastInputWins),
+        &[
+            (LEFT, true, [true, false]),
+            (RIGHT, true, [false, true]),
+            (RIGHT, false, [true, false]),
+            (RIGHT, true, [false, true]),
+            (LEFT, false, [false, true]),
+            (LEFT, true, [true, false]),
+            (LEFT, false, [false, true]),
+            (RIGHT, false, [false, false]),
+        ],
+    );
+    check(
+        vertical(SocdPolicy::LastInputWins),
+        &[
+            (UP, true, [true, false]),
+            (DOWN, true, [false, true]),
+            (DOWN, false, [true, false]),
+        ],
+    );
+}
+
+#[test]
+fn up_priority() {
+    check(
+        vertical(SocdPolicy::UpPriority),
+        &[
+            (DOWN, true, [false, true]),
+            (UP, true, [true, false]),
+            (UP, false, [false, true]),
+            (UP, true, [true, false]),
+            (DOWN, false, [true, false]),
+            (DOWN, true, [true, false]),
+            (UP, false, [false, true]),
+        ],
+    );
+    // Left and Right have no up, so they cancel out.
+    check(
+        horizontal(SocdPolicy::UpPriority),
+        &[
+            (LEFT, true, [true, false]),
+            (RIGHT, true, [false, false]),
+            (LEFT, false, [false, true]),
+        ],
+    );
+}
+
+#[test]
+fn axes_are_resolved_independently() {
+    let socd = Socd {
+        horizontal: SocdPolicy::Neutral,
+        vertical: SocdPolicy::UpPriority,
+    };
+    let mut resolver = SocdResolver::default();
+    let mut state = ControllerState::default();
+    for button in [LEFT, RIGHT, UP, DOWN] {
+        state.set_button(button, true);
+    }
+    resolver.resolve(socd, &mut state);
+
+    assert!(!state.button(LEFT));
+    assert!(!state.button(RIGHT));
+    assert!(state.button(UP));
+    assert!(!state.button(DOWN));
+}